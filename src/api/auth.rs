@@ -0,0 +1,352 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use poem::{http::header, Request, Response};
+use poem_openapi::{auth::Bearer, error::AuthorizationError, Enum, Object, SecurityScheme};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::util::error::{Error, Result};
+use crate::util::file_system::write_atomic;
+
+/// Prefix of every token, makes leaked tokens easy to spot by secret scanners.
+const TOKEN_PREFIX: &str = "rwt_";
+
+/// What a token may do, every scope includes the ones before it.
+#[derive(
+    Debug, Enum, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash,
+)]
+#[oai(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Read repositories, tags, jobs and their logs.
+    Read,
+    /// Start, cancel and sync builds.
+    Build,
+    /// Add and configure repositories and manage tokens.
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Build => "build",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+/// An API token as reported by the API, the secret itself is only returned on creation.
+#[derive(Debug, Object, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct ApiToken {
+    pub id: String,
+    /// Description of what the token is used for.
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Repositories the token is restricted to, all if empty.
+    #[serde(default)]
+    pub repositories: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ApiToken {
+    /// Fails unless the token has `scope` and, if `repository` is set, may access it.
+    pub fn require(&self, scope: Scope, repository: Option<&str>) -> Result<()> {
+        if !self.scopes.iter().any(|granted| *granted >= scope) {
+            return Err(Error::Forbidden(format!(
+                "Token {} lacks the {} scope",
+                self.name,
+                scope.as_str()
+            )));
+        }
+
+        match repository {
+            Some(repository) if !self.may_access(repository) => Err(Error::Forbidden(format!(
+                "Token {} may not access repository {}",
+                self.name, repository
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails unless the token has the admin scope for every repository, as needed to manage tokens.
+    pub fn require_unrestricted_admin(&self) -> Result<()> {
+        self.require(Scope::Admin, None)?;
+        if !self.repositories.is_empty() {
+            return Err(Error::Forbidden(format!(
+                "Token {} is restricted to some repositories",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn may_access(&self, repository: &str) -> bool {
+        self.repositories.is_empty() || self.repositories.iter().any(|name| name == repository)
+    }
+}
+
+/// Settings of a token to create.
+#[derive(Debug, Object, Clone)]
+pub struct NewToken {
+    /// Description of what the token is used for, e.g. "CI of project x".
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Repositories to restrict the token to, all if empty.
+    #[oai(default)]
+    pub repositories: Vec<String>,
+}
+
+/// A newly created token.
+#[derive(Debug, Object, Clone)]
+pub struct CreatedToken {
+    /// The secret to send as `Authorization: Bearer <secret>`, it can't be retrieved again.
+    pub secret: String,
+    pub token: ApiToken,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct StoredToken {
+    #[serde(flatten)]
+    token: ApiToken,
+    /// SHA-256 of the secret, the secret itself is never stored.
+    hash: String,
+}
+
+/// API tokens, persisted as JSON with only the hashes of their secrets.
+///
+/// The handle is cheap to clone.
+#[derive(Clone)]
+pub struct TokenStore {
+    path: String,
+    tokens: Arc<Mutex<BTreeMap<String, StoredToken>>>,
+}
+
+impl TokenStore {
+    /// Loads the tokens from `path`, starting without tokens if the file does not exist yet.
+    pub fn load(path: &str) -> Result<Self> {
+        let tokens = match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| Error::Io(format!("Failed to parse tokens {}: {}", path, e)))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(Error::Io(format!("Failed to read tokens {}: {}", path, e))),
+        };
+
+        Ok(TokenStore {
+            path: path.to_string(),
+            tokens: Arc::new(Mutex::new(tokens)),
+        })
+    }
+
+    fn lock_tokens(&self) -> MutexGuard<'_, BTreeMap<String, StoredToken>> {
+        self.tokens.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn list(&self) -> Vec<ApiToken> {
+        self.lock_tokens()
+            .values()
+            .map(|stored| stored.token.clone())
+            .collect()
+    }
+
+    /// Creates a token with a random secret and persists its hash.
+    pub fn create(&self, new: NewToken) -> Result<CreatedToken> {
+        if new.name.trim().is_empty() {
+            return Err(Error::Invalid("Token name must not be empty".to_string()));
+        }
+        if new.scopes.is_empty() {
+            return Err(Error::Invalid("Token needs at least one scope".to_string()));
+        }
+
+        // two v4 UUIDs carry 244 random bits from the OS generator
+        let secret = format!(
+            "{}{}{}",
+            TOKEN_PREFIX,
+            Uuid::new_v4().simple(),
+            Uuid::new_v4().simple()
+        );
+        let token = ApiToken {
+            id: Uuid::new_v4().to_string(),
+            name: new.name,
+            scopes: new.scopes,
+            repositories: new.repositories,
+            created_at: Utc::now(),
+        };
+
+        let mut tokens = self.lock_tokens();
+        tokens.insert(
+            token.id.clone(),
+            StoredToken {
+                token: token.clone(),
+                hash: hash_secret(&secret),
+            },
+        );
+        if let Err(err) = self.save(&tokens) {
+            tokens.remove(&token.id);
+            return Err(err);
+        }
+
+        Ok(CreatedToken { secret, token })
+    }
+
+    /// Creates an admin token if there is none at all, so the first tokens can be set up.
+    pub fn bootstrap(&self) -> Result<Option<CreatedToken>> {
+        if !self.lock_tokens().is_empty() {
+            return Ok(None);
+        }
+
+        self.create(NewToken {
+            name: "initial admin".to_string(),
+            scopes: vec![Scope::Admin],
+            repositories: Vec::new(),
+        })
+        .map(Some)
+    }
+
+    /// Revokes a token, requests using it are rejected immediately.
+    pub fn revoke(&self, id: &str) -> Result<ApiToken> {
+        let mut tokens = self.lock_tokens();
+        let stored = tokens
+            .remove(id)
+            .ok_or_else(|| Error::NotFound(format!("Token not found ({})", id)))?;
+
+        if let Err(err) = self.save(&tokens) {
+            tokens.insert(id.to_string(), stored);
+            return Err(err);
+        }
+        Ok(stored.token)
+    }
+
+    /// The token a request outside the OpenAPI routes, like git's smart HTTP, was sent with,
+    /// either as `Authorization: Bearer <token>` or as the password of Basic auth.
+    pub fn authenticate_request(&self, req: &Request) -> Result<ApiToken> {
+        let secret = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(request_secret)
+            .ok_or_else(|| {
+                Error::Unauthorized(
+                    "Missing API token, send it as the password of Basic auth or as a Bearer token"
+                        .to_string(),
+                )
+            })?;
+
+        self.authenticate(&secret)
+            .ok_or_else(|| Error::Unauthorized("Invalid API token".to_string()))
+    }
+
+    /// The token `secret` belongs to.
+    pub fn authenticate(&self, secret: &str) -> Option<ApiToken> {
+        let hash = hash_secret(secret);
+        self.lock_tokens()
+            .values()
+            .find(|stored| stored.hash == hash)
+            .map(|stored| stored.token.clone())
+    }
+
+    /// Persists the token hashes, readable by the service user only.
+    fn save(&self, tokens: &BTreeMap<String, StoredToken>) -> Result<()> {
+        let content = serde_json::to_string_pretty(tokens)
+            .map_err(|e| Error::Io(format!("Failed to serialize tokens: {}", e)))?;
+
+        write_atomic(&self.path, content.as_bytes(), true)
+            .map_err(|e| Error::Io(format!("Failed to write tokens {}: {}", self.path, e)))
+    }
+}
+
+/// Secret of an `Authorization` header value, the user name of Basic auth is ignored.
+fn request_secret(value: &str) -> Option<String> {
+    let (scheme, credentials) = value.trim().split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") {
+        return Some(credentials.trim().to_string());
+    }
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = STANDARD.decode(credentials.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (_, password) = decoded.split_once(':')?;
+    Some(password.to_string())
+}
+
+/// Tokens are long random strings, so a plain SHA-256 is enough to make the stored hashes useless.
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+/// Bearer token authentication, create tokens with `POST /tokens`.
+#[derive(SecurityScheme)]
+#[oai(ty = "bearer", checker = "check_token")]
+pub struct TokenAuth(pub ApiToken);
+
+impl TokenAuth {
+    /// Fails unless the token has `scope` and, if `repository` is set, may access it.
+    pub fn require(&self, scope: Scope, repository: Option<&str>) -> Result<()> {
+        self.0.require(scope, repository)
+    }
+}
+
+/// Gives requests without a token the same JSON error body as invalid tokens,
+/// poem-openapi rejects them with a plain text error before the checker runs.
+pub async fn missing_token(resp: poem::Result<Response>) -> poem::Result<Response> {
+    match resp {
+        Err(err) if err.is::<AuthorizationError>() => Err(Error::Unauthorized(
+            "Missing API token, send it as `Authorization: Bearer <token>`".to_string(),
+        )
+        .into()),
+        resp => resp,
+    }
+}
+
+async fn check_token(req: &Request, bearer: Bearer) -> poem::Result<ApiToken> {
+    let store = req
+        .data::<TokenStore>()
+        .ok_or_else(|| Error::Config("Token store is not configured".to_string()))?;
+
+    store
+        .authenticate(&bearer.token)
+        .ok_or_else(|| Error::Unauthorized("Invalid API token".to_string()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_is_read_from_bearer_and_basic_auth() {
+        assert_eq!(request_secret("Bearer rwt_1").as_deref(), Some("rwt_1"));
+        let basic = format!("Basic {}", STANDARD.encode("git:rwt_2"));
+        assert_eq!(request_secret(&basic).as_deref(), Some("rwt_2"));
+        assert_eq!(request_secret("Digest rwt_3"), None);
+        assert_eq!(request_secret("Basic not-base64"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tokens_are_only_readable_by_the_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir
+            .path()
+            .join("tokens.json")
+            .to_string_lossy()
+            .into_owned();
+        let store = TokenStore::load(&path).unwrap();
+        let created = store.bootstrap().unwrap().unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let reloaded = TokenStore::load(&path).unwrap();
+        assert_eq!(reloaded.authenticate(&created.secret), Some(created.token));
+    }
+}
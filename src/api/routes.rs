@@ -1,22 +1,200 @@
-use std::process::Command;
+use std::{collections::BTreeMap, time::Duration};
 
+use futures_util::stream::BoxStream;
+use poem::{web::sse::Event, Body};
 use poem_openapi::{
     param,
-    payload::Json,
+    payload::{Binary, EventStream, Json, PlainText},
     types::{ParseFromJSON, ToJSON},
-    ApiResponse, OpenApi,
+    ApiResponse, Enum, Object, OpenApi,
 };
-use tracing::{debug, error, info};
+use tracing::{debug, info};
 
-use crate::build::docker::DockerManager;
-use crate::build::{make, script};
-use crate::git::manager::RepositoryManager as Repo;
+use crate::api::auth::{ApiToken, CreatedToken, NewToken, Scope, TokenAuth, TokenStore};
+use crate::build::digests::Digests;
+use crate::build::jobs::{Job, JobQueue};
+use crate::build::log::{self, LogEvent, LogFinished};
+use crate::build::releases::{Release, ReleaseStore};
+use crate::build::runner::{BuildOptions, BUILD_METHODS};
+use crate::build::triggers::BuildTrigger;
+use crate::git::credentials::{Credentials, CredentialsInfo};
+use crate::git::manager::{
+    CloneOptions, RepositoryInfo, RepositoryKind, RepositoryManager as Repo, SyncReport, TagInfo,
+};
+use crate::git::registry::RepositoryEntry;
+use crate::git::tags::{self, TagSort};
+use crate::notify::{self, NotificationRoute};
+use crate::util::config::Config;
+use crate::util::depends::{Dependencies, MethodStatus, ToolReport};
+use crate::util::error::{Error, ErrorResponse};
 use crate::util::file_system::FileSystem;
+use crate::util::signing::{PublicKey, SigningKeys};
 use crate::util::workflows::{workflows_exist, WorkflowScripts};
 
+/// Repositories listed when no limit is requested.
+const DEFAULT_PAGE_SIZE: u32 = 50;
+/// Most repositories listed at once.
+const MAX_PAGE_SIZE: u32 = 500;
+
 pub struct Api {
     repo_manager: Repo,
     file_system: FileSystem,
+    jobs: JobQueue,
+    dependencies: Dependencies,
+    tokens: TokenStore,
+    releases: ReleaseStore,
+    signing_keys: SigningKeys,
+}
+
+/// Overall state of the service.
+#[derive(Debug, Enum, Clone, Copy, Eq, PartialEq)]
+#[oai(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// Everything the service needs is available.
+    Ok,
+    /// The service runs, but git or the tools of some build methods are missing.
+    Degraded,
+}
+
+/// Health of the service.
+#[derive(Debug, Object, Clone)]
+pub struct Health {
+    pub status: HealthStatus,
+    /// Version of the service.
+    pub version: String,
+    pub build_methods: Vec<MethodStatus>,
+}
+
+#[derive(ApiResponse)]
+pub enum HealthResponse {
+    /// Successfully -> OK, The Service Is Running
+    #[oai(status = 200)]
+    Ok(Json<Health>),
+}
+
+#[derive(ApiResponse)]
+pub enum ToolsResponse {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(Json<ToolReport>),
+}
+
+#[derive(ApiResponse)]
+pub enum TokensResponse {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(Json<Vec<ApiToken>>),
+}
+
+#[derive(ApiResponse)]
+pub enum CreateToken {
+    /// Successfully -> Created
+    #[oai(status = 201)]
+    Ok(Json<CreatedToken>),
+}
+
+#[derive(ApiResponse)]
+pub enum RevokeToken {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(Json<ApiToken>),
+}
+
+#[derive(ApiResponse)]
+pub enum CredentialsListResponse {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(Json<Vec<CredentialsInfo>>),
+}
+
+#[derive(ApiResponse)]
+pub enum CredentialsResponse {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(Json<CredentialsInfo>),
+}
+
+/// Credentials a repository is synced with.
+#[derive(Debug, Object, Clone)]
+pub struct RepositoryCredentials {
+    /// Name of the stored credentials, `null` to sync anonymously.
+    pub credentials: Option<String>,
+}
+
+/// A repository to add.
+#[derive(Debug, Object, Clone)]
+pub struct NewRepository {
+    /// Name the repository is managed under.
+    pub name: String,
+    /// URL to clone the repository from, an `https://`, `ssh://` or `user@host:path` remote.
+    pub url: String,
+    /// Branch to check out and keep in sync, the remote's default branch if not set.
+    pub branch: Option<String>,
+    /// Name of the stored credentials to clone and sync with, see `PUT /credentials/:name`.
+    pub credentials: Option<String>,
+    /// Seconds between background syncs, the configured interval if not set.
+    pub sync_interval_secs: Option<u64>,
+    /// Default build method of the repository.
+    pub build_method: Option<String>,
+}
+
+/// A build to start.
+#[derive(Debug, Object, Clone)]
+pub struct NewBuild {
+    /// Build method, the repository's default build method if not set.
+    pub method: Option<String>,
+    /// Tag, branch or commit SHA to build, the checked out commit if not set.
+    #[oai(rename = "ref")]
+    pub git_ref: Option<String>,
+    /// Variables added to the environment of the build. Docker builds get them as build args,
+    /// cargo builds in the container's environment. Neither may set variables configuring
+    /// the container engine, e.g. `PATH` or `DOCKER_*`.
+    #[oai(default)]
+    pub env: BTreeMap<String, String>,
+    #[oai(default)]
+    pub options: BuildOptions,
+}
+
+#[derive(ApiResponse)]
+pub enum RepositoryResponse {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(Json<RepositoryEntry>),
+
+    /// Successfully -> Created
+    #[oai(status = 201)]
+    Created(Json<RepositoryEntry>),
+}
+
+/// A page of the managed repositories.
+#[derive(Debug, Object, Clone)]
+pub struct RepositoryList {
+    /// Number of repositories matching the filters, across all pages.
+    pub total: u32,
+    pub offset: u32,
+    pub limit: u32,
+    pub repositories: Vec<RepositoryInfo>,
+}
+
+#[derive(ApiResponse)]
+pub enum ListRepositories {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(Json<RepositoryList>),
+}
+
+#[derive(ApiResponse)]
+pub enum GetRepository {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(Json<Box<RepositoryInfo>>),
+}
+
+#[derive(ApiResponse)]
+pub enum DeleteRepository {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(Json<Box<RepositoryInfo>>),
 }
 
 #[derive(ApiResponse)]
@@ -24,10 +202,6 @@ pub enum AddRepository {
     /// Successfully -> Created
     #[oai(status = 201)]
     Ok,
-
-    /// Server Errors -> Internal Server Error
-    #[oai(status = 500)]
-    ServerError,
 }
 
 #[derive(ApiResponse)]
@@ -35,10 +209,56 @@ pub enum GetTags<T: ParseFromJSON + ToJSON> {
     /// Successfully -> OK
     #[oai(status = 200)]
     Ok(Json<T>),
+}
+
+#[derive(ApiResponse)]
+pub enum ListReleases {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(Json<Vec<Release>>),
+}
+
+#[derive(ApiResponse)]
+pub enum GetRelease {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(Json<Box<Release>>),
+}
+
+#[derive(ApiResponse)]
+pub enum SigningPublicKey {
+    /// Successfully -> OK, A `minisign.pub` File
+    #[oai(status = 200)]
+    Ok(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+pub enum ListSigningKeys {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(Json<Vec<PublicKey>>),
+}
+
+#[derive(ApiResponse)]
+pub enum DownloadArtifact {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(
+        Binary<Body>,
+        #[oai(header = "Content-Type")] String,
+        #[oai(header = "Content-Length")] u64,
+        #[oai(header = "Content-Disposition")] String,
+        /// SHA-256 of the file, unset for artifacts published without digests.
+        #[oai(header = "ETag")]
+        Option<String>,
+        /// Base64 encoded digests of the file, as `sha-256=<digest>`.
+        #[oai(header = "Digest")]
+        Option<String>,
+    ),
 
-    /// Client Error -> Not Found
-    #[oai(status = 404)]
-    NotFound,
+    /// Redirection -> Not Modified, The `If-None-Match` Header Matches The Artifact's ETag
+    #[oai(status = 304)]
+    NotModified(#[oai(header = "ETag")] String),
 }
 
 #[derive(ApiResponse)]
@@ -46,21 +266,63 @@ pub enum CreateRepository {
     /// Successfully -> Created
     #[oai(status = 201)]
     Ok(Json<String>),
-
-    /// Server Errors -> Failed Response Body For Details
-    #[oai(status = 500)]
-    ServerError(Json<String>),
 }
 
 #[derive(ApiResponse)]
 pub enum BuildRepo {
-    /// Successfully -> Created
-    #[oai(status = 201)]
-    Ok(Json<String>),
+    /// Successfully -> Accepted, The Build Runs In The Background
+    #[oai(status = 202)]
+    Accepted(Json<Box<Job>>),
+}
+
+#[derive(ApiResponse)]
+pub enum GetJob {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(Json<Box<Job>>),
+}
+
+#[derive(ApiResponse)]
+pub enum GetJobLog {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(
+        PlainText<String>,
+        /// Current size of the whole log in bytes.
+        #[oai(header = "X-Log-Size")]
+        u64,
+        /// Offset to continue reading from.
+        #[oai(header = "X-Next-Offset")]
+        u64,
+    ),
+
+    /// Successfully -> Partial Content For A `Range` Request
+    #[oai(status = 206)]
+    PartialContent(
+        PlainText<String>,
+        #[oai(header = "Content-Range")] String,
+        /// Offset to continue reading from.
+        #[oai(header = "X-Next-Offset")]
+        u64,
+    ),
 
-    /// Server Errors -> Failed Build Response Body For Details
-    #[oai(status = 500)]
-    ServerError(Json<String>),
+    /// Client Error -> Requested Range Not Satisfiable
+    #[oai(status = 416)]
+    RangeNotSatisfiable(Json<String>, #[oai(header = "Content-Range")] String),
+}
+
+#[derive(ApiResponse)]
+pub enum StreamJobLog {
+    /// Successfully -> OK, Server-Sent Events
+    #[oai(status = 200)]
+    Ok(EventStream<BoxStream<'static, LogEvent>>),
+}
+
+#[derive(ApiResponse)]
+pub enum CancelJob {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(Json<Box<Job>>),
 }
 
 #[derive(ApiResponse)]
@@ -68,21 +330,42 @@ pub enum BuildScriptsResponse {
     /// Successfully -> Created
     #[oai(status = 201)]
     Ok(Json<WorkflowScripts>),
-
-    /// Server Errors -> Failed Build Response Body For Details
-    #[oai(status = 500)]
-    ServerError(Json<String>),
 }
 
 #[derive(ApiResponse)]
-pub enum SyncRepoResponse {
+pub enum SyncRepoResponse<T: ParseFromJSON + ToJSON> {
     /// Successfully -> Created
     #[oai(status = 201)]
+    Ok(Json<T>),
+}
+
+/// Webhook settings of a repository.
+#[derive(Debug, Object, Clone)]
+pub struct WebhookSettings {
+    /// Secret configured on the forge, `null` disables webhooks for the repository.
+    #[oai(write_only)]
+    pub secret: Option<String>,
+}
+
+#[derive(ApiResponse)]
+pub enum WebhookResponse {
+    /// Successfully -> OK
+    #[oai(status = 200)]
     Ok(Json<String>),
+}
+
+#[derive(ApiResponse)]
+pub enum TriggersResponse {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(Json<Vec<BuildTrigger>>),
+}
 
-    /// Server Errors -> Failed Build Response Body For Details
-    #[oai(status = 500)]
-    ServerError(Json<String>),
+#[derive(ApiResponse)]
+pub enum NotificationsResponse {
+    /// Successfully -> OK
+    #[oai(status = 200)]
+    Ok(Json<Vec<NotificationRoute>>),
 }
 
 #[OpenApi]
@@ -91,309 +374,1320 @@ impl Api {
     ///
     /// # Parameters
     ///
-    /// * `repos_base_path`: Base path for repositories.
+    /// * `config`: Service configuration.
+    /// * `dependencies`: Host tools probed at startup.
     ///
     /// # Returns
     ///
-    /// A new instance of `Api`.
-    pub fn new(repos_base_path: &str) -> Self {
+    /// A new instance of `Api`, with the repository manager restored from the
+    /// persisted registry, the releases and the API tokens, or an error if they could not be loaded.
+    /// Nothing runs in the background until [`Api::start`] is called.
+    pub fn new(config: &Config, dependencies: Dependencies) -> Result<Self, Error> {
+        let file_system = FileSystem::new(&config.data_dir);
+        let releases = ReleaseStore::load(&file_system)?;
+        let jobs = JobQueue::new(config, dependencies.clone(), releases.clone());
         // Initialize RepoManager
-        let repo_manager = Repo::new(repos_base_path);
-        let file_system = FileSystem::new(repos_base_path);
+        let repo_manager = Repo::new(config, jobs.clone())?;
+
+        let tokens = TokenStore::load(&file_system.tokens_path())?;
 
-        Api {
+        Ok(Api {
             repo_manager,
+            signing_keys: SigningKeys::new(&file_system),
             file_system,
+            jobs,
+            dependencies,
+            tokens,
+            releases,
+        })
+    }
+
+    /// Starts the build workers, the background syncs of the registered repositories
+    /// and the delivery of notifications.
+    pub fn start(&self, config: &Config) {
+        self.jobs.start_workers(config);
+        self.repo_manager.start_syncs();
+        notify::spawn(config, self.repo_manager.clone(), &self.jobs);
+    }
+
+    /// Clones the repository and registers it with `options`.
+    async fn add(
+        &self,
+        name: &str,
+        url: &str,
+        options: CloneOptions,
+    ) -> Result<RepositoryEntry, Error> {
+        debug!("adding repo {} from: {}", name, url);
+
+        if let Some(method) = options.build_method.as_deref() {
+            if !BUILD_METHODS.contains(&method) {
+                return Err(Error::Invalid(format!("Invalid build method: {}", method)));
+            }
         }
+
+        self.repo_manager
+            .clone_repository(url, name, options)
+            .await
+            .map_err(|err| err.context("Failed to clone the repository"))?;
+
+        info!("repo is successfully cloned ({})", name);
+        self.registered(name)
     }
 
-    /// Adds a new repository.
-    ///
-    /// # Parameters
+    /// The job `id`, if the token may access its repository with `scope`.
+    fn job(&self, auth: &TokenAuth, id: &str, scope: Scope) -> Result<Job, Error> {
+        let job = self.jobs.get(id).ok_or_else(|| job_not_found(id))?;
+        auth.require(scope, Some(&job.repository))?;
+        Ok(job)
+    }
+
+    /// Syncs the repository with its origin, firing its build triggers.
+    async fn sync(&self, name: &str) -> Result<SyncReport, Error> {
+        debug!("syncing repo {} ", name);
+        let report = self.repo_manager.sync_repo(name).await?;
+
+        info!("synced repo successfully ({})", name);
+        Ok(report)
+    }
+
+    /// Registry entry of the repository `name`, an error if it is not registered.
+    fn registered(&self, name: &str) -> Result<RepositoryEntry, Error> {
+        self.repo_manager
+            .get_entry(name)
+            .ok_or_else(|| Error::repo_not_found(name))
+    }
+
+    /// Handle to the repository manager, shared with the webhook routes.
+    pub fn repo_manager(&self) -> Repo {
+        self.repo_manager.clone()
+    }
+
+    /// Handle to the API tokens, the token checker finds it in the request data.
+    pub fn tokens(&self) -> TokenStore {
+        self.tokens.clone()
+    }
+
+    /// Reports whether the service is healthy.
     ///
-    /// * `name`: Name of the repository.
-    /// * `url`: URL of the repository.
+    /// The service is degraded if git is not installed or a build method can't run
+    /// because its tools are missing, e.g. `make` or the container engine.
+    /// Tools are probed at startup, see `/system/tools`.
     ///
     /// # Returns
     ///
-    /// `AddRepository::Ok` if the repository is added successfully, otherwise `AddRepository::ServerError`.
-    #[oai(path = "/repo/:name/add/:url", method = "post")]
-    pub async fn add_repository(
-        &self,
-        name: param::Path<String>,
-        url: param::Path<String>,
-    ) -> AddRepository {
-        debug!("adding repo {} from: {}", name.to_string(), url.to_string());
-        match self.repo_manager.clone_repository(&url, &name).await {
-            Ok(_) => {
-                info!("repo is successfully cloned ({})", name.to_string());
-                AddRepository::Ok
-            }
-            Err(err) => {
-                error!("failed to clone the repo ({})", err);
-                AddRepository::ServerError
-            }
-        }
+    /// `HealthResponse::Ok` with the status and the availability of every build method.
+    #[oai(path = "/health", method = "get")]
+    pub async fn health(&self) -> HealthResponse {
+        let report = self.dependencies.report();
+        let status = if report.is_healthy() {
+            HealthStatus::Ok
+        } else {
+            HealthStatus::Degraded
+        };
+
+        HealthResponse::Ok(Json(Health {
+            status,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            build_methods: report.build_methods,
+        }))
     }
 
-    /// Retrieves tags for a repository.
+    /// Lists the host tools the service depends on.
+    ///
+    /// Reports git, make, sh, docker, podman, the configured container engine and cargo
+    /// with their versions, and which build methods they make available. Builds of an
+    /// unavailable method are rejected with `503 tool_missing`.
     ///
     /// # Parameters
     ///
-    /// * `name`: Name of the repository.
+    /// * `refresh`: Probe the tools again instead of returning the results cached at startup,
+    ///   e.g. after installing one.
     ///
     /// # Returns
     ///
-    /// `GetTags::Ok` with repository tags if successful, otherwise `GetTags::NotFound`.
-    #[oai(path = "/repo/:name/tags", method = "get")]
-    pub async fn get_tags(&self, name: param::Path<String>) -> GetTags<Vec<String>> {
-        debug!("requesting tags for ({})", name.to_string());
-        match self.repo_manager.get_tags(&name).await {
-            Ok(tags) => {
-                info!("get tags successfully ({})", name.to_string());
-                GetTags::Ok(Json(tags))
-            }
-            Err(e) => {
-                error!("failed to get tags ({})", e);
-                GetTags::NotFound
-            }
-        }
+    /// `ToolsResponse::Ok` with the probed tools and build methods.
+    #[oai(path = "/system/tools", method = "get")]
+    pub async fn get_tools(
+        &self,
+        auth: TokenAuth,
+        refresh: param::Query<Option<bool>>,
+    ) -> Result<ToolsResponse, ErrorResponse> {
+        auth.require(Scope::Read, None)?;
+        let report = if refresh.unwrap_or(false) {
+            self.dependencies.refresh().await
+        } else {
+            self.dependencies.report()
+        };
+
+        Ok(ToolsResponse::Ok(Json(report)))
     }
 
-    /// Builds a repository using the specified method.
+    /// Lists the API tokens, without their secrets.
     ///
-    /// # Parameters
+    /// Needs an admin token that is not restricted to some repositories.
     ///
-    /// * `name`: Name of the repository.
+    /// # Returns
     ///
-    /// * `method`: The build method to be used. Valid methods are "make", "script", "cargo", and "docker".
+    /// `TokensResponse::Ok` with the tokens.
+    #[oai(path = "/tokens", method = "get")]
+    pub async fn list_tokens(&self, auth: TokenAuth) -> Result<TokensResponse, ErrorResponse> {
+        auth.0.require_unrestricted_admin()?;
+        Ok(TokensResponse::Ok(Json(self.tokens.list())))
+    }
+
+    /// Creates an API token.
     ///
-    /// # Folder Structure
+    /// Tokens are sent as `Authorization: Bearer <secret>`. The scope `read` allows reading
+    /// repositories, tags, jobs and logs, `build` additionally starting, cancelling and syncing
+    /// builds, and `admin` adding and configuring repositories and managing tokens.
+    /// Only a hash of the secret is stored, so it is returned once and can't be retrieved again.
     ///
-    /// For the "make" method, a Makefile named "Makefile" must be present in the repository's `make/` directory.
-    /// For the "script" method, the script must be named "build_script.sh" and placed in the repository's `script/` directory.
+    /// Needs an admin token that is not restricted to some repositories.
     ///
-    /// Example folder structure:
-    /// ```
-    /// workflows/
-    /// ├── make/
-    /// │   └── Makefile
-    /// └── script/
-    /// │   └── build_script.sh
+    /// # Parameters
     ///
-    /// ```
+    /// * `token`: Name, scopes and the repositories to restrict the token to, all if empty.
     ///
     /// # Returns
     ///
-    /// If the repository is built successfully, returns `GetTags::Ok` containing a message indicating success.
-    /// If the provided method is invalid or if any error occurs during the build process, returns `GetTags::InternalServerError`
-    /// with an appropriate error message.
-    #[oai(path = "/repo/:method/build/:name/:url", method = "put")]
-    pub async fn build_repo(
+    /// `CreateToken::Ok` with the secret and the token, `422 invalid_request` without a name or scope.
+    #[oai(path = "/tokens", method = "post")]
+    pub async fn create_token(
         &self,
-        name: param::Path<String>,
-        method: param::Path<String>,
-        url: param::Path<String>,
-    ) -> BuildRepo {
-        let name = name.to_string();
-        let method = method.to_string();
-        let url = url.to_string();
-
-        let repo_path = &self.file_system.git_path(&name);
-
-        // Validate the method
-        if !["make", "script", "cargo", "docker"].contains(&method.as_str()) {
-            let err_msg = format!("Invalid build method: {}", method);
-            error!(err_msg);
-            return BuildRepo::ServerError(Json(err_msg));
-        }
-
-        // Check if the repository has the required build scripts
-        let script_data = match workflows_exist(&repo_path) {
-            Ok(script_data) => script_data,
-            Err(err) => {
-                let err_msg = format!("Failed to get build scripts: {}", err);
-                error!(err_msg);
-                return BuildRepo::ServerError(Json(err_msg));
-            }
-        };
-
-        // Check if the specified method is available
-        match method.as_str() {
-            "make" if !script_data.has_makefile() => {
-                let err_msg = "Makefile not found in the repository".to_string();
-                error!(err_msg);
-                return BuildRepo::ServerError(Json(err_msg));
-            }
-            "script" if !script_data.has_script() => {
-                let err_msg = "Build script not found in the repository".to_string();
-                error!(err_msg);
-                return BuildRepo::ServerError(Json(err_msg));
-            }
-            "cargo" if !script_data.has_cargo_toml() => {
-                let err_msg = "Cargo toml not found in the repository".to_string();
-                error!(err_msg);
-                return BuildRepo::ServerError(Json(err_msg));
-            }
-            _ => (),
-        }
-
-        // Execute the build process based on the method
-        match method.as_str() {
-            "cargo" => {
-                let dockerfile_content = r#"
-                    FROM ubuntu:latest
-                    RUN apt-get update && \
-                        apt-get install -y curl && \
-                        curl --proto '=https' --tlsv1.2 -sSf https://sh.rustup.rs | sh -s -- -y && \
-                        . $HOME/.cargo/env
-                "#;
-
-                let container_name = format!("cargo_release_{}", &name);
-                let image_name = "my_image";
-
-                // Initialize DockerManager with the desired image name
-                let docker_manager = match DockerManager::new(&image_name, &container_name) {
-                    Ok(manager) => manager,
-                    Err(err) => {
-                        let err_msg = format!("Docker Error: {}", err);
-                        error!(err_msg);
-                        return BuildRepo::ServerError(Json(err_msg));
-                    }
-                };
-
-                docker_manager.build_image(dockerfile_content).unwrap();
-
-                // Run the Docker container
-                if let Err(err) = docker_manager.run_container(&[]) {
-                    let err_msg = format!("Failed to run Docker container: {}", err);
-                    error!(err_msg);
-                    return BuildRepo::ServerError(Json(err_msg));
-                }
-
-                // Execute the cargo build command inside the Docker container
-                if let Err(err) = docker_manager.run_command_in_container("cargo build") {
-                    let err_msg = format!("Cargo build failed: {}", err);
-                    error!(err_msg);
-                    return BuildRepo::ServerError(Json(err_msg));
-                }
-            }
-            "make" => {
-                let make_build_output =
-                    make::execute_makefile(&WorkflowScripts::get_makefile_path(&repo_path)).await;
-
-                if let Err(e) = make_build_output {
-                    let err_msg = format!("Make build failed: {}", e);
-                    error!(err_msg);
-                    return BuildRepo::ServerError(Json(err_msg));
-                }
-            }
-            "script" => {
-                let script_build_output = Command::new("sh")
-                    .arg("./script/build_script.sh")
-                    .output()
-                    .map_err(|e| format!("Failed to execute build script: {}", e));
-
-                if let Err(e) = script_build_output {
-                    let err_msg = format!("Script build failed: {}", e);
-                    error!(err_msg);
-                    return BuildRepo::ServerError(Json(err_msg));
-                }
-            }
-            // TODO: Add docker support
-            // "docker" => {
-            //     let docker_manager = docker::DockerManager::new("");
-            //
-            //     let docker_build_output = docker_manager.build_image(".", ".");
-            //
-            //     if let Err(e) = docker_build_output {
-            //         let err_msg = format!("Docker build failed: {}", e);
-            //         error!(err_msg);
-            //         return BuildRepo::ServerError(Json(err_msg));
-            //     }
-            // }
-            _ => {
-                let err_msg = "Invalid build method specified".to_string();
-                error!(err_msg);
-                return BuildRepo::ServerError(Json(err_msg));
-            }
-        }
+        auth: TokenAuth,
+        token: Json<NewToken>,
+    ) -> Result<CreateToken, ErrorResponse> {
+        auth.0.require_unrestricted_admin()?;
 
-        let msg = format!("Build successful for latest commit ({})", name);
-        info!(msg);
-
-        BuildRepo::Ok(Json(msg))
+        let created = self.tokens.create(token.0)?;
+        info!(
+            "created token {} ({}) by {}",
+            created.token.name, created.token.id, auth.0.name
+        );
+        Ok(CreateToken::Ok(Json(created)))
     }
-    /// Retrieves the available build scripts for a repository.
+
+    /// Revokes an API token, requests using it are rejected from now on.
     ///
-    /// This endpoint is designed as support for the `/repo/:name/build/:method` endpoint.
-    /// It returns a list of available scripts for building the repository.
-    /// More details on scripts can be found in the description of the main endpoint.
+    /// Needs an admin token that is not restricted to some repositories.
     ///
     /// # Parameters
     ///
-    /// * `name`: The name of the repository.
+    /// * `id`: The id of the token.
     ///
     /// # Returns
     ///
-    /// If the operation succeeds, returns `BuildScriptsResponse::Ok` containing a JSON object
-    /// representing the available build scripts. If an error occurs, returns `BuildScriptsResponse::ServerError`
-    /// with an appropriate error message.
-    ///
-    #[oai(path = "/repo/:name/build", method = "get")]
-    pub async fn get_build_scripts_for_repo(
+    /// `RevokeToken::Ok` with the revoked token, `404 not_found` if there is no such token.
+    #[oai(path = "/tokens/:id", method = "delete")]
+    pub async fn revoke_token(
         &self,
-        name: param::Path<String>,
-    ) -> BuildScriptsResponse {
-        let repo_name = name.to_string();
+        auth: TokenAuth,
+        id: param::Path<String>,
+    ) -> Result<RevokeToken, ErrorResponse> {
+        auth.0.require_unrestricted_admin()?;
 
-        let git_path = self.file_system.git_path(&repo_name);
+        let revoked = self.tokens.revoke(&id)?;
+        info!(
+            "revoked token {} ({}) by {}",
+            revoked.name, revoked.id, auth.0.name
+        );
+        Ok(RevokeToken::Ok(Json(revoked)))
+    }
 
-        // serialize the struct to json
-        match workflows_exist(&git_path) {
-            Ok(script_data) => {
-                info!("get build script success ({})", name.to_string());
-                BuildScriptsResponse::Ok(Json(script_data))
-            }
-            Err(err) => {
-                let err_msg = format!("failed to get build scripts: {}", err);
-                error!(err_msg);
-                BuildScriptsResponse::ServerError(Json(err_msg))
-            }
-        }
+    /// Lists the stored credentials, without their secrets.
+    ///
+    /// # Returns
+    ///
+    /// `CredentialsListResponse::Ok` with the name, type and user name of all credentials,
+    /// `403 forbidden` unless the token has the admin scope for all repositories.
+    #[oai(path = "/credentials", method = "get")]
+    pub async fn list_credentials(
+        &self,
+        auth: TokenAuth,
+    ) -> Result<CredentialsListResponse, ErrorResponse> {
+        auth.0.require_unrestricted_admin()?;
+        Ok(CredentialsListResponse::Ok(Json(
+            self.repo_manager.credentials().list(),
+        )))
     }
 
-    /// Syncs a repository with its origin.
+    /// Stores credentials to clone and sync private repositories with.
     ///
-    /// This operation deletes the local repository and clones it again from the origin.
-    /// As long as there are no local changes (as it should be), this operation is without risk.
+    /// SSH keys are used for `ssh://` and `git@host:path` remotes, a username and token for
+    /// `https://` remotes. Existing credentials are replaced, repositories using them pick up
+    /// the new secret on their next sync. Secrets are never returned.
     ///
     /// # Parameters
     ///
-    /// * `name`: The name of the repository to sync.
+    /// * `name`: Name repositories refer to the credentials by.
+    /// * `credentials`: An SSH key or HTTPS token, tagged by `type`.
     ///
     /// # Returns
     ///
-    /// If the repository is successfully reset and synced with the origin, returns `SyncRepoResponse::Ok`
-    /// with a success message. If an error occurs during the process, returns `SyncRepoResponse::ServerError`
-    /// with an appropriate error message.
-    ///
-    #[oai(path = "/repo/:name/sync", method = "get")]
-    pub async fn sync_repo_with_origin(&self, name: param::Path<String>) -> SyncRepoResponse {
-        let repo_name = name.to_string();
+    /// `CredentialsResponse::Ok` with the stored credentials, `422 invalid_request` for an
+    /// invalid name or key.
+    #[oai(path = "/credentials/:name", method = "put")]
+    pub async fn put_credentials(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+        credentials: Json<Credentials>,
+    ) -> Result<CredentialsResponse, ErrorResponse> {
+        auth.0.require_unrestricted_admin()?;
 
-        let git_path = self.file_system.git_path(&repo_name);
-        debug!("syncing repo {} ", name.to_string());
-        match self.repo_manager.sync_repo(&git_path).await {
-            Ok(_) => {
-                let msg = format!("Reset/synced repo successfully ({})", name.to_string());
-                info!("{}", msg);
-                SyncRepoResponse::Ok(Json(msg))
-            }
-            Err(err_msg) => {
-                error!("{}", err_msg);
-                SyncRepoResponse::ServerError(Json(err_msg))
+        let stored = self.repo_manager.credentials().put(&name, credentials.0)?;
+        info!("stored credentials ({}) by {}", stored.name, auth.0.name);
+        Ok(CredentialsResponse::Ok(Json(stored)))
+    }
+
+    /// Deletes stored credentials.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the credentials.
+    ///
+    /// # Returns
+    ///
+    /// `CredentialsResponse::Ok` with the deleted credentials, `404 not_found` if there are no
+    /// such credentials and `409 conflict` while repositories are synced with them.
+    #[oai(path = "/credentials/:name", method = "delete")]
+    pub async fn delete_credentials(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+    ) -> Result<CredentialsResponse, ErrorResponse> {
+        auth.0.require_unrestricted_admin()?;
+
+        let deleted = self.repo_manager.delete_credentials(&name)?;
+        info!("deleted credentials ({}) by {}", deleted.name, auth.0.name);
+        Ok(CredentialsResponse::Ok(Json(deleted)))
+    }
+
+    /// Adds a new repository.
+    ///
+    /// Deprecated, use `POST /v1/repos`: the URL has to be percent-encoded into a single path segment.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    /// * `url`: URL of the repository.
+    /// * `branch`: Branch to check out and keep in sync, defaults to the remote's default branch.
+    /// * `sync_interval`: Seconds between background syncs, defaults to one hour.
+    /// * `build_method`: Default build method of the repository.
+    ///
+    /// The repository is stored in the registry, so it keeps syncing after a restart.
+    ///
+    /// # Returns
+    ///
+    /// `AddRepository::Ok` if the repository is added successfully, otherwise an error, e.g.
+    /// `409 already_exists` if a repository with the name exists or `400 invalid_name` if the name
+    /// isn't made of letters, digits, `-`, `_` and `.`, starts with `.` or `-` or ends in `.git`.
+    #[oai(path = "/repo/:name/add/:url", method = "post", deprecated)]
+    pub async fn add_repository(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+        url: param::Path<String>,
+        branch: param::Query<Option<String>>,
+        sync_interval: param::Query<Option<u64>>,
+        build_method: param::Query<Option<String>>,
+    ) -> Result<AddRepository, ErrorResponse> {
+        auth.require(Scope::Admin, Some(&name))?;
+
+        let options = CloneOptions {
+            branch: branch.0,
+            credentials: None,
+            sync_interval_secs: sync_interval.0,
+            build_method: build_method.0,
+        };
+        self.add(&name, &url, options).await?;
+
+        Ok(AddRepository::Ok)
+    }
+
+    /// Adds a repository by cloning it.
+    ///
+    /// The repository is stored in the registry, so it keeps syncing after a restart.
+    ///
+    /// # Parameters
+    ///
+    /// * `repository`: Name, URL, branch, credentials, sync interval and default build method.
+    ///
+    /// # Returns
+    ///
+    /// `RepositoryResponse::Created` with the registered repository, otherwise an error, e.g.
+    /// `409 already_exists` if a repository with the name exists or `400 invalid_name` if the name
+    /// isn't made of letters, digits, `-`, `_` and `.`, starts with `.` or `-` or ends in `.git`.
+    #[oai(path = "/v1/repos", method = "post")]
+    pub async fn add_repository_v1(
+        &self,
+        auth: TokenAuth,
+        repository: Json<NewRepository>,
+    ) -> Result<RepositoryResponse, ErrorResponse> {
+        let repository = repository.0;
+        auth.require(Scope::Admin, Some(&repository.name))?;
+
+        let options = CloneOptions {
+            branch: repository.branch,
+            credentials: repository.credentials,
+            sync_interval_secs: repository.sync_interval_secs,
+            build_method: repository.build_method,
+        };
+        let entry = self.add(&repository.name, &repository.url, options).await?;
+
+        Ok(RepositoryResponse::Created(Json(entry)))
+    }
+
+    /// Creates an empty bare repository to push to.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    /// * `branch`: Initial branch HEAD points at, defaults to "main".
+    ///
+    /// The repository is served over git's smart HTTP protocol at `/git/<name>`,
+    /// e.g. `git push http://<host>/git/<name> main`, and can be built like any other.
+    ///
+    /// # Returns
+    ///
+    /// `CreateRepository::Ok` if the repository is created, otherwise an error, e.g.
+    /// `409 already_exists` if a repository with the name exists or `400 invalid_name` if the name
+    /// isn't made of letters, digits, `-`, `_` and `.`, starts with `.` or `-` or ends in `.git`.
+    #[oai(path = "/repo/:name/create", method = "post")]
+    pub async fn create_repository(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+        branch: param::Query<Option<String>>,
+    ) -> Result<CreateRepository, ErrorResponse> {
+        auth.require(Scope::Admin, Some(&name))?;
+        let branch = branch.as_deref().unwrap_or("main");
+
+        self.repo_manager
+            .create_repository(&name, branch)
+            .await
+            .map_err(|err| err.context("Failed to create repository"))?;
+
+        info!("created repository ({})", name.as_str());
+        Ok(CreateRepository::Ok(Json(format!(
+            "Created {}",
+            name.as_str()
+        ))))
+    }
+
+    /// Lists the repositories the token may access.
+    ///
+    /// # Parameters
+    ///
+    /// * `offset`: Number of repositories to skip, defaults to 0.
+    /// * `limit`: Number of repositories to return, defaults to 50 and is capped at 500.
+    /// * `filter`: Glob the repository names have to match, e.g. `service-*`.
+    /// * `kind`: Only list "mirror" repositories cloned from a remote or "hosted" ones pushed to.
+    ///
+    /// # Returns
+    ///
+    /// `ListRepositories::Ok` with a page of repositories sorted by name and the number
+    /// of matching repositories.
+    #[oai(path = "/repos", method = "get")]
+    pub async fn list_repositories(
+        &self,
+        auth: TokenAuth,
+        offset: param::Query<Option<u32>>,
+        limit: param::Query<Option<u32>>,
+        filter: param::Query<Option<String>>,
+        kind: param::Query<Option<RepositoryKind>>,
+    ) -> Result<ListRepositories, ErrorResponse> {
+        auth.require(Scope::Read, None)?;
+
+        let offset = offset.unwrap_or(0);
+        let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+        let matcher = tags::tag_filter(filter.as_deref(), None)?;
+
+        let matching: Vec<RepositoryInfo> = self
+            .repo_manager
+            .list_repositories()?
+            .into_iter()
+            .filter(|repo| auth.0.may_access(&repo.name))
+            .filter(|repo| kind.is_none_or(|kind| repo.kind == kind))
+            .filter(|repo| matcher.as_ref().is_none_or(|m| m.is_match(&repo.name)))
+            .collect();
+
+        let total = matching.len() as u32;
+        let repositories = matching
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect();
+
+        Ok(ListRepositories::Ok(Json(RepositoryList {
+            total,
+            offset,
+            limit,
+            repositories,
+        })))
+    }
+
+    /// Retrieves a repository with the state of its checkout.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    ///
+    /// # Returns
+    ///
+    /// `GetRepository::Ok` with the repository, its HEAD commit and the outcome of the
+    /// last sync, `404 repo_not_found` if there is no such repository.
+    #[oai(path = "/repo/:name", method = "get")]
+    pub async fn get_repository(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+    ) -> Result<GetRepository, ErrorResponse> {
+        auth.require(Scope::Read, Some(&name))?;
+
+        let repository = self.repo_manager.repository_info(&name)?;
+        Ok(GetRepository::Ok(Json(Box::new(repository))))
+    }
+
+    /// Deletes a repository.
+    ///
+    /// Stops its background sync, cancels its queued and running builds and removes
+    /// the checkout and the artifacts of its builds. Build logs are kept.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    ///
+    /// # Returns
+    ///
+    /// `DeleteRepository::Ok` with the deleted repository, `404 repo_not_found` if there
+    /// is no such repository.
+    #[oai(path = "/repo/:name", method = "delete")]
+    pub async fn delete_repository(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+    ) -> Result<DeleteRepository, ErrorResponse> {
+        auth.require(Scope::Admin, Some(&name))?;
+
+        let repository = self
+            .repo_manager
+            .delete_repository(&name)
+            .await
+            .map_err(|err| err.context("Failed to delete repository"))?;
+
+        info!("deleted repository ({}) by {}", name.as_str(), auth.0.name);
+        Ok(DeleteRepository::Ok(Json(Box::new(repository))))
+    }
+
+    /// Retrieves tags for a repository.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    /// * `sort`: "name" (default), "semver" for the highest version first, or "date" for the newest first.
+    /// * `filter`: Glob the tag names have to match, e.g. `v*`.
+    /// * `regex`: Regular expression the tag names have to match, instead of `filter`.
+    ///
+    /// # Returns
+    ///
+    /// `GetTags::Ok` with repository tags if successful, `422 invalid_request` for an invalid
+    /// filter and `404 repo_not_found` if there is no such repository.
+    #[oai(path = "/repo/:name/tags", method = "get")]
+    pub async fn get_tags(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+        sort: param::Query<Option<TagSort>>,
+        filter: param::Query<Option<String>>,
+        regex: param::Query<Option<String>>,
+    ) -> Result<GetTags<Vec<TagInfo>>, ErrorResponse> {
+        auth.require(Scope::Read, Some(&name))?;
+        debug!("requesting tags for ({})", name.to_string());
+
+        let matcher = tags::tag_filter(filter.as_deref(), regex.as_deref())?;
+
+        let mut tags = self.repo_manager.get_tags(&name).await?;
+        if let Some(matcher) = matcher {
+            tags.retain(|tag| matcher.is_match(&tag.name));
+        }
+        tags::sort_tags(&mut tags, sort.unwrap_or_default());
+
+        info!("get tags successfully ({})", name.to_string());
+        Ok(GetTags::Ok(Json(tags)))
+    }
+
+    /// Lists the releases of a repository.
+    ///
+    /// A release is opened whenever a tag is built and published with the artifacts
+    /// of the build once it succeeded.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    ///
+    /// # Returns
+    ///
+    /// `ListReleases::Ok` with the releases, the newest first, or `404 repo_not_found`
+    /// if there is no such repository.
+    #[oai(path = "/repo/:name/releases", method = "get")]
+    pub async fn list_releases(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+    ) -> Result<ListReleases, ErrorResponse> {
+        auth.require(Scope::Read, Some(&name))?;
+        self.repo_manager.repository_info(&name)?;
+
+        Ok(ListReleases::Ok(Json(self.releases.list(&name))))
+    }
+
+    /// Retrieves the latest published release of a repository.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    ///
+    /// # Returns
+    ///
+    /// `GetRelease::Ok` with the release of the highest version, or the one published last
+    /// if no tag is a semantic version, `404 not_found` if nothing was published yet.
+    #[oai(path = "/repo/:name/releases/latest", method = "get")]
+    pub async fn latest_release(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+    ) -> Result<GetRelease, ErrorResponse> {
+        auth.require(Scope::Read, Some(&name))?;
+
+        let release = self.releases.latest(&name)?;
+        Ok(GetRelease::Ok(Json(Box::new(release))))
+    }
+
+    /// Retrieves the release of a tag.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    /// * `tag`: The tag that was built.
+    ///
+    /// # Returns
+    ///
+    /// `GetRelease::Ok` with the release and its artifacts, `404 not_found` if the tag
+    /// was not built.
+    #[oai(path = "/repo/:name/releases/:tag", method = "get")]
+    pub async fn get_release(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+        tag: param::Path<String>,
+    ) -> Result<GetRelease, ErrorResponse> {
+        auth.require(Scope::Read, Some(&name))?;
+
+        let release = self.releases.get(&name, &tag)?;
+        Ok(GetRelease::Ok(Json(Box::new(release))))
+    }
+
+    /// Downloads an artifact of a release.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    /// * `tag`: The tag that was built, or `latest` for the latest published release.
+    /// * `artifact`: Name of the artifact.
+    ///
+    /// # Returns
+    ///
+    /// `DownloadArtifact::Ok` streaming the file with its content type, size and digests
+    /// as `ETag` and `Digest` headers, `DownloadArtifact::NotModified` if the `If-None-Match`
+    /// header matches its ETag, `404 not_found` if there is no such release or artifact.
+    #[oai(path = "/repo/:name/releases/:tag/artifacts/:artifact", method = "get")]
+    pub async fn download_artifact(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+        tag: param::Path<String>,
+        artifact: param::Path<String>,
+        #[oai(name = "If-None-Match")] if_none_match: param::Header<Option<String>>,
+    ) -> Result<DownloadArtifact, ErrorResponse> {
+        auth.require(Scope::Read, Some(&name))?;
+
+        let tag = match tag.as_str() {
+            "latest" => self.releases.latest(&name)?.tag,
+            tag => tag.to_string(),
+        };
+        let (artifact, path) = self.releases.artifact(&name, &tag, &artifact)?;
+
+        let etag = artifact.digests.as_ref().map(Digests::etag);
+        if let (Some(etag), Some(if_none_match)) = (&etag, if_none_match.as_deref()) {
+            let matches = if_none_match
+                .split(',')
+                .map(|candidate| candidate.trim().trim_start_matches("W/"))
+                .any(|candidate| candidate == "*" || candidate == etag);
+            if matches {
+                return Ok(DownloadArtifact::NotModified(etag.clone()));
             }
         }
+
+        let file = tokio::fs::File::open(&path)
+            .await
+            .map_err(|e| Error::Io(format!("Failed to open artifact {}: {}", artifact.name, e)))?;
+        let disposition = format!(
+            "attachment; filename=\"{}\"",
+            artifact.name.replace('\\', "\\\\").replace('"', "\\\"")
+        );
+
+        debug!("downloading {} of {} {}", artifact.name, name.as_str(), tag);
+        Ok(DownloadArtifact::Ok(
+            Binary(Body::from_async_read(file)),
+            artifact.content_type,
+            artifact.size,
+            disposition,
+            etag,
+            artifact.digests.as_ref().map(Digests::digest_header),
+        ))
+    }
+
+    /// Returns the public key build outputs are signed with, in minisign format.
+    ///
+    /// Needs no token. Artifacts with a signature have a `.minisig` artifact next to them,
+    /// check one with `minisign -V -p minisign.pub -m <artifact>`.
+    ///
+    /// # Returns
+    ///
+    /// `SigningPublicKey::Ok` with the contents of a `minisign.pub` file,
+    /// `404 not_found` if no signing key was generated.
+    #[oai(path = "/signing/minisign.pub", method = "get")]
+    pub async fn signing_public_key(&self) -> Result<SigningPublicKey, ErrorResponse> {
+        let key = self
+            .signing_keys
+            .current()?
+            .ok_or_else(|| Error::NotFound("No signing key, outputs are not signed".to_string()))?;
+        Ok(SigningPublicKey::Ok(PlainText(
+            key.public_key().to_minisign(),
+        )))
+    }
+
+    /// Lists the current and the retired public signing keys.
+    ///
+    /// Needs no token. Retired keys were replaced by rotating and verify the signatures
+    /// of releases published before.
+    ///
+    /// # Returns
+    ///
+    /// `ListSigningKeys::Ok` with the keys, the current one first.
+    #[oai(path = "/signing/keys", method = "get")]
+    pub async fn list_signing_keys(&self) -> Result<ListSigningKeys, ErrorResponse> {
+        Ok(ListSigningKeys::Ok(Json(self.signing_keys.public_keys()?)))
     }
+
+    /// Builds a repository using the specified method.
+    ///
+    /// Deprecated, use `POST /v1/repos/:name/builds`, which also takes environment variables
+    /// and build options. The methods and folder structure are described there.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    ///
+    /// * `method`: The build method to be used. Valid methods are "make", "script", "cargo", and "docker".
+    ///
+    /// * `url`: Unused, the repository is built from the checkout created when it was added.
+    ///
+    /// * `ref`: Tag, branch or commit SHA to build, defaults to the checked out commit.
+    ///
+    /// # Returns
+    ///
+    /// If the build is queued, returns `BuildRepo::Accepted` containing the new job,
+    /// otherwise the same errors as `POST /v1/repos/:name/builds`.
+    #[oai(path = "/repo/:method/build/:name/:url", method = "put", deprecated)]
+    pub async fn build_repo(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+        method: param::Path<String>,
+        #[oai(name = "url")] _url: param::Path<String>,
+        #[oai(name = "ref")] git_ref: param::Query<Option<String>>,
+    ) -> Result<BuildRepo, ErrorResponse> {
+        auth.require(Scope::Build, Some(&name))?;
+
+        let job = self.jobs.submit(
+            &name,
+            &method,
+            git_ref.0,
+            None,
+            BTreeMap::new(),
+            BuildOptions::default(),
+        )?;
+        Ok(BuildRepo::Accepted(Json(Box::new(job))))
+    }
+
+    /// Builds a repository using the specified method.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    ///
+    /// * `build`: The build method, "make", "script", "cargo" or "docker", defaulting to the
+    ///   repository's build method, the tag, branch or commit SHA to build, defaulting to the
+    ///   checked out commit, environment variables and build options.
+    ///
+    /// # Folder Structure
+    ///
+    /// For the "make" method, a Makefile named "Makefile" must be present in the repository's `make/` directory,
+    /// the `make_target` option selects the target.
+    /// For the "script" method, the script must be named "build_script.sh" and placed in the repository's `script/` directory.
+    /// For the "cargo" method, the repository is built with `cargo build --release` in a Rust container
+    /// (honouring `rust-toolchain.toml`), features and targets are read from
+    /// `[package.metadata.release_workflows]`, extended by the `features` and `targets` options,
+    /// and the built binaries are collected as artifacts.
+    /// For the "docker" method, the image is built from `docker/Dockerfile`, or a `Dockerfile` at the repository root,
+    /// with the repository as build context. Paths listed (comma separated) in the image's
+    /// `release_workflows.artifacts` label are copied out of the image as artifacts. The `no_cache`
+    /// option builds without cached layers.
+    ///
+    /// Example folder structure:
+    /// ```
+    /// workflows/
+    /// ├── docker/
+    /// │   └── Dockerfile
+    /// ├── make/
+    /// │   └── Makefile
+    /// └── script/
+    /// │   └── build_script.sh
+    ///
+    /// ```
+    ///
+    /// The commit is exported to a workspace of its own once the job starts, so builds of different
+    /// refs can run at the same time. The build runs in the background, use `/jobs/:id` to follow
+    /// its progress; it fails if the commit lacks the files the method needs.
+    ///
+    /// # Returns
+    ///
+    /// If the build is queued, returns `BuildRepo::Accepted` containing the new job.
+    /// If the provided method is invalid, or the repository has no
+    /// default method, returns `422 invalid_request`, also for invalid variable names or options,
+    /// `422 invalid_ref` if the ref can't be resolved, `503 tool_missing` if the tools of the method
+    /// are not installed (see `/system/tools`) and `503 unavailable` if the build queue is full.
+    #[oai(path = "/v1/repos/:name/builds", method = "post")]
+    pub async fn build_repo_v1(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+        build: Json<NewBuild>,
+    ) -> Result<BuildRepo, ErrorResponse> {
+        auth.require(Scope::Build, Some(&name))?;
+        let build = build.0;
+
+        let method = match build.method {
+            Some(method) => method,
+            None => self.registered(&name)?.build_method.ok_or_else(|| {
+                Error::Invalid(format!(
+                    "No build method given and {} has no default",
+                    name.as_str()
+                ))
+            })?,
+        };
+
+        let job = self.jobs.submit(
+            &name,
+            &method,
+            build.git_ref,
+            None,
+            build.env,
+            build.options,
+        )?;
+        Ok(BuildRepo::Accepted(Json(Box::new(job))))
+    }
+
+    /// Retrieves the status of a build job.
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The id returned when the build was queued.
+    ///
+    /// # Returns
+    ///
+    /// `GetJob::Ok` with the job, including its status (queued, running, succeeded,
+    /// failed or cancelled) and timestamps, otherwise `404 not_found`.
+    #[oai(path = "/jobs/:id", method = "get")]
+    pub async fn get_job(
+        &self,
+        auth: TokenAuth,
+        id: param::Path<String>,
+    ) -> Result<GetJob, ErrorResponse> {
+        let job = self.job(&auth, &id, Scope::Read)?;
+        Ok(GetJob::Ok(Json(Box::new(job))))
+    }
+
+    /// Retrieves the log of a build job.
+    ///
+    /// The log combines stdout and stderr of every build step, each line prefixed
+    /// with a timestamp and the stream it came from. Large logs can be paged either
+    /// with `offset`/`limit` (in bytes, continue at `X-Next-Offset`) or a standard
+    /// `Range: bytes=start-end` header, which is answered with `206 Partial Content`.
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The id of the job.
+    /// * `offset`: Byte offset to start reading at, defaults to the start of the log.
+    /// * `limit`: Maximum number of bytes to return, defaults to and is capped at 1 MiB.
+    ///
+    /// # Returns
+    ///
+    /// `GetJobLog::Ok` or `GetJobLog::PartialContent` with the requested part of the log,
+    /// `404 not_found` if there is no such job.
+    #[oai(path = "/jobs/:id/log", method = "get")]
+    pub async fn get_job_log(
+        &self,
+        auth: TokenAuth,
+        id: param::Path<String>,
+        offset: param::Query<Option<u64>>,
+        limit: param::Query<Option<u64>>,
+        #[oai(name = "Range")] range: param::Header<Option<String>>,
+    ) -> Result<GetJobLog, ErrorResponse> {
+        self.job(&auth, &id, Scope::Read)?;
+
+        let log_path = self.jobs.log_path(&id);
+
+        if let Some(range) = range.0 {
+            let total = std::fs::metadata(&log_path).map_err(log_read_error)?.len();
+
+            let (start, length) = match log::parse_range(&range, total) {
+                Some(range) => range,
+                None => {
+                    return Ok(GetJobLog::RangeNotSatisfiable(
+                        Json(format!("Invalid range: {}", range)),
+                        format!("bytes */{}", total),
+                    ))
+                }
+            };
+
+            let chunk = log::read_chunk(&log_path, start, Some(length)).map_err(log_read_error)?;
+            let content_range = format!(
+                "bytes {}-{}/{}",
+                chunk.start,
+                chunk.end.saturating_sub(1),
+                chunk.total
+            );
+            return Ok(GetJobLog::PartialContent(
+                PlainText(chunk.content),
+                content_range,
+                chunk.end,
+            ));
+        }
+
+        let chunk =
+            log::read_chunk(&log_path, offset.unwrap_or(0), limit.0).map_err(log_read_error)?;
+        Ok(GetJobLog::Ok(
+            PlainText(chunk.content),
+            chunk.total,
+            chunk.end,
+        ))
+    }
+
+    /// Streams the log of a build job as Server-Sent Events.
+    ///
+    /// Lines that were already written are replayed first, then new stdout/stderr lines
+    /// are sent as they are emitted (`line` events, with the byte offset as event id).
+    /// The stream ends with a `finished` event carrying the job status and the exit code.
+    /// Lines a slow client missed are read back from the log; should that fail, the stream
+    /// ends with an `error` event carrying the offset to continue at.
+    /// A reconnecting client can send `Last-Event-ID` to resume after the last line it got.
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The id of the job.
+    /// * `offset`: Byte offset to start the replay at, defaults to the start of the log.
+    ///
+    /// # Returns
+    ///
+    /// `StreamJobLog::Ok` with the event stream, `404 not_found` if there is no such job.
+    #[oai(path = "/jobs/:id/stream", method = "get")]
+    pub async fn stream_job_log(
+        &self,
+        auth: TokenAuth,
+        id: param::Path<String>,
+        offset: param::Query<Option<u64>>,
+        #[oai(name = "Last-Event-ID")] last_event_id: param::Header<Option<u64>>,
+    ) -> Result<StreamJobLog, ErrorResponse> {
+        self.job(&auth, &id, Scope::Read)?;
+        let (job, live) = self
+            .jobs
+            .get_with_log(&id)
+            .ok_or_else(|| job_not_found(&id))?;
+
+        // resume right after the line the client has seen last
+        let from = match last_event_id.0 {
+            Some(last) => last + 1,
+            None => offset.unwrap_or(0),
+        };
+
+        let finished = job.status.is_finished().then(|| LogFinished {
+            status: job.status,
+            exit_code: job.exit_code,
+            message: job.message.clone(),
+        });
+
+        let events = log::event_stream(&self.jobs.log_path(&id), from, live.as_ref(), finished)
+            .map_err(log_read_error)?;
+
+        Ok(StreamJobLog::Ok(
+            EventStream::new(events)
+                .keep_alive(Duration::from_secs(15))
+                .to_event(|event| {
+                    let data = event.to_json_string();
+                    match event {
+                        LogEvent::Line(line) => Event::message(data)
+                            .event_type("line")
+                            .id(line.offset.to_string()),
+                        LogEvent::Finished(_) => Event::message(data).event_type("finished"),
+                        LogEvent::Error(_) => Event::message(data).event_type("error"),
+                    }
+                }),
+        ))
+    }
+
+    /// Cancels a queued or running build job.
+    ///
+    /// Running builds are stopped by killing their processes.
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The id of the job to cancel.
+    ///
+    /// # Returns
+    ///
+    /// `CancelJob::Ok` with the job, `404 not_found` if there is no such job
+    /// and `409 conflict` if the job already finished.
+    #[oai(path = "/jobs/:id/cancel", method = "post")]
+    pub async fn cancel_job(
+        &self,
+        auth: TokenAuth,
+        id: param::Path<String>,
+    ) -> Result<CancelJob, ErrorResponse> {
+        self.job(&auth, &id, Scope::Build)?;
+        let job = self.jobs.cancel(&id)?;
+        Ok(CancelJob::Ok(Json(Box::new(job))))
+    }
+
+    /// Retrieves the available build scripts for a repository.
+    ///
+    /// This endpoint is designed as support for the `/repo/:name/build/:method` endpoint.
+    /// It returns a list of available scripts for building the repository.
+    /// More details on scripts can be found in the description of the main endpoint.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: The name of the repository.
+    ///
+    /// # Returns
+    ///
+    /// If the operation succeeds, returns `BuildScriptsResponse::Ok` containing a JSON object
+    /// representing the available build scripts. If an error occurs, returns the error with
+    /// an appropriate message.
+    ///
+    #[oai(path = "/repo/:name/build", method = "get")]
+    pub async fn get_build_scripts_for_repo(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+    ) -> Result<BuildScriptsResponse, ErrorResponse> {
+        auth.require(Scope::Read, Some(&name))?;
+        let repo_name = name.to_string();
+
+        let git_path = self.file_system.git_path(&repo_name)?;
+        if !std::path::Path::new(&git_path).exists() {
+            return Err(Error::repo_not_found(&repo_name).into());
+        }
+
+        // serialize the struct to json
+        let script_data =
+            workflows_exist(&git_path).map_err(|err| err.context("Failed to get build scripts"))?;
+
+        info!("get build script success ({})", name.to_string());
+        Ok(BuildScriptsResponse::Ok(Json(script_data)))
+    }
+
+    /// Retrieves the build triggers of a repository.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    ///
+    /// # Returns
+    ///
+    /// `TriggersResponse::Ok` with the triggers, `404 repo_not_found` if the repository is not registered.
+    #[oai(path = "/repo/:name/triggers", method = "get")]
+    pub async fn get_triggers(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+    ) -> Result<TriggersResponse, ErrorResponse> {
+        auth.require(Scope::Read, Some(&name))?;
+        let entry = self.registered(&name)?;
+        Ok(TriggersResponse::Ok(Json(entry.triggers)))
+    }
+
+    /// Replaces the build triggers of a repository.
+    ///
+    /// After every sync, new commits on branches matching a "branch" trigger and new tags
+    /// matching a "tag" trigger are built, with the trigger's method or the repository's
+    /// default build method. Patterns are globs, e.g. `main` or `v*`.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    /// * `triggers`: The new triggers, an empty list disables automatic builds.
+    ///
+    /// # Returns
+    ///
+    /// `TriggersResponse::Ok` with the stored triggers, `422 invalid_request` if a trigger
+    /// is invalid, or `404 repo_not_found` if the repository is not registered.
+    #[oai(path = "/repo/:name/triggers", method = "put")]
+    pub async fn set_triggers(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+        triggers: Json<Vec<BuildTrigger>>,
+    ) -> Result<TriggersResponse, ErrorResponse> {
+        auth.require(Scope::Admin, Some(&name))?;
+        let entry = self.repo_manager.set_triggers(&name, triggers.0)?;
+
+        info!("updated triggers of {}", name.as_str());
+        Ok(TriggersResponse::Ok(Json(entry.triggers)))
+    }
+
+    /// Configures push webhooks of the forge hosting a repository.
+    ///
+    /// Once a secret is set, the forge can notify the service about pushes at
+    /// `POST /hooks/<provider>/<name>`, with provider `github`, `gitea`, `forgejo` or `gitlab`.
+    /// GitHub and Gitea payloads are verified with an HMAC-SHA256 signature using the secret,
+    /// GitLab sends the secret as token. Every verified push syncs the repository, which
+    /// starts builds for its matching triggers. Replayed deliveries are rejected until the
+    /// next restart, unless the sync they started failed, so the forge's redelivery is processed.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    /// * `settings`: The secret, at least 16 characters, or `null` to disable webhooks.
+    ///
+    /// # Returns
+    ///
+    /// `WebhookResponse::Ok` if the settings are stored, `422 invalid_request` for a
+    /// short secret and `404 repo_not_found` if the repository is not registered.
+    #[oai(path = "/repo/:name/webhook", method = "put")]
+    pub async fn set_webhook(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+        settings: Json<WebhookSettings>,
+    ) -> Result<WebhookResponse, ErrorResponse> {
+        auth.require(Scope::Admin, Some(&name))?;
+        self.registered(&name)?;
+
+        let secret = settings.0.secret;
+        if matches!(&secret, Some(secret) if secret.len() < 16) {
+            return Err(Error::Invalid(
+                "Webhook secret must be at least 16 characters".to_string(),
+            )
+            .into());
+        }
+        let enabled = secret.is_some();
+
+        self.repo_manager.set_webhook_secret(&name, secret)?;
+        if enabled {
+            info!("enabled webhooks of {}", name.as_str());
+            Ok(WebhookResponse::Ok(Json(format!(
+                "Webhooks enabled for {}",
+                name.as_str()
+            ))))
+        } else {
+            info!("disabled webhooks of {}", name.as_str());
+            Ok(WebhookResponse::Ok(Json(format!(
+                "Webhooks disabled for {}",
+                name.as_str()
+            ))))
+        }
+    }
+
+    /// Sets the stored credentials a repository is synced with.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    /// * `credentials`: Name of the stored credentials, `null` to sync anonymously.
+    ///
+    /// # Returns
+    ///
+    /// `RepositoryResponse::Ok` with the updated repository, `422 invalid_request` for unknown
+    /// credentials and `404 repo_not_found` if the repository is not registered.
+    #[oai(path = "/repo/:name/credentials", method = "put")]
+    pub async fn set_credentials(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+        credentials: Json<RepositoryCredentials>,
+    ) -> Result<RepositoryResponse, ErrorResponse> {
+        auth.require(Scope::Admin, Some(&name))?;
+
+        let entry = self
+            .repo_manager
+            .set_credentials(&name, credentials.0.credentials)?;
+        info!(
+            "set credentials of {} to {}",
+            name.as_str(),
+            entry.credentials.as_deref().unwrap_or("none")
+        );
+        Ok(RepositoryResponse::Ok(Json(entry)))
+    }
+
+    /// Lists where the build events of a repository are sent.
+    ///
+    /// Secrets of the notifiers, like webhook URLs, tokens and passwords, are not returned.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    ///
+    /// # Returns
+    ///
+    /// `NotificationsResponse::Ok` with the notification routes of the repository, or
+    /// `404 repo_not_found` if the repository is not registered.
+    #[oai(path = "/repo/:name/notifications", method = "get")]
+    pub async fn get_notifications(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+    ) -> Result<NotificationsResponse, ErrorResponse> {
+        auth.require(Scope::Read, Some(&name))?;
+        let entry = self.registered(&name)?;
+        Ok(NotificationsResponse::Ok(Json(entry.notifications)))
+    }
+
+    /// Replaces the notification routes of a repository.
+    ///
+    /// Every route sends some events, or all of them if `events` is empty, to a notifier:
+    /// a Discord or Slack webhook, a Matrix room, a generic JSON webhook with an optional
+    /// Handlebars body template, or email over SMTP. Events are `started`, `succeeded`,
+    /// `failed` and `release_published`, the latter for successful builds of a tag.
+    /// Notifications link to the job log if `public_url` is configured.
+    ///
+    /// Secrets are never returned, so they have to be sent again with every update.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: Name of the repository.
+    /// * `routes`: The new routes, an empty list disables notifications.
+    ///
+    /// # Returns
+    ///
+    /// `NotificationsResponse::Ok` with the stored routes, `422 invalid_request`
+    /// if a notifier is misconfigured, or `404 repo_not_found` if the repository
+    /// is not registered.
+    #[oai(path = "/repo/:name/notifications", method = "put")]
+    pub async fn set_notifications(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+        routes: Json<Vec<NotificationRoute>>,
+    ) -> Result<NotificationsResponse, ErrorResponse> {
+        auth.require(Scope::Admin, Some(&name))?;
+        let entry = self.repo_manager.set_notifications(&name, routes.0)?;
+
+        info!("updated notifications of {}", name.as_str());
+        Ok(NotificationsResponse::Ok(Json(entry.notifications)))
+    }
+
+    /// Syncs a repository with its origin.
+    ///
+    /// Deprecated, use `POST /v1/repos/:name/sync`: a sync changes the checkout and may start
+    /// builds, which a GET request must not do.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: The name of the repository to sync.
+    ///
+    /// # Returns
+    ///
+    /// The same as `POST /v1/repos/:name/sync`.
+    ///
+    #[oai(path = "/repo/:name/sync", method = "get", deprecated)]
+    pub async fn sync_repo_with_origin(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+    ) -> Result<SyncRepoResponse<SyncReport>, ErrorResponse> {
+        auth.require(Scope::Build, Some(&name))?;
+        let report = self.sync(&name).await?;
+        Ok(SyncRepoResponse::Ok(Json(report)))
+    }
+
+    /// Syncs a repository with its origin.
+    ///
+    /// Fetches branches and tags from the origin (pruning refs deleted there) and
+    /// fast-forwards the checked out branch, falling back to a hard reset if the
+    /// histories diverged. The checkout stays in place, so build caches are kept.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: The name of the repository to sync.
+    ///
+    /// # Returns
+    ///
+    /// If the repository is successfully synced with the origin, returns `SyncRepoResponse::Ok`
+    /// with the list of references that moved, builds are started for matching triggers. If an error occurs during the process,
+    /// returns the error with an appropriate message.
+    ///
+    #[oai(path = "/v1/repos/:name/sync", method = "post")]
+    pub async fn sync_repo_v1(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+    ) -> Result<SyncRepoResponse<SyncReport>, ErrorResponse> {
+        auth.require(Scope::Build, Some(&name))?;
+        let report = self.sync(&name).await?;
+        Ok(SyncRepoResponse::Ok(Json(report)))
+    }
+
+    /// Re-clones a repository from its origin.
+    ///
+    /// This operation deletes the local repository and clones it again from the origin.
+    /// It is meant as a fallback for checkouts that can no longer be synced, as the
+    /// repository is missing while the clone is in progress.
+    ///
+    /// # Parameters
+    ///
+    /// * `name`: The name of the repository to re-clone.
+    ///
+    /// # Returns
+    ///
+    /// If the repository is successfully re-cloned, returns `SyncRepoResponse::Ok`
+    /// with a success message. If an error occurs during the process, returns
+    /// the error with an appropriate message.
+    ///
+    #[oai(path = "/repo/:name/reclone", method = "post")]
+    pub async fn reclone_repo_from_origin(
+        &self,
+        auth: TokenAuth,
+        name: param::Path<String>,
+    ) -> Result<SyncRepoResponse<String>, ErrorResponse> {
+        auth.require(Scope::Build, Some(&name))?;
+        let repo_name = name.to_string();
+
+        debug!("re-cloning repo {} ", name.to_string());
+        self.repo_manager.reclone_repo(&repo_name).await?;
+
+        let msg = format!("Reset/synced repo successfully ({})", *name);
+        info!("{}", msg);
+        Ok(SyncRepoResponse::Ok(Json(msg)))
+    }
+}
+
+fn job_not_found(id: &str) -> Error {
+    Error::NotFound(format!("Job not found ({})", id))
+}
+
+fn log_read_error(err: std::io::Error) -> Error {
+    Error::Io(format!("Failed to read build log: {}", err))
 }
@@ -0,0 +1,366 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Component, Path},
+};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::build::docker::DockerManager;
+use crate::build::log::JobLog;
+use crate::util::error::{Error, Result};
+use crate::util::workflows::WorkflowScripts;
+
+/// Deliverables of a build, declared in `workflows/artifacts.toml` of the repository.
+///
+/// ```toml
+/// [[artifact]]
+/// path = "target/release/mytool"
+///
+/// [[artifact]]
+/// path = "dist/*.tar.gz"
+/// required = false
+/// ```
+///
+/// Paths are relative to the repository root, or to the image's working directory for
+/// docker builds. `*` and `?` match within a directory, `**` matches any number of
+/// directories. Matched files are collected under their file name once the build succeeded,
+/// instead of the binaries or image label collected without a manifest.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArtifactManifest {
+    #[serde(rename = "artifact")]
+    pub artifacts: Vec<ArtifactPattern>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArtifactPattern {
+    pub path: String,
+    /// Whether the build fails if nothing matches, defaults to true.
+    #[serde(default = "default_required")]
+    pub required: bool,
+}
+
+fn default_required() -> bool {
+    true
+}
+
+impl ArtifactPattern {
+    fn validate(&self) -> Result<()> {
+        let path = Path::new(&self.path);
+        let escapes = path
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+        if self.path.is_empty() || escapes {
+            return Err(Error::Invalid(format!(
+                "Invalid artifact path {:?}, expected a relative path without `..`",
+                self.path
+            )));
+        }
+        Ok(())
+    }
+
+    fn regex(&self) -> Regex {
+        let mut pattern = String::from("^");
+        let mut chars = self.path.trim_start_matches("./").chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        pattern.push_str("(?:.*/)?");
+                    } else {
+                        pattern.push_str(".*");
+                    }
+                }
+                '*' => pattern.push_str("[^/]*"),
+                '?' => pattern.push_str("[^/]"),
+                c => pattern.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        pattern.push('$');
+
+        // every other character is escaped, so the pattern is always valid
+        Regex::new(&pattern).expect("artifact pattern is a valid regex")
+    }
+
+    /// Leading directories without wildcards, everything that can match lies below them.
+    fn static_prefix(&self) -> String {
+        let components: Vec<&str> = self
+            .path
+            .trim_start_matches("./")
+            .split('/')
+            .take_while(|component| !component.contains(['*', '?']))
+            .collect();
+        components.join("/")
+    }
+}
+
+impl ArtifactManifest {
+    /// Reads the manifest of the repository at `repo_path`, `None` if it has none.
+    pub fn load(repo_path: &str) -> Result<Option<Self>> {
+        let path = WorkflowScripts::get_artifacts_manifest_path(repo_path);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::Io(format!("Failed to read {}: {}", path, e))),
+        };
+
+        let manifest: ArtifactManifest = toml::from_str(&content)
+            .map_err(|e| Error::Invalid(format!("Invalid artifact manifest: {}", e)))?;
+        for artifact in &manifest.artifacts {
+            artifact.validate()?;
+        }
+        Ok(Some(manifest))
+    }
+
+    /// Copies the files below `root` matching the manifest to `artifacts_dir`.
+    ///
+    /// Fails if a required artifact is missing or two artifacts have the same file name.
+    pub fn collect(&self, root: &Path, artifacts_dir: &str, log: &JobLog) -> Result<()> {
+        let files = list_files(root);
+        let mut collected: BTreeMap<String, String> = BTreeMap::new();
+        let mut missing = Vec::new();
+
+        for artifact in &self.artifacts {
+            let regex = artifact.regex();
+            let matches: Vec<&String> = files.iter().filter(|file| regex.is_match(file)).collect();
+
+            if matches.is_empty() {
+                if artifact.required {
+                    missing.push(artifact.path.as_str());
+                } else {
+                    log.info(&format!("optional artifact {} not found", artifact.path));
+                }
+                continue;
+            }
+
+            for file in matches {
+                let name = file.rsplit('/').next().unwrap_or(file).to_string();
+                match collected.get(&name) {
+                    Some(other) if other == file => continue,
+                    Some(other) => {
+                        return Err(Error::BuildFailed(format!(
+                            "Artifacts {} and {} have the same file name",
+                            other, file
+                        )))
+                    }
+                    None => (),
+                }
+
+                fs::create_dir_all(artifacts_dir).map_err(|e| {
+                    Error::Io(format!("Failed to create artifacts directory: {}", e))
+                })?;
+                fs::copy(root.join(file), Path::new(artifacts_dir).join(&name))
+                    .map_err(|e| Error::Io(format!("Failed to collect {}: {}", file, e)))?;
+                log.info(&format!("collected {}", file));
+                collected.insert(name, file.clone());
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(Error::BuildFailed(format!(
+                "Required artifacts not found after the build: {}",
+                missing.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    /// Copies the files matching the manifest out of a container, with paths relative
+    /// to `container_root`, to `artifacts_dir`.
+    ///
+    /// `docker cp` knows no wildcards, so everything below the leading directories
+    /// of each pattern is copied to a staging directory and matched there.
+    pub async fn collect_from_container(
+        &self,
+        docker_manager: &DockerManager,
+        container_root: &str,
+        artifacts_dir: &str,
+        log: &JobLog,
+    ) -> Result<()> {
+        let staging = tempfile::tempdir()
+            .map_err(|e| Error::Io(format!("Failed to create staging directory: {}", e)))?;
+
+        let mut prefixes: Vec<String> = self
+            .artifacts
+            .iter()
+            .map(ArtifactPattern::static_prefix)
+            .collect();
+        prefixes.sort();
+        prefixes.dedup();
+
+        for prefix in prefixes {
+            let source = match prefix.as_str() {
+                "" => format!("{}/.", container_root.trim_end_matches('/')),
+                prefix => format!("{}/{}", container_root.trim_end_matches('/'), prefix),
+            };
+            let target = staging.path().join(&prefix);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|e| Error::Io(format!("Failed to create staging directory: {}", e)))?;
+            }
+
+            // a missing path is reported by the required check below
+            if let Err(err) = docker_manager
+                .copy_from_container(&source, &target.to_string_lossy())
+                .await
+            {
+                log.info(&format!("could not copy {}: {}", source, err));
+            }
+        }
+
+        self.collect(staging.path(), artifacts_dir, log)
+    }
+}
+
+/// Paths of all regular files below `dir`, relative to it and separated by `/`.
+///
+/// Symlinks are skipped rather than followed, builds and images control them,
+/// so they could point at files of the host or loop back to a parent directory.
+pub fn list_files(dir: &Path) -> Vec<String> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(current) = pending.pop() {
+        let entries = match fs::read_dir(&current) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(_) => continue,
+            };
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if !file_type.is_file() {
+                continue;
+            } else if let Ok(relative) = path.strip_prefix(dir) {
+                let components: Vec<String> = relative
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                    .collect();
+                files.push(components.join("/"));
+            }
+        }
+    }
+
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(path: &str, required: bool) -> ArtifactPattern {
+        ArtifactPattern {
+            path: path.to_string(),
+            required,
+        }
+    }
+
+    fn write(dir: &Path, name: &str) {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, name).unwrap();
+    }
+
+    fn temp_log(dir: &Path) -> JobLog {
+        JobLog::create(&dir.join("build.log").to_string_lossy()).unwrap()
+    }
+
+    #[test]
+    fn globs_match_within_and_across_directories() {
+        let regex = pattern("dist/*.tar.gz", true).regex();
+        assert!(regex.is_match("dist/tool.tar.gz"));
+        assert!(!regex.is_match("dist/nested/tool.tar.gz"));
+        assert!(!regex.is_match("dist/tool.tarxgz"));
+
+        let regex = pattern("./target/release/tool-?", true).regex();
+        assert!(regex.is_match("target/release/tool-1"));
+        assert!(!regex.is_match("target/release/tool-12"));
+
+        let regex = pattern("**/*.deb", true).regex();
+        assert!(regex.is_match("tool.deb"));
+        assert!(regex.is_match("target/debian/tool.deb"));
+
+        let regex = pattern("out/**", true).regex();
+        assert!(regex.is_match("out/a/b"));
+        assert!(!regex.is_match("other/a"));
+
+        assert_eq!(pattern("target/*/tool", true).static_prefix(), "target");
+        assert_eq!(pattern("**/*.deb", true).static_prefix(), "");
+    }
+
+    #[test]
+    fn paths_outside_the_repository_are_rejected() {
+        for path in ["", "../tool", "dist/../../tool", "/etc/passwd"] {
+            assert!(
+                matches!(pattern(path, true).validate(), Err(Error::Invalid(_))),
+                "{:?}",
+                path
+            );
+        }
+        assert_eq!(pattern("./dist/*.tar.gz", true).validate(), Ok(()));
+    }
+
+    #[test]
+    fn only_missing_required_artifacts_fail_the_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        write(&root, "target/release/tool");
+        let artifacts = dir.path().join("artifacts");
+        let log = temp_log(dir.path());
+
+        let manifest = ArtifactManifest {
+            artifacts: vec![
+                pattern("target/release/tool", true),
+                pattern("dist/*.tar.gz", false),
+            ],
+        };
+        manifest
+            .collect(&root, &artifacts.to_string_lossy(), &log)
+            .unwrap();
+        assert!(artifacts.join("tool").is_file());
+
+        let manifest = ArtifactManifest {
+            artifacts: vec![pattern("dist/*.tar.gz", true)],
+        };
+        let err = manifest
+            .collect(&root, &artifacts.to_string_lossy(), &log)
+            .unwrap_err();
+        assert!(matches!(&err, Error::BuildFailed(message) if message.contains("dist/*.tar.gz")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_not_followed() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        write(&root, "out/tool");
+        write(dir.path(), "secret/passwd");
+        symlink(dir.path().join("secret"), root.join("escape")).unwrap();
+        symlink(dir.path().join("secret/passwd"), root.join("out/passwd")).unwrap();
+        symlink(".", root.join("out/loop")).unwrap();
+
+        assert_eq!(list_files(&root), ["out/tool"]);
+
+        let artifacts = dir.path().join("artifacts");
+        let manifest = ArtifactManifest {
+            artifacts: vec![pattern("escape/passwd", true)],
+        };
+        let log = temp_log(dir.path());
+        assert!(manifest
+            .collect(&root, &artifacts.to_string_lossy(), &log)
+            .is_err());
+        assert!(!artifacts.join("passwd").exists());
+    }
+}
@@ -0,0 +1,261 @@
+use std::{fs, path::Path};
+
+use serde::Deserialize;
+
+use crate::build::artifacts::{list_files, ArtifactManifest};
+use crate::build::docker::DockerManager;
+use crate::build::jobs::BuildRequest;
+use crate::build::log::JobLog;
+use crate::build::runner::is_safe_char;
+use crate::util::config::Config;
+use crate::util::error::{Error, Result};
+use crate::util::workflows::WorkflowScripts;
+
+/// Directory the repository is copied to inside the build container.
+const SOURCE_DIR: &str = "/src";
+/// Directory the built binaries are collected in inside the build container.
+const OUTPUT_DIR: &str = "/out";
+
+/// Build settings read from `[package.metadata.release_workflows]`
+/// (or `[workspace.metadata.release_workflows]`) of the repository's Cargo.toml.
+///
+/// ```toml
+/// [package.metadata.release_workflows]
+/// features = ["cli"]
+/// targets = ["x86_64-unknown-linux-musl"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CargoSettings {
+    pub features: Vec<String>,
+    pub all_features: bool,
+    pub no_default_features: bool,
+    /// Target triples to build for, the container's host target if empty.
+    pub targets: Vec<String>,
+}
+
+impl CargoSettings {
+    /// Reads the settings from the Cargo.toml of the repository at `repo_path`.
+    pub fn load(repo_path: &str) -> Result<Self> {
+        let path = WorkflowScripts::get_cargo_toml_path(repo_path);
+        let content = fs::read_to_string(&path)
+            .map_err(|e| Error::Io(format!("Failed to read {}: {}", path, e)))?;
+        let manifest: toml::Value = toml::from_str(&content)
+            .map_err(|e| Error::Invalid(format!("Failed to parse {}: {}", path, e)))?;
+
+        let settings = ["package", "workspace"].iter().find_map(|section| {
+            manifest
+                .get(section)?
+                .get("metadata")?
+                .get("release_workflows")
+        });
+
+        let settings: CargoSettings = match settings {
+            Some(settings) => settings.clone().try_into().map_err(|e| {
+                Error::Invalid(format!(
+                    "Invalid release_workflows metadata in {}: {}",
+                    path, e
+                ))
+            })?,
+            None => CargoSettings::default(),
+        };
+
+        // the values end up in a shell script, only allow what cargo accepts anyway
+        for value in settings.features.iter().chain(&settings.targets) {
+            if value.is_empty() || !value.chars().all(is_safe_char) {
+                return Err(Error::Invalid(format!(
+                    "Invalid feature or target {:?} in {}",
+                    value, path
+                )));
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+/// Toolchain channel pinned by `rust-toolchain.toml` or the legacy `rust-toolchain` file.
+pub fn pinned_toolchain(repo_path: &str) -> Option<String> {
+    if let Ok(content) = fs::read_to_string(format!("{}/rust-toolchain.toml", repo_path)) {
+        return toml_channel(&content);
+    }
+
+    let content = fs::read_to_string(format!("{}/rust-toolchain", repo_path)).ok()?;
+    let content = content.trim();
+    if content.starts_with('[') {
+        // the legacy file may also be in toml format
+        toml_channel(content)
+    } else if content.is_empty() {
+        None
+    } else {
+        Some(content.to_string())
+    }
+}
+
+fn toml_channel(content: &str) -> Option<String> {
+    let file: toml::Value = toml::from_str(content).ok()?;
+    file.get("toolchain")?
+        .get("channel")?
+        .as_str()
+        .map(str::to_string)
+}
+
+/// Image to build in: `rust:<version>` for a pinned version, the configured image otherwise.
+fn toolchain_image(config: &Config, toolchain: Option<&str>) -> String {
+    match toolchain {
+        Some(channel)
+            if !channel.is_empty() && channel.chars().all(|c| c.is_ascii_digit() || c == '.') =>
+        {
+            format!("rust:{}", channel)
+        }
+        _ => config.docker.rust_image.clone(),
+    }
+}
+
+/// Shell script run in the container: installs the toolchain, builds and collects binaries.
+fn build_script(settings: &CargoSettings, toolchain_file: bool) -> String {
+    let mut script = vec!["set -e".to_string()];
+
+    if toolchain_file {
+        // installs the pinned channel together with its components and targets,
+        // rustup before 1.28 does that on `rustup show` instead
+        script.push("rustup toolchain install || rustup show".to_string());
+    }
+    if !settings.targets.is_empty() {
+        script.push(format!("rustup target add {}", settings.targets.join(" ")));
+    }
+
+    let mut build = vec!["cargo build --release".to_string()];
+    if !settings.features.is_empty() {
+        build.push(format!("--features {}", settings.features.join(",")));
+    }
+    if settings.all_features {
+        build.push("--all-features".to_string());
+    }
+    if settings.no_default_features {
+        build.push("--no-default-features".to_string());
+    }
+    for target in &settings.targets {
+        build.push(format!("--target {}", target));
+    }
+    script.push(build.join(" "));
+
+    // executables directly in the release directory are the build's binaries,
+    // build scripts and dependencies live in subdirectories
+    let outputs: Vec<(String, String)> = if settings.targets.is_empty() {
+        vec![("target/release".to_string(), OUTPUT_DIR.to_string())]
+    } else {
+        settings
+            .targets
+            .iter()
+            .map(|target| {
+                (
+                    format!("target/{}/release", target),
+                    format!("{}/{}", OUTPUT_DIR, target),
+                )
+            })
+            .collect()
+    };
+    for (release_dir, output_dir) in outputs {
+        script.push(format!("mkdir -p {}", output_dir));
+        script.push(format!(
+            "find {} -maxdepth 1 -type f -perm -u+x -exec cp {{}} {} \\;",
+            release_dir, output_dir
+        ));
+    }
+
+    // a single line keeps the command readable in the job log
+    script.join("; ")
+}
+
+/// Builds the repository in release mode inside a fresh Rust container and
+/// copies the resulting binaries, or the artifacts declared by `manifest`, to `artifacts_dir`.
+///
+/// The container is created for this job only and removed afterwards.
+/// Features and targets of the request's options are applied on top of the repository's settings.
+pub async fn execute_cargo(
+    config: &Config,
+    id: &str,
+    repo_path: &str,
+    artifacts_dir: &str,
+    manifest: Option<&ArtifactManifest>,
+    request: &BuildRequest,
+    log: &JobLog,
+) -> Result<()> {
+    let mut settings = CargoSettings::load(repo_path)?;
+    settings
+        .features
+        .extend(request.options.features.iter().cloned());
+    if !request.options.targets.is_empty() {
+        settings.targets = request.options.targets.clone();
+    }
+
+    let toolchain = pinned_toolchain(repo_path);
+    let image = toolchain_image(config, toolchain.as_deref());
+
+    match &toolchain {
+        Some(channel) => log.info(&format!("using toolchain {} in {}", channel, image)),
+        None => log.info(&format!("using {}", image)),
+    }
+
+    let container_name = format!("cargo_build_{}", id);
+    let docker_manager = DockerManager::new(&config.docker.binary, &image, &container_name)
+        .with_env(request.env.clone());
+
+    let script = build_script(&settings, toolchain.is_some());
+    let container = docker_manager
+        .create_container(
+            &["--workdir", SOURCE_DIR, "--env", "CARGO_TERM_COLOR=never"],
+            &["sh", "-c", &script],
+            log,
+        )
+        .await?;
+
+    let result = build_in_container(&docker_manager, repo_path, artifacts_dir, manifest, log).await;
+
+    if let Err(err) = container.remove().await {
+        log.info(&format!("failed to remove container: {}", err));
+    }
+
+    result
+}
+
+async fn build_in_container(
+    docker_manager: &DockerManager,
+    repo_path: &str,
+    artifacts_dir: &str,
+    manifest: Option<&ArtifactManifest>,
+    log: &JobLog,
+) -> Result<()> {
+    // copying instead of mounting keeps the checkout clean and works with remote engines
+    log.info(&format!("copying {} into the container", repo_path));
+    docker_manager
+        .copy_to_container(&format!("{}/.", repo_path), SOURCE_DIR)
+        .await?;
+
+    docker_manager.start_attached(log).await?;
+
+    // declared artifacts replace the binaries, relative to the repository like for host builds
+    if let Some(manifest) = manifest {
+        return manifest
+            .collect_from_container(docker_manager, SOURCE_DIR, artifacts_dir, log)
+            .await
+            .map_err(|e| e.context("Failed to collect artifacts"));
+    }
+
+    fs::create_dir_all(artifacts_dir)
+        .map_err(|e| Error::Io(format!("Failed to create artifacts directory: {}", e)))?;
+    docker_manager
+        .copy_from_container(&format!("{}/.", OUTPUT_DIR), artifacts_dir)
+        .await?;
+
+    let collected = list_files(Path::new(artifacts_dir));
+    if collected.is_empty() {
+        log.info("no binaries were built");
+    }
+    for file in collected {
+        log.info(&format!("collected {}", file));
+    }
+
+    Ok(())
+}
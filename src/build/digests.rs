@@ -0,0 +1,280 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+use base64::Engine;
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::build::artifacts::list_files;
+use crate::util::error::{Error, Result};
+use crate::util::file_system::replace_file;
+use crate::util::signing::{is_signature_file, SigningKey};
+
+/// Checksum file listing the SHA-256 of every output, in the format of `sha256sum`.
+const SHA256SUMS: &str = "SHA256SUMS";
+const SHA512SUMS: &str = "SHA512SUMS";
+const B3SUMS: &str = "B3SUMS";
+
+/// Hash algorithms computed in addition to SHA-256, which is always computed.
+#[derive(Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestAlgorithm {
+    Sha512,
+    Blake3,
+}
+
+/// Hex encoded digests of a file.
+#[derive(Debug, Object, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct Digests {
+    pub sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha512: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
+}
+
+impl Digests {
+    /// Value of an `ETag` header identifying the file by its content.
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", self.sha256)
+    }
+
+    /// Value of a `Digest` header (RFC 3230), the digests are base64 encoded there.
+    pub fn digest_header(&self) -> String {
+        let base64 = |hex_digest: &str| {
+            hex::decode(hex_digest)
+                .map(|bytes| base64::engine::general_purpose::STANDARD.encode(bytes))
+                .unwrap_or_default()
+        };
+
+        let mut values = vec![format!("sha-256={}", base64(&self.sha256))];
+        if let Some(sha512) = &self.sha512 {
+            values.push(format!("sha-512={}", base64(sha512)));
+        }
+        values.join(",")
+    }
+}
+
+/// A file a build produced.
+#[derive(Debug, Object, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct BuildOutput {
+    /// Path relative to the job's artifacts directory.
+    pub name: String,
+    /// Size in bytes.
+    pub size: u64,
+    pub digests: Digests,
+    /// File name of the output's minisign signature, unset without a signing key.
+    pub signature: Option<String>,
+}
+
+/// Digests the file at `path`, reading it once for all algorithms.
+pub fn digest_file(path: &Path, algorithms: &[DigestAlgorithm]) -> io::Result<Digests> {
+    let mut sha256 = Sha256::new();
+    let mut sha512 = algorithms
+        .contains(&DigestAlgorithm::Sha512)
+        .then(Sha512::new);
+    let mut blake3 = algorithms
+        .contains(&DigestAlgorithm::Blake3)
+        .then(blake3::Hasher::new);
+
+    let mut file = fs::File::open(path)?;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        sha256.update(&buffer[..read]);
+        if let Some(sha512) = &mut sha512 {
+            sha512.update(&buffer[..read]);
+        }
+        if let Some(blake3) = &mut blake3 {
+            blake3.update(&buffer[..read]);
+        }
+    }
+
+    Ok(Digests {
+        sha256: hex::encode(sha256.finalize()),
+        sha512: sha512.map(|hasher| hex::encode(hasher.finalize())),
+        blake3: blake3.map(|hasher| hasher.finalize().to_hex().to_string()),
+    })
+}
+
+/// Whether `name` is one of the checksum files written by [`write_checksums`].
+pub fn is_checksum_file(name: &str) -> bool {
+    [SHA256SUMS, SHA512SUMS, B3SUMS].contains(&name)
+}
+
+/// Digests every file in `dir` and writes the checksum files next to them,
+/// and signs the outputs and checksum files with `signing_key` if there is one.
+///
+/// Checksum and signature files a build left in the directory are no outputs,
+/// they are replaced if the service writes them as well. Only regular files are outputs,
+/// the build controls the directory, so a symlink could point at any file of the host.
+pub fn record_outputs(
+    dir: &str,
+    algorithms: &[DigestAlgorithm],
+    signing_key: Option<&SigningKey>,
+) -> Result<Vec<BuildOutput>> {
+    let io_error = |e: io::Error| Error::Io(format!("Failed to digest build outputs: {}", e));
+
+    let dir = Path::new(dir);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut outputs = Vec::new();
+    for name in list_files(dir) {
+        if is_checksum_file(&name) || is_signature_file(&name) {
+            continue;
+        }
+        let path = dir.join(&name);
+        let metadata = fs::symlink_metadata(&path).map_err(io_error)?;
+        if !metadata.is_file() {
+            tracing::warn!("skipping build output {}, not a regular file", name);
+            continue;
+        }
+        let size = metadata.len();
+        let digests = digest_file(&path, algorithms).map_err(io_error)?;
+        outputs.push(BuildOutput {
+            name,
+            size,
+            digests,
+            signature: None,
+        });
+    }
+
+    let entries: Vec<(&str, &Digests)> = outputs
+        .iter()
+        .map(|output| (output.name.as_str(), &output.digests))
+        .collect();
+    let checksum_files = write_checksums(dir, &entries).map_err(io_error)?;
+
+    if let Some(signing_key) = signing_key {
+        let sign_error = |e: io::Error| Error::Io(format!("Failed to sign build outputs: {}", e));
+        for output in &mut outputs {
+            let signature = signing_key
+                .write_signature(&dir.join(&output.name))
+                .map_err(sign_error)?;
+            output.signature = Some(signature);
+        }
+        for file in checksum_files {
+            signing_key
+                .write_signature(&dir.join(file))
+                .map_err(sign_error)?;
+        }
+    }
+
+    Ok(outputs)
+}
+
+/// Writes `SHA256SUMS`, plus `SHA512SUMS` and `B3SUMS` if those digests were computed,
+/// to `dir`, in the format `sha256sum --check` and `b3sum --check` read.
+///
+/// Returns the names of the files written, nothing is written without entries.
+pub fn write_checksums(dir: &Path, entries: &[(&str, &Digests)]) -> io::Result<Vec<&'static str>> {
+    if entries.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut written = Vec::new();
+    for file in [SHA256SUMS, SHA512SUMS, B3SUMS] {
+        let digest = |digests: &Digests| match file {
+            SHA512SUMS => digests.sha512.clone(),
+            B3SUMS => digests.blake3.clone(),
+            _ => Some(digests.sha256.clone()),
+        };
+        let lines: Option<Vec<String>> = entries
+            .iter()
+            .map(|(name, digests)| digest(digests).map(|digest| format!("{}  {}\n", digest, name)))
+            .collect();
+        if let Some(lines) = lines {
+            replace_file(&dir.join(file), lines.concat().as_bytes())?;
+            written.push(file);
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digests(sha256: &str, blake3: Option<&str>) -> Digests {
+        Digests {
+            sha256: sha256.to_string(),
+            sha512: None,
+            blake3: blake3.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn checksum_files_use_the_sha256sum_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let tool = digests("aa", Some("cc"));
+        let archive = digests("bb", Some("dd"));
+
+        let written =
+            write_checksums(dir.path(), &[("tool", &tool), ("tool.tar.gz", &archive)]).unwrap();
+
+        assert_eq!(written, [SHA256SUMS, B3SUMS]);
+        assert_eq!(
+            fs::read_to_string(dir.path().join(SHA256SUMS)).unwrap(),
+            "aa  tool\nbb  tool.tar.gz\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join(B3SUMS)).unwrap(),
+            "cc  tool\ndd  tool.tar.gz\n"
+        );
+        assert!(!dir.path().join(SHA512SUMS).exists());
+    }
+
+    #[test]
+    fn digests_match_known_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty");
+        fs::write(&path, "").unwrap();
+
+        let digests = digest_file(&path, &[DigestAlgorithm::Blake3]).unwrap();
+        assert_eq!(
+            digests.sha256,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            digests.blake3.as_deref(),
+            Some("af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262")
+        );
+        assert_eq!(digests.sha512, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_outputs_are_neither_digested_nor_written_through() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let outputs = dir.path().join("outputs");
+        fs::create_dir(&outputs).unwrap();
+        fs::write(outputs.join("tool"), "tool").unwrap();
+        let host_file = dir.path().join("shadow");
+        fs::write(&host_file, "secret").unwrap();
+        symlink(&host_file, outputs.join("x")).unwrap();
+        symlink(&host_file, outputs.join(SHA256SUMS)).unwrap();
+
+        let recorded = record_outputs(&outputs.to_string_lossy(), &[], None).unwrap();
+
+        let names: Vec<&str> = recorded.iter().map(|output| output.name.as_str()).collect();
+        assert_eq!(names, ["tool"]);
+        assert_eq!(fs::read_to_string(&host_file).unwrap(), "secret");
+        let checksums = fs::read_to_string(outputs.join(SHA256SUMS)).unwrap();
+        assert!(checksums.ends_with("  tool\n"));
+        assert!(!fs::symlink_metadata(outputs.join(SHA256SUMS))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+    }
+}
@@ -1,248 +1,315 @@
-use std::process::{Command, Output};
+use std::{
+    collections::{BTreeMap, HashMap},
+    process::Stdio,
+};
+
+use tokio::process::Command;
+
+use crate::build::log::JobLog;
+use crate::build::process;
+use crate::util::error::{Error, Result};
+
+/// Variables the engine's CLI reads itself, e.g. to pick the daemon, its configuration
+/// or registry credentials. Builds may not set them, see [`DockerManager::with_env`].
+const ENGINE_VARIABLES: [&str; 8] = [
+    "PATH",
+    "HOME",
+    "TMPDIR",
+    "REGISTRY_AUTH_FILE",
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "NO_PROXY",
+    "ALL_PROXY",
+];
+
+/// Prefixes of further variables the engine's CLI, its plugins or the loader read.
+const ENGINE_VARIABLE_PREFIXES: [&str; 9] = [
+    "DOCKER_",
+    "BUILDKIT_",
+    "BUILDX_",
+    "CONTAINER_",
+    "CONTAINERS_",
+    "PODMAN_",
+    "XDG_",
+    "LD_",
+    "SSL_",
+];
+
+/// Fails if `env` sets a variable that would configure the engine's CLI rather than the build.
+pub fn check_env(env: &BTreeMap<String, String>) -> Result<()> {
+    for name in env.keys() {
+        let upper = name.to_ascii_uppercase();
+        if ENGINE_VARIABLES.contains(&upper.as_str())
+            || ENGINE_VARIABLE_PREFIXES
+                .iter()
+                .any(|prefix| upper.starts_with(prefix))
+        {
+            return Err(Error::Invalid(format!(
+                "Environment variable {} would configure the container engine, not the build",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
 
 pub struct DockerManager {
+    binary: String,
     image_name: String,
     container_name: String,
+    /// Variables set for the engine, passed on by name as build args and container env.
+    env: BTreeMap<String, String>,
 }
 
 impl DockerManager {
-    pub fn new(image_name: &str, container_name: &str) -> Result<Self, String> {
-        // Check if Docker is available
-        let docker_check = Command::new("docker").arg("--version").output();
-
-        if let Ok(output) = docker_check {
-            if output.status.success() {
-                // Docker is available
-                Ok(DockerManager {
-                    image_name: image_name.to_string(),
-                    container_name: container_name.to_string(),
-                })
-            } else {
-                // Docker command failed
-                Err("Docker is not available".to_string())
-            }
-        } else {
-            // Error executing Docker command
-            Err("Failed to execute Docker command".to_string())
+    /// Creates a manager for the engine `binary`, which the startup probe found
+    /// (see [`crate::util::depends`]); a missing one fails the first command.
+    pub fn new(binary: &str, image_name: &str, container_name: &str) -> Self {
+        DockerManager {
+            binary: binary.to_string(),
+            image_name: image_name.to_string(),
+            container_name: container_name.to_string(),
+            env: BTreeMap::new(),
         }
     }
 
-    pub fn run_container(&self, options: &[&str]) -> Result<(), String> {
-        // Check if the container is already running
-        if self.is_container_running()? {
-            return Ok(());
-        }
-
-        // Start the container if it's not running
-        self.start_container(options)
+    /// Sets variables passed to image builds as `--build-arg` and to containers as `--env`.
+    ///
+    /// Only the names appear on the command line, the engine takes the values from its own
+    /// environment, so they don't end up in the build log. That environment is the CLI's too,
+    /// so `env` must have passed [`check_env`].
+    pub fn with_env(mut self, env: BTreeMap<String, String>) -> Self {
+        self.env = env;
+        self
     }
 
-    pub fn stop_container(&self) -> Result<(), String> {
-        let output = Command::new("docker")
-            .args(&["stop", &self.container_name])
-            .output()
-            .map_err(|e| format!("Failed to stop Docker container: {}", e))?;
+    /// Creates a command for the container engine, killed if the build is cancelled.
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.binary);
+        cmd.kill_on_drop(true).envs(&self.env);
+        cmd
+    }
 
-        if output.status.success() {
-            Ok(())
-        } else {
-            Err(format!(
-                "Failed to stop Docker container: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ))
+    /// Builds the image from a Dockerfile using `context_path` as build context,
+    /// tagging it with the manager's image name and every entry of `extra_tags`.
+    pub async fn build_image_from_context(
+        &self,
+        dockerfile_path: &str,
+        context_path: &str,
+        extra_tags: &[String],
+        no_cache: bool,
+        log: &JobLog,
+    ) -> Result<()> {
+        let mut cmd = self.command();
+        cmd.args(["build", "-f", dockerfile_path, "-t", &self.image_name]);
+        for tag in extra_tags {
+            cmd.args(["-t", tag]);
         }
-    }
+        for name in self.env.keys() {
+            cmd.args(["--build-arg", name]);
+        }
+        if no_cache {
+            cmd.arg("--no-cache");
+        }
+        cmd.arg(context_path);
 
-    pub fn build_image(&self, dockerfile_content: &str) -> Result<Output, String> {
-        let temp_dir =
-            tempfile::tempdir().map_err(|e| format!("Failed to create temp directory: {}", e))?;
-        let dockerfile_path = temp_dir.path().join("Dockerfile");
-        std::fs::write(&dockerfile_path, dockerfile_content)
-            .map_err(|e| format!("Failed to write Dockerfile: {}", e))?;
-
-        Command::new("docker")
-            .args(&[
-                "build",
-                "-t",
-                &self.image_name,
-                "-f",
-                dockerfile_path.to_str().unwrap(),
-                ".",
-            ])
-            .current_dir(temp_dir.path())
-            .output()
-            .map_err(|e| format!("Failed to execute build command: {}", e))
+        process::run_checked(&mut cmd, log)
+            .await
+            .map_err(|e| e.context("Failed to execute build command"))
     }
 
-    /// Method to pull the Docker image if not found locally
-    fn pull_image_if_not_exists(&self) -> Result<(), String> {
-        let output = Command::new("docker")
-            .args(&["images", "--format", "{{.Repository}}:{{.Tag}}"])
+    /// Returns a value of the image's config, rendered as JSON by the `--format` template.
+    async fn inspect_image<T: serde::de::DeserializeOwned>(&self, format: &str) -> Result<T> {
+        let output = self
+            .command()
+            .args(["image", "inspect", "--format", format, &self.image_name])
             .output()
-            .map_err(|e| format!("Failed to execute docker command: {}", e))?;
-
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        if !output_str.contains(&self.image_name) {
-            println!("Pulling Docker image {}...", &self.image_name);
-            let pull_output = Command::new("docker")
-                .args(&["pull", &self.image_name])
-                .output()
-                .map_err(|e| format!("Failed to pull Docker image: {}", e))?;
-
-            if !pull_output.status.success() {
-                return Err(format!(
-                    "Failed to pull Docker image: {}",
-                    String::from_utf8_lossy(&pull_output.stderr)
-                ));
-            }
+            .await
+            .map_err(|e| Error::Docker(format!("Failed to inspect Docker image: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(Error::Docker(format!(
+                "Failed to inspect Docker image: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
         }
 
-        Ok(())
+        serde_json::from_slice(&output.stdout)
+            .map_err(|e| Error::Docker(format!("Failed to parse Docker image config: {}", e)))
     }
 
-    fn start_container(&self, options: &[&str]) -> Result<(), String> {
-        let mut cmd = Command::new("docker");
-        cmd.arg("start");
-
-        // Specify the container name
-        cmd.arg(&self.container_name);
-
-        // Execute the command
-        let output = cmd
-            .output()
-            .map_err(|e| format!("Failed to execute start command: {}", e))?;
+    /// Returns the labels of the image.
+    pub async fn image_labels(&self) -> Result<HashMap<String, String>> {
+        // images without labels report `null`
+        let labels: Option<HashMap<String, String>> =
+            self.inspect_image("{{json .Config.Labels}}").await?;
+        Ok(labels.unwrap_or_default())
+    }
 
-        if output.status.success() {
-            Ok(())
-        } else {
-            // Attempt to pull the image if the start command failed
-            if let Err(err) = self.pull_image_if_not_exists() {
-                return Err(format!("Failed to pull Docker image: {}", err));
-            }
+    /// Returns the working directory of the image, `/` if it sets none.
+    pub async fn image_working_dir(&self) -> Result<String> {
+        let working_dir: Option<String> = self.inspect_image("{{json .Config.WorkingDir}}").await?;
+        Ok(working_dir
+            .filter(|dir| !dir.is_empty())
+            .unwrap_or_else(|| "/".to_string()))
+    }
 
-            // Retry starting the container
-            let output_retry = Command::new("docker")
-                .arg("start")
-                .arg(&self.container_name)
-                .output()
-                .map_err(|e| {
-                    format!("Failed to execute start command after pulling image: {}", e)
-                })?;
-
-            if output_retry.status.success() {
-                tracing::info!("Container is now running.");
-                Ok(())
-            } else {
-                let err_msg = format!(
-                    "Failed to start Docker container: {}",
-                    String::from_utf8_lossy(&output_retry.stderr)
-                );
-                tracing::error!(err_msg);
-                Err(err_msg)
-            }
+    /// Creates (without starting) a container of the image running `command`.
+    ///
+    /// The image is pulled if it is not available locally. The returned guard
+    /// removes the container again, also if the build is cancelled.
+    pub async fn create_container(
+        &self,
+        options: &[&str],
+        command: &[&str],
+        log: &JobLog,
+    ) -> Result<ContainerGuard> {
+        let mut cmd = self.command();
+        cmd.args(["create", "--name", &self.container_name]);
+        for name in self.env.keys() {
+            cmd.args(["--env", name]);
         }
-    }
 
-    /// Check if the Docker container is running
-    fn is_container_running(&self) -> Result<bool, String> {
-        let output = Command::new("docker")
-            .args(&["ps", "--format", "{{.Names}}"])
-            .output()
-            .map_err(|e| format!("Failed to execute docker command: {}", e))?;
+        process::run_checked(cmd.args(options).arg(&self.image_name).args(command), log)
+            .await
+            .map_err(|e| match e {
+                Error::ToolMissing(_) => e,
+                e => Error::Docker(format!("Failed to create Docker container: {}", e)),
+            })?;
+
+        Ok(ContainerGuard {
+            binary: self.binary.clone(),
+            container_name: self.container_name.clone(),
+            removed: false,
+        })
+    }
 
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        Ok(output_str.contains(&self.container_name))
+    /// Starts the created container and waits for it, logging its output.
+    pub async fn start_attached(&self, log: &JobLog) -> Result<()> {
+        process::run_checked(
+            self.command()
+                .args(["start", "--attach", &self.container_name]),
+            log,
+        )
+        .await
     }
 
-    pub fn run_script_in_container(
-        &self,
-        script_path: &str,
-        script_name: &str,
-    ) -> Result<(), String> {
-        // copy script to container
-        let cp_output = Command::new("docker")
-            .args(&[
+    /// Copies a file or directory from the host into the container.
+    pub async fn copy_to_container(&self, host_path: &str, container_path: &str) -> Result<()> {
+        let output = self
+            .command()
+            .args([
                 "cp",
-                script_path,
-                &format!("{}:{}", &self.container_name, script_name),
+                host_path,
+                &format!("{}:{}", &self.container_name, container_path),
             ])
             .output()
-            .map_err(|e| format!("Failed to copy script to container: {}", e))?;
-
-        if !cp_output.status.success() {
-            return Err(format!(
-                "Failed to copy script to container: {}",
-                String::from_utf8_lossy(&cp_output.stderr)
-            ));
-        }
+            .await
+            .map_err(|e| Error::Docker(format!("Failed to copy files to container: {}", e)))?;
 
-        // execute script
-        let exec_output = Command::new("docker")
-            .args(&["exec", &self.image_name, "sh", script_name])
-            .output()
-            .map_err(|e| format!("Failed to execute script in container: {}", e))?;
-
-        if !exec_output.status.success() {
-            return Err(format!(
-                "Script execution failed: {}",
-                String::from_utf8_lossy(&exec_output.stderr)
-            ));
+        if !output.status.success() {
+            return Err(Error::Docker(format!(
+                "Failed to copy files to container: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
         }
 
         Ok(())
     }
 
-    pub fn copy_from_container(&self, container_path: &str, host_path: &str) -> Result<(), String> {
-        let output = Command::new("docker")
-            .args(&[
+    pub async fn copy_from_container(&self, container_path: &str, host_path: &str) -> Result<()> {
+        let output = self
+            .command()
+            .args([
                 "cp",
                 &format!("{}:{}", &self.container_name, container_path),
                 host_path,
             ])
             .output()
-            .map_err(|e| format!("Failed to copy file from container: {}", e))?;
+            .await
+            .map_err(|e| Error::Docker(format!("Failed to copy file from container: {}", e)))?;
 
         if !output.status.success() {
-            return Err(format!(
+            return Err(Error::Docker(format!(
                 "Failed to copy file from container: {}",
                 String::from_utf8_lossy(&output.stderr)
-            ));
+            )));
         }
 
         Ok(())
     }
+}
 
-    pub fn clone_repository(
-        &self,
-        repository_url: &str,
-        target_directory: &str,
-    ) -> Result<Output, String> {
-        Command::new("docker")
-            .args(&[
-                "exec",
-                &self.container_name,
-                "git",
-                "clone",
-                repository_url,
-                target_directory,
-            ])
-            .output()
-            .map_err(|e| format!("Failed to clone repository: {}", e))
-    }
+/// A container created for a single job, removed when the guard is dropped.
+pub struct ContainerGuard {
+    binary: String,
+    container_name: String,
+    removed: bool,
+}
+
+impl ContainerGuard {
+    /// Removes the container, stopping it first if it is running.
+    pub async fn remove(mut self) -> Result<()> {
+        self.removed = true;
 
-    pub fn run_command_in_container(&self, command: &str) -> Result<(), String> {
-        let exec_output = Command::new("docker")
-            .args(&["exec", &self.container_name, "sh", "-c", command])
+        let output = Command::new(&self.binary)
+            .args(["rm", "-f", &self.container_name])
             .output()
-            .map_err(|e| format!("Failed to execute command in container: {}", e))?;
+            .await
+            .map_err(|e| Error::Docker(format!("Failed to remove Docker container: {}", e)))?;
 
-        if !exec_output.status.success() {
-            return Err(format!(
-                "Command execution failed: {}",
-                String::from_utf8_lossy(&exec_output.stderr)
-            ));
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(Error::Docker(format!(
+                "Failed to remove Docker container: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )))
         }
+    }
+}
 
-        Ok(())
+impl Drop for ContainerGuard {
+    fn drop(&mut self) {
+        if self.removed {
+            return;
+        }
+
+        // dropped without `remove`, e.g. because the job was cancelled; clean up in the background
+        let child = std::process::Command::new(&self.binary)
+            .args(["rm", "-f", &self.container_name])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+
+        match child {
+            Ok(mut child) => {
+                std::thread::spawn(move || child.wait());
+            }
+            Err(e) => tracing::warn!("failed to remove container {}: {}", self.container_name, e),
+        }
     }
 }
+
+/// Turns a name and tag into a valid image reference, e.g. `My Repo` and `v1.0`
+/// into `my-repo:v1.0`.
+pub fn image_reference(name: &str, tag: &str) -> String {
+    let sanitize = |value: &str, allowed: fn(char) -> bool| -> String {
+        let value: String = value
+            .chars()
+            .map(|c| if allowed(c) { c } else { '-' })
+            .collect();
+        value.trim_matches(|c| c == '-' || c == '.').to_string()
+    };
+
+    let name = sanitize(&name.to_lowercase(), |c| {
+        c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_' | '.')
+    });
+    let tag = sanitize(tag, |c| {
+        c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')
+    });
+
+    // tags are limited to 128 characters
+    let tag: String = tag.chars().take(128).collect();
+    format!("{}:{}", name, if tag.is_empty() { "latest" } else { &tag })
+}
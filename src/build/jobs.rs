@@ -0,0 +1,866 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs, io,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc, Notify};
+use uuid::Uuid;
+
+use crate::build::digests::{self, BuildOutput};
+use crate::build::log::JobLog;
+use crate::build::releases::ReleaseStore;
+use crate::build::runner::{self, BuildOptions};
+use crate::git::manager::{CommitInfo, RepositoryManager};
+use crate::util::config::Config;
+use crate::util::depends::Dependencies;
+use crate::util::error::{Error, Result};
+use crate::util::file_system::{write_atomic, FileSystem};
+use crate::util::signing::SigningKeys;
+
+/// Job updates buffered for slow subscribers before they miss some.
+const EVENT_CAPACITY: usize = 256;
+
+/// Seconds finished jobs are kept in memory, later they are read from their job directory.
+const FINISHED_JOB_RETENTION_SECS: i64 = 60 * 60;
+
+/// Lifecycle state of a build job.
+#[derive(Debug, Enum, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[oai(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobStatus::Succeeded | JobStatus::Failed | JobStatus::Cancelled
+        )
+    }
+}
+
+/// A build job as reported by the API.
+///
+/// Recorded next to the job's log on every change, see [`JobQueue::get`].
+#[derive(Debug, Object, Serialize, Deserialize, Clone)]
+pub struct Job {
+    /// Unique id of the job.
+    pub id: String,
+    /// Name of the repository being built.
+    pub repository: String,
+    /// Build method, one of "make", "script", "cargo" or "docker".
+    pub method: String,
+    /// Tag, branch or commit the build was requested for, HEAD if not set.
+    pub git_ref: Option<String>,
+    /// SHA of the commit being built.
+    pub commit: String,
+    /// What started the build, e.g. a new tag found by a sync; unset for builds requested directly.
+    pub trigger: Option<String>,
+    /// Tag the build releases, set when the requested ref is a tag.
+    pub release_tag: Option<String>,
+    pub options: BuildOptions,
+    pub status: JobStatus,
+    /// Result message of a finished job, the error for failed jobs.
+    pub message: Option<String>,
+    /// Exit code of the last process the build ran.
+    pub exit_code: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Files the build produced with their digests, set once it succeeded.
+    #[serde(default)]
+    pub outputs: Vec<BuildOutput>,
+}
+
+/// Everything a worker needs to run a build.
+#[derive(Debug, Clone)]
+pub struct BuildRequest {
+    pub repository: String,
+    /// Checkout of the repository, the commit is exported from it.
+    pub repo_path: String,
+    pub method: String,
+    pub git_ref: Option<String>,
+    /// The commit `git_ref` resolved to.
+    pub commit: CommitInfo,
+    pub trigger: Option<String>,
+    /// Variables added to the environment of the build.
+    pub env: BTreeMap<String, String>,
+    pub options: BuildOptions,
+}
+
+struct JobEntry {
+    job: Job,
+    request: BuildRequest,
+    /// Export of the commit the build runs in, created when the job starts
+    /// and removed once it finished.
+    workspace: String,
+    cancel: Arc<Notify>,
+    /// Open while the job is queued or running.
+    log: Option<JobLog>,
+}
+
+/// Queue of build jobs processed by a fixed number of workers.
+///
+/// Unfinished jobs and recently finished ones are kept in memory,
+/// older ones are read from their job directory.
+/// The handle is cheap to clone.
+#[derive(Clone)]
+pub struct JobQueue {
+    jobs: Arc<Mutex<HashMap<String, JobEntry>>>,
+    sender: mpsc::Sender<String>,
+    /// Ids of queued jobs, shared by the workers.
+    receiver: Arc<tokio::sync::Mutex<mpsc::Receiver<String>>>,
+    events: broadcast::Sender<Job>,
+    file_system: Arc<FileSystem>,
+    dependencies: Dependencies,
+    /// Releases opened by builds of tags.
+    releases: ReleaseStore,
+}
+
+impl JobQueue {
+    /// Creates the queue, jobs are only run once the workers were started.
+    ///
+    /// Builds of tags publish their artifacts as releases in `releases`.
+    pub fn new(config: &Config, dependencies: Dependencies, releases: ReleaseStore) -> Self {
+        let (sender, receiver) = mpsc::channel(config.build.queue_size);
+
+        JobQueue {
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+            sender,
+            receiver: Arc::new(tokio::sync::Mutex::new(receiver)),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            file_system: Arc::new(FileSystem::new(&config.data_dir)),
+            dependencies,
+            releases,
+        }
+    }
+
+    /// Spawns `config.build.workers` workers processing the queued jobs.
+    pub fn start_workers(&self, config: &Config) {
+        for worker in 0..config.build.workers {
+            let queue = self.clone();
+            let receiver = self.receiver.clone();
+            let config = config.clone();
+
+            tokio::spawn(async move {
+                tracing::debug!("build worker {} started", worker);
+                loop {
+                    // only hold the lock while waiting, so other workers can pick up jobs
+                    let id = match receiver.lock().await.recv().await {
+                        Some(id) => id,
+                        None => break,
+                    };
+                    queue.run_job(&config, &id).await;
+                }
+            });
+        }
+    }
+
+    /// Subscribes to job updates, a job is sent when it starts running and once it finished.
+    pub fn subscribe(&self) -> broadcast::Receiver<Job> {
+        self.events.subscribe()
+    }
+
+    fn publish(&self, job: Job) {
+        // an error only means nobody is listening
+        let _ = self.events.send(job);
+    }
+
+    fn lock_jobs(&self) -> MutexGuard<'_, HashMap<String, JobEntry>> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Records the job in its directory, failures are only traced so they never fail a build.
+    fn persist(&self, job: &Job) {
+        if let Err(err) = save_job(&self.file_system.job_metadata_path(&job.id), job) {
+            tracing::warn!("failed to record job {}: {}", job.id, err);
+        }
+    }
+
+    /// Reads a job recorded before the last restart.
+    ///
+    /// Jobs don't survive restarts, so ones that were queued or running are reported failed.
+    fn load(&self, id: &str) -> Option<Job> {
+        // ids are UUIDs, anything else could point outside the jobs directory
+        Uuid::parse_str(id).ok()?;
+
+        let path = self.file_system.job_metadata_path(id);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                tracing::warn!("failed to read job {}: {}", path, e);
+                return None;
+            }
+        };
+
+        let mut job: Job = match serde_json::from_str(&content) {
+            Ok(job) => job,
+            Err(e) => {
+                tracing::warn!("failed to parse job {}: {}", path, e);
+                return None;
+            }
+        };
+        if !job.status.is_finished() {
+            job.status = JobStatus::Failed;
+            job.message = Some("Build was interrupted by a restart".to_string());
+        }
+        Some(job)
+    }
+
+    /// Resolves `git_ref` (HEAD if `None`) in the checkout of `repository` and queues
+    /// a build of the commit with `method`, `env` and `options`.
+    ///
+    /// This is how every build is started, whether requested over the API or by a trigger.
+    pub fn submit(
+        &self,
+        repository: &str,
+        method: &str,
+        git_ref: Option<String>,
+        trigger: Option<String>,
+        env: BTreeMap<String, String>,
+        options: BuildOptions,
+    ) -> Result<Job> {
+        if !runner::BUILD_METHODS.contains(&method) {
+            return Err(Error::Invalid(format!("Invalid build method: {}", method)));
+        }
+        runner::validate_env(method, &env)?;
+        options.validate()?;
+        self.dependencies.check_method(method)?;
+
+        let repo_path = self.file_system.git_path(repository)?;
+        if !std::path::Path::new(&repo_path).exists() {
+            return Err(Error::repo_not_found(repository));
+        }
+        let commit = RepositoryManager::resolve_commit(&repo_path, git_ref.as_deref())?;
+
+        self.enqueue(BuildRequest {
+            repository: repository.to_string(),
+            repo_path,
+            method: method.to_string(),
+            git_ref,
+            commit,
+            trigger,
+            env,
+            options,
+        })
+    }
+
+    /// Queues the build, the worker exports the commit once the job starts.
+    /// Builds of tags open their release.
+    ///
+    /// Fails if the release of the tag is already published or being built, or the queue is full.
+    fn enqueue(&self, request: BuildRequest) -> Result<Job> {
+        let release_tag = request
+            .git_ref
+            .clone()
+            .filter(|git_ref| request.commit.tags.contains(git_ref));
+
+        let job = Job {
+            id: Uuid::new_v4().to_string(),
+            repository: request.repository.clone(),
+            method: request.method.clone(),
+            git_ref: request.git_ref.clone(),
+            commit: request.commit.commit.clone(),
+            trigger: request.trigger.clone(),
+            release_tag,
+            options: request.options.clone(),
+            status: JobStatus::Queued,
+            message: None,
+            exit_code: None,
+            created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+            outputs: Vec::new(),
+        };
+
+        let notes = job
+            .release_tag
+            .as_deref()
+            .and_then(|tag| RepositoryManager::tag_message(&request.repo_path, tag));
+        self.releases.begin(&job, notes)?;
+
+        let log = match JobLog::create(&self.file_system.job_log_path(&job.id)) {
+            Ok(log) => log,
+            Err(err) => {
+                self.releases.abandon(&job, &err.to_string());
+                return Err(err);
+            }
+        };
+        log.info(&format!(
+            "queued {} build of {} at {}",
+            request.method, request.repository, job.commit
+        ));
+
+        let entry = JobEntry {
+            job: job.clone(),
+            request,
+            workspace: self.file_system.workspace_path(&job.id),
+            cancel: Arc::new(Notify::new()),
+            log: Some(log),
+        };
+        self.evict_finished();
+        self.lock_jobs().insert(job.id.clone(), entry);
+
+        if let Err(e) = self.sender.try_send(job.id.clone()) {
+            self.lock_jobs().remove(&job.id);
+            let err = Error::Unavailable(match e {
+                mpsc::error::TrySendError::Full(_) => "Build queue is full".to_string(),
+                mpsc::error::TrySendError::Closed(_) => "Build queue is closed".to_string(),
+            });
+            self.releases.abandon(&job, &err.to_string());
+            return Err(err);
+        }
+
+        self.persist(&job);
+        tracing::info!("queued build job {} ({})", job.id, job.repository);
+        Ok(job)
+    }
+
+    /// Drops jobs from memory that finished more than [`FINISHED_JOB_RETENTION_SECS`] ago,
+    /// they were recorded in their directory and are read from there.
+    fn evict_finished(&self) {
+        let now = Utc::now();
+        self.lock_jobs()
+            .retain(|_, entry| match entry.job.finished_at {
+                Some(finished_at) if entry.job.status.is_finished() => {
+                    (now - finished_at).num_seconds() < FINISHED_JOB_RETENTION_SECS
+                }
+                _ => true,
+            });
+    }
+
+    /// Jobs recorded in the jobs directory, including those before the last restart.
+    fn recorded_jobs(&self) -> Vec<Job> {
+        let entries = match fs::read_dir(self.file_system.jobs_path()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Vec::new(),
+            Err(e) => {
+                tracing::warn!("failed to list jobs: {}", e);
+                return Vec::new();
+            }
+        };
+
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| self.load(&entry.file_name().to_string_lossy()))
+            .collect()
+    }
+
+    /// The job `id`, from memory or, for jobs before the last restart, from its directory.
+    pub fn get(&self, id: &str) -> Option<Job> {
+        let job = self.lock_jobs().get(id).map(|entry| entry.job.clone());
+        job.or_else(|| self.load(id))
+    }
+
+    /// Path of the log file of a job.
+    pub fn log_path(&self, id: &str) -> String {
+        self.file_system.job_log_path(id)
+    }
+
+    /// Returns a job together with its log, if the job has not finished yet.
+    pub fn get_with_log(&self, id: &str) -> Option<(Job, Option<JobLog>)> {
+        let job = self
+            .lock_jobs()
+            .get(id)
+            .map(|entry| (entry.job.clone(), entry.log.clone()));
+        job.or_else(|| self.load(id).map(|job| (job, None)))
+    }
+
+    /// Cancels a queued or running job, fails if there is no such job or it already finished.
+    pub fn cancel(&self, id: &str) -> Result<Job> {
+        let mut jobs = self.lock_jobs();
+        let entry = match jobs.get_mut(id) {
+            Some(entry) => entry,
+            None => {
+                return Err(match self.load(id) {
+                    Some(job) => {
+                        Error::Conflict(format!("Job {} already finished ({:?})", id, job.status))
+                    }
+                    None => Error::NotFound(format!("Job not found ({})", id)),
+                })
+            }
+        };
+
+        match entry.job.status {
+            JobStatus::Queued => {
+                // the worker skips it once it is dequeued
+                entry.job.status = JobStatus::Cancelled;
+                entry.job.finished_at = Some(Utc::now());
+                if let Some(log) = entry.log.take() {
+                    log.info("cancelled before it started");
+                    log.finish(JobStatus::Cancelled, None);
+                }
+                remove_workspace(&entry.workspace);
+                self.releases.abandon(&entry.job, "Build cancelled");
+                self.persist(&entry.job);
+                self.publish(entry.job.clone());
+            }
+            JobStatus::Running => {
+                // the worker stops the build and records the cancellation
+                entry.cancel.notify_one();
+            }
+            status => {
+                return Err(Error::Conflict(format!(
+                    "Job {} already finished ({:?})",
+                    id, status
+                )));
+            }
+        }
+
+        tracing::info!("cancelling build job {}", id);
+        Ok(entry.job.clone())
+    }
+
+    /// Cancels the unfinished jobs of a repository that is being deleted, waits for them
+    /// to stop and removes the artifacts of all its jobs, returns the ids of the cancelled jobs.
+    ///
+    /// Jobs before the last restart are found through their recorded metadata.
+    pub async fn purge_repository(&self, repository: &str) -> Vec<String> {
+        // subscribed before cancelling, so no job can finish unnoticed
+        let mut events = self.subscribe();
+        let (unfinished, mut all): (Vec<String>, Vec<String>) = {
+            let jobs = self.lock_jobs();
+            let of_repository: Vec<&Job> = jobs
+                .values()
+                .map(|entry| &entry.job)
+                .filter(|job| job.repository == repository)
+                .collect();
+            (
+                of_repository
+                    .iter()
+                    .filter(|job| !job.status.is_finished())
+                    .map(|job| job.id.clone())
+                    .collect(),
+                of_repository.iter().map(|job| job.id.clone()).collect(),
+            )
+        };
+
+        let cancelled: Vec<String> = unfinished
+            .into_iter()
+            .filter(|id| self.cancel(id).is_ok())
+            .collect();
+
+        // running builds may still write to their artifacts directory until they stopped
+        let mut running: HashSet<String> = cancelled.iter().cloned().collect();
+        loop {
+            running.retain(|id| {
+                self.lock_jobs()
+                    .get(id)
+                    .is_some_and(|entry| !entry.job.status.is_finished())
+            });
+            if running.is_empty() {
+                break;
+            }
+            match events.recv().await {
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => (),
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+
+        for job in self.recorded_jobs() {
+            if job.repository == repository && !all.contains(&job.id) {
+                all.push(job.id);
+            }
+        }
+
+        for id in all {
+            let artifacts = self.file_system.job_artifacts_path(&id);
+            match fs::remove_dir_all(&artifacts) {
+                Ok(()) => (),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => tracing::warn!("failed to remove artifacts {}: {}", artifacts, e),
+            }
+        }
+
+        if let Err(e) = self.releases.remove_repository(repository) {
+            tracing::warn!("failed to remove releases of {}: {}", repository, e);
+        }
+
+        cancelled
+    }
+
+    /// Digests the files the job left in its artifacts directory, writes the checksum files
+    /// and signs them if a signing key was generated.
+    async fn record_outputs(
+        &self,
+        config: &Config,
+        id: &str,
+        log: &JobLog,
+    ) -> Result<Vec<BuildOutput>> {
+        let artifacts_dir = self.file_system.job_artifacts_path(id);
+        let algorithms = config.build.digests.clone();
+        let signing_keys = SigningKeys::new(&self.file_system);
+
+        // outputs may be large, don't block the runtime while reading them
+        let (outputs, key_id) = tokio::task::spawn_blocking(move || {
+            let signing_key = signing_keys.current()?;
+            let outputs =
+                digests::record_outputs(&artifacts_dir, &algorithms, signing_key.as_ref())?;
+            Ok((outputs, signing_key.map(|key| key.key_id().to_string())))
+        })
+        .await
+        .unwrap_or_else(|e| Err(Error::Io(format!("Failed to digest build outputs: {}", e))))?;
+
+        if let (Some(key_id), false) = (key_id, outputs.is_empty()) {
+            log.info(&format!(
+                "signed {} outputs with key {}",
+                outputs.len(),
+                key_id
+            ));
+        }
+        Ok(outputs)
+    }
+
+    async fn run_job(&self, config: &Config, id: &str) {
+        let (job, request, workspace, cancel, log) = {
+            let mut jobs = self.lock_jobs();
+            let entry = match jobs.get_mut(id) {
+                Some(entry) if entry.job.status == JobStatus::Queued => entry,
+                _ => return,
+            };
+
+            let log = match &entry.log {
+                Some(log) => log.clone(),
+                None => return,
+            };
+
+            entry.job.status = JobStatus::Running;
+            entry.job.started_at = Some(Utc::now());
+            (
+                entry.job.clone(),
+                entry.request.clone(),
+                entry.workspace.clone(),
+                entry.cancel.clone(),
+                log,
+            )
+        };
+
+        tracing::info!("running build job {} ({})", id, request.repository);
+        self.persist(&job);
+        self.publish(job);
+
+        // a cancellation during the export is remembered by `cancel` and stops the build right away
+        let (mut status, mut message) = match prepare_workspace(&request, &workspace, &log).await {
+            Err(err) => (JobStatus::Failed, err.to_string()),
+            Ok(()) => {
+                let build = runner::run_build(config, id, &workspace, &request, &log);
+
+                // dropping the build future kills its child processes
+                tokio::select! {
+                    result = build => match result {
+                        Ok(msg) => (JobStatus::Succeeded, msg),
+                        Err(err) => {
+                            if let Error::ToolMissing(_) = err {
+                                // the tool was removed since it was probed, don't queue more builds needing it
+                                let dependencies = self.dependencies.clone();
+                                tokio::spawn(async move { dependencies.refresh().await });
+                            }
+                            (JobStatus::Failed, err.to_string())
+                        }
+                    },
+                    _ = cancel.notified() => (JobStatus::Cancelled, "Build cancelled".to_string()),
+                }
+            }
+        };
+
+        let mut outputs = Vec::new();
+        if status == JobStatus::Succeeded {
+            match self.record_outputs(config, id, &log).await {
+                Ok(recorded) => outputs = recorded,
+                Err(err) => (status, message) = (JobStatus::Failed, err.to_string()),
+            }
+        }
+
+        match status {
+            JobStatus::Failed => tracing::error!("build job {} failed: {}", id, message),
+            _ => tracing::info!("build job {} finished: {}", id, message),
+        }
+        log.info(&message);
+        log.finish(status, Some(message.clone()));
+        remove_workspace(&workspace);
+
+        let finished = self.lock_jobs().get_mut(id).map(|entry| {
+            entry.job.status = status;
+            entry.job.message = Some(message);
+            entry.job.exit_code = log.exit_code();
+            entry.job.finished_at = Some(Utc::now());
+            entry.job.outputs = outputs;
+            // closes the log file
+            entry.log = None;
+            entry.job.clone()
+        });
+        if let Some(job) = finished {
+            self.persist(&job);
+            // publish the release first, so subscribers see it once they hear of the job
+            self.releases
+                .finish(
+                    &job,
+                    &self.file_system.job_artifacts_path(id),
+                    &config.build.digests,
+                )
+                .await;
+            self.publish(job);
+        }
+    }
+}
+
+/// Records `job` at `path`, so it can be looked up after a restart.
+fn save_job(path: &str, job: &Job) -> Result<()> {
+    let content = serde_json::to_string_pretty(job)
+        .map_err(|e| Error::Io(format!("Failed to serialize job: {}", e)))?;
+
+    write_atomic(path, content.as_bytes(), false)
+        .map_err(|e| Error::Io(format!("Failed to write job {}: {}", path, e)))
+}
+
+/// Exports the commit of `request` to a workspace of its own, so concurrent builds
+/// never share files, and checks that it can be built with the requested method.
+async fn prepare_workspace(request: &BuildRequest, workspace: &str, log: &JobLog) -> Result<()> {
+    log.info(&format!(
+        "exporting {} to the workspace",
+        request.commit.commit
+    ));
+
+    let repo_path = request.repo_path.clone();
+    let commit = request.commit.commit.clone();
+    let method = request.method.clone();
+    let target = workspace.to_string();
+    // large trees take a while to write, don't block the runtime meanwhile
+    tokio::task::spawn_blocking(move || {
+        RepositoryManager::export_commit(&repo_path, &commit, &target)?;
+        runner::check_method(&target, &method)
+    })
+    .await
+    .unwrap_or_else(|e| Err(Error::Io(format!("Failed to export commit: {}", e))))
+}
+
+/// Removes a workspace, which does not exist for jobs cancelled before they started.
+fn remove_workspace(workspace: &str) {
+    match fs::remove_dir_all(workspace) {
+        Ok(()) => (),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+        Err(e) => tracing::warn!("failed to remove workspace {}: {}", workspace, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use chrono::TimeDelta;
+    use git2::{Repository, Signature};
+    use tokio::time;
+
+    use super::*;
+
+    fn config(dir: &Path) -> Config {
+        Config {
+            data_dir: dir.to_string_lossy().into_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Commits a build script with `content` to a repository `tool`.
+    fn repository(config: &Config, content: &str) {
+        let location = FileSystem::new(&config.data_dir).git_path("tool").unwrap();
+        let repo = Repository::init(&location).unwrap();
+        let script = "workflows/script/build_script.sh";
+        let path = Path::new(&location).join(script);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(script)).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("test", "test@example.org").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "build", &tree, &[])
+            .unwrap();
+    }
+
+    fn submit(queue: &JobQueue) -> Result<Job> {
+        queue.submit(
+            "tool",
+            "script",
+            None,
+            None,
+            BTreeMap::new(),
+            BuildOptions::default(),
+        )
+    }
+
+    fn queue(config: &Config) -> JobQueue {
+        let releases = ReleaseStore::load(&FileSystem::new(&config.data_dir)).unwrap();
+        JobQueue::new(config, Dependencies::unprobed(config), releases)
+    }
+
+    fn job(status: JobStatus) -> Job {
+        Job {
+            id: Uuid::new_v4().to_string(),
+            repository: "tool".to_string(),
+            method: "make".to_string(),
+            git_ref: Some("v1.0.0".to_string()),
+            commit: "0123456789abcdef".to_string(),
+            trigger: None,
+            release_tag: None,
+            options: BuildOptions::default(),
+            status,
+            message: None,
+            exit_code: None,
+            created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+            outputs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn jobs_are_read_back_after_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            data_dir: dir.path().to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+
+        let succeeded = job(JobStatus::Succeeded);
+        let running = job(JobStatus::Running);
+        let before = queue(&config);
+        before.persist(&succeeded);
+        before.persist(&running);
+
+        let after = queue(&config);
+        let loaded = after.get(&succeeded.id).unwrap();
+        assert_eq!(loaded.status, JobStatus::Succeeded);
+        assert_eq!(loaded.commit, succeeded.commit);
+
+        let (interrupted, log) = after.get_with_log(&running.id).unwrap();
+        assert_eq!(interrupted.status, JobStatus::Failed);
+        assert!(interrupted.message.unwrap().contains("restart"));
+        assert!(log.is_none());
+
+        assert!(after.get(&Uuid::new_v4().to_string()).is_none());
+        assert!(after.get("../jobs").is_none());
+    }
+
+    #[test]
+    fn queued_jobs_can_be_cancelled_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(dir.path());
+        repository(&config, "exit 0");
+        let queue = queue(&config);
+
+        let job = submit(&queue).unwrap();
+        assert_eq!(job.status, JobStatus::Queued);
+
+        let cancelled = queue.cancel(&job.id).unwrap();
+        assert_eq!(cancelled.status, JobStatus::Cancelled);
+        assert!(cancelled.finished_at.is_some());
+        assert!(matches!(queue.cancel(&job.id), Err(Error::Conflict(_))));
+        assert!(matches!(
+            queue.cancel(&Uuid::new_v4().to_string()),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn engine_builds_may_not_configure_the_engine() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(dir.path());
+        repository(&config, "exit 0");
+        let queue = queue(&config);
+
+        for name in ["DOCKER_HOST", "docker_config", "PATH", "https_proxy"] {
+            let env = BTreeMap::from([(name.to_string(), "value".to_string())]);
+            for method in ["docker", "cargo"] {
+                let submitted = queue.submit(
+                    "tool",
+                    method,
+                    None,
+                    None,
+                    env.clone(),
+                    BuildOptions::default(),
+                );
+                assert!(matches!(submitted, Err(Error::Invalid(_))), "{}", name);
+            }
+        }
+
+        let env = BTreeMap::from([("DOCKER_HOST".to_string(), "value".to_string())]);
+        let submitted = queue.submit("tool", "script", None, None, env, BuildOptions::default());
+        assert!(submitted.is_ok());
+    }
+
+    #[test]
+    fn full_queues_reject_jobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config(dir.path());
+        config.build.queue_size = 1;
+        repository(&config, "exit 0");
+        let queue = queue(&config);
+
+        let queued = submit(&queue).unwrap();
+        assert!(matches!(submit(&queue), Err(Error::Unavailable(_))));
+        assert_eq!(queue.lock_jobs().len(), 1);
+        assert!(queue.get(&queued.id).is_some());
+    }
+
+    #[test]
+    fn finished_jobs_are_evicted_from_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(dir.path());
+        repository(&config, "exit 0");
+        let queue = queue(&config);
+
+        let old = submit(&queue).unwrap();
+        queue.cancel(&old.id).unwrap();
+        let recent = submit(&queue).unwrap();
+        queue.cancel(&recent.id).unwrap();
+        let queued = submit(&queue).unwrap();
+
+        let long_ago = Utc::now() - TimeDelta::try_seconds(FINISHED_JOB_RETENTION_SECS).unwrap();
+        queue.lock_jobs().get_mut(&old.id).unwrap().job.finished_at = Some(long_ago);
+        queue.evict_finished();
+
+        let jobs = queue.lock_jobs();
+        assert!(!jobs.contains_key(&old.id));
+        assert!(jobs.contains_key(&recent.id));
+        assert!(jobs.contains_key(&queued.id));
+        drop(jobs);
+
+        // evicted jobs are still found on disk
+        assert_eq!(queue.get(&old.id).unwrap().status, JobStatus::Cancelled);
+        assert!(matches!(queue.cancel(&old.id), Err(Error::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn purging_waits_for_running_jobs_to_stop() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(dir.path());
+        repository(&config, "exec sleep 30");
+        let queue = queue(&config);
+        let mut events = queue.subscribe();
+        queue.start_workers(&config);
+
+        let job = submit(&queue).unwrap();
+        let running = events.recv().await.unwrap();
+        assert_eq!(running.status, JobStatus::Running);
+        let artifacts = queue.file_system.job_artifacts_path(&job.id);
+        fs::create_dir_all(&artifacts).unwrap();
+
+        let cancelled = time::timeout(Duration::from_secs(10), queue.purge_repository("tool"))
+            .await
+            .unwrap();
+
+        assert_eq!(cancelled, vec![job.id.clone()]);
+        assert_eq!(queue.get(&job.id).unwrap().status, JobStatus::Cancelled);
+        assert!(!Path::new(&artifacts).exists());
+    }
+}
@@ -0,0 +1,583 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use chrono::{SecondsFormat, Utc};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use poem_openapi::{Enum, Object, Union};
+use tokio::sync::broadcast;
+
+use crate::build::jobs::JobStatus;
+use crate::util::error::Error;
+
+/// Number of live events buffered per subscriber before it starts missing lines.
+const LIVE_BUFFER: usize = 1024;
+
+/// Most bytes of a log returned at once, larger logs have to be paged.
+pub const MAX_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Origin of a log line.
+#[derive(Debug, Enum, Clone, Copy, Eq, PartialEq)]
+#[oai(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+    /// Messages of the service itself, e.g. the commands being run.
+    Info,
+}
+
+impl LogStream {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout",
+            LogStream::Stderr => "stderr",
+            LogStream::Info => "info",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "stdout" => Some(LogStream::Stdout),
+            "stderr" => Some(LogStream::Stderr),
+            "info" => Some(LogStream::Info),
+            _ => None,
+        }
+    }
+}
+
+/// A single line of a build log.
+#[derive(Debug, Object, Clone)]
+pub struct LogLine {
+    /// Byte offset of the line in the log file, usable to resume a stream.
+    pub offset: u64,
+    /// RFC 3339 time the line was emitted.
+    pub timestamp: String,
+    pub stream: LogStream,
+    pub text: String,
+}
+
+impl LogLine {
+    /// Parses a line as written by [`JobLog::line`], without the trailing newline.
+    fn parse(offset: u64, line: &str) -> Option<Self> {
+        let (timestamp, rest) = line.split_once(" [")?;
+        let (stream, text) = rest.split_once("] ")?;
+
+        Some(LogLine {
+            offset,
+            timestamp: timestamp.to_string(),
+            stream: LogStream::parse(stream)?,
+            text: text.to_string(),
+        })
+    }
+}
+
+/// Final event of a build log.
+#[derive(Debug, Object, Clone)]
+pub struct LogFinished {
+    pub status: JobStatus,
+    /// Exit code of the last process the build ran, if it exited normally.
+    pub exit_code: Option<i32>,
+    pub message: Option<String>,
+}
+
+/// Why a live build log stream ended before the build finished.
+#[derive(Debug, Object, Clone)]
+pub struct LogError {
+    pub message: String,
+    /// Offset to resume the stream at, e.g. with `GET /jobs/:id/log`.
+    pub offset: u64,
+}
+
+/// Event of a live build log stream.
+#[derive(Debug, Union, Clone)]
+#[oai(discriminator_name = "type")]
+pub enum LogEvent {
+    #[oai(mapping = "line")]
+    Line(LogLine),
+    #[oai(mapping = "finished")]
+    Finished(LogFinished),
+    #[oai(mapping = "error")]
+    Error(LogError),
+}
+
+struct LogState {
+    file: File,
+    /// Bytes written so far, the offset of the next line.
+    written: u64,
+    exit_code: Option<i32>,
+    finished: Option<LogFinished>,
+}
+
+/// Combined log of all steps of a build job, written to a file on disk.
+///
+/// Every line is prefixed with an RFC 3339 timestamp and the stream it came from,
+/// and is also published to live subscribers.
+/// Cheap to clone, all clones append to the same file.
+#[derive(Clone)]
+pub struct JobLog {
+    state: Arc<Mutex<LogState>>,
+    sender: broadcast::Sender<LogEvent>,
+}
+
+/// Live view of a log, see [`JobLog::subscribe`].
+pub struct LogSubscription {
+    /// Size of the log file when subscribing, every later line arrives on `receiver`.
+    pub written: u64,
+    pub receiver: broadcast::Receiver<LogEvent>,
+    /// Set if the log was already finished when subscribing.
+    pub finished: Option<LogFinished>,
+}
+
+impl JobLog {
+    /// Creates (or truncates) the log file at `path`.
+    pub fn create(path: &str) -> Result<Self, Error> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| Error::Io(format!("Failed to create log directory: {}", e)))?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|e| Error::Io(format!("Failed to create log file {}: {}", path, e)))?;
+
+        let (sender, _) = broadcast::channel(LIVE_BUFFER);
+
+        Ok(JobLog {
+            state: Arc::new(Mutex::new(LogState {
+                file,
+                written: 0,
+                exit_code: None,
+                finished: None,
+            })),
+            sender,
+        })
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, LogState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Appends a line, failures are only traced so logging never fails a build.
+    pub fn line(&self, stream: LogStream, line: &str) {
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        let entry = format!("{} [{}] {}\n", timestamp, stream.as_str(), line);
+
+        // publishing under the lock keeps the file and the live stream in the same order
+        let mut state = self.lock_state();
+        if let Err(e) = state.file.write_all(entry.as_bytes()) {
+            tracing::warn!("failed to write build log: {}", e);
+            return;
+        }
+
+        let offset = state.written;
+        state.written += entry.len() as u64;
+
+        // no receivers is not an error, nobody is watching
+        let _ = self.sender.send(LogEvent::Line(LogLine {
+            offset,
+            timestamp,
+            stream,
+            text: line.to_string(),
+        }));
+    }
+
+    pub fn info(&self, line: &str) {
+        self.line(LogStream::Info, line);
+    }
+
+    /// Remembers the exit code of the process that just finished.
+    pub fn record_exit(&self, code: Option<i32>) {
+        self.lock_state().exit_code = code;
+    }
+
+    pub fn exit_code(&self) -> Option<i32> {
+        self.lock_state().exit_code
+    }
+
+    /// Marks the log as complete, which ends all live streams.
+    pub fn finish(&self, status: JobStatus, message: Option<String>) {
+        let mut state = self.lock_state();
+        let finished = LogFinished {
+            status,
+            exit_code: state.exit_code,
+            message,
+        };
+
+        state.finished = Some(finished.clone());
+        let _ = self.sender.send(LogEvent::Finished(finished));
+    }
+
+    /// Subscribes to new lines, returning how much of the log was already written.
+    pub fn subscribe(&self) -> LogSubscription {
+        let state = self.lock_state();
+
+        LogSubscription {
+            written: state.written,
+            receiver: self.sender.subscribe(),
+            finished: state.finished.clone(),
+        }
+    }
+}
+
+/// Reads the lines of the log at `path` between the byte offsets `from` and `to`.
+///
+/// A partial first line (when `from` points into a line) is skipped.
+pub fn read_lines(path: &str, from: u64, to: u64) -> io::Result<Vec<LogLine>> {
+    let mut file = File::open(path)?;
+
+    // `from` is at a line start if it is the start of the file or follows a newline
+    let mut at_line_start = from == 0;
+    if !at_line_start {
+        let mut previous = [0; 1];
+        file.seek(SeekFrom::Start(from - 1))?;
+        file.read_exact(&mut previous)?;
+        at_line_start = previous[0] == b'\n';
+    }
+    file.seek(SeekFrom::Start(from))?;
+
+    let mut reader = BufReader::new(file.take(to.saturating_sub(from)));
+    let mut lines = Vec::new();
+    let mut offset = from;
+    let mut buffer = Vec::new();
+
+    loop {
+        buffer.clear();
+        let read = reader.read_until(b'\n', &mut buffer)?;
+        if read == 0 {
+            break;
+        }
+
+        if at_line_start {
+            let line = String::from_utf8_lossy(&buffer);
+            if let Some(line) = LogLine::parse(offset, line.trim_end_matches('\n')) {
+                lines.push(line);
+            }
+        }
+        at_line_start = true;
+        offset += read as u64;
+    }
+
+    Ok(lines)
+}
+
+/// A chunk of a log file.
+pub struct LogChunk {
+    pub content: String,
+    /// Byte offset of the first byte of `content`.
+    pub start: u64,
+    /// Byte offset just after the last byte of `content`, where the next chunk starts.
+    pub end: u64,
+    /// Current size of the whole log file.
+    pub total: u64,
+}
+
+/// Reads up to `limit` bytes, at most [`MAX_CHUNK_SIZE`], of the log at `path`,
+/// starting at `offset`.
+///
+/// The range is narrowed to UTF-8 character boundaries, so paging with
+/// `end` as the next offset never splits a character.
+pub fn read_chunk(path: &str, offset: u64, limit: Option<u64>) -> io::Result<LogChunk> {
+    let mut file = File::open(path)?;
+    let total = file.metadata()?.len();
+
+    let limit = limit.unwrap_or(MAX_CHUNK_SIZE).min(MAX_CHUNK_SIZE);
+    let start = offset.min(total);
+    let end = start.saturating_add(limit).min(total);
+
+    let mut buffer = vec![0; (end - start) as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut buffer)?;
+
+    // skip continuation bytes at the start and cut a partial character at the end
+    let skip = buffer.iter().take_while(|b| is_continuation(**b)).count();
+    let mut keep = buffer.len();
+    if end < total {
+        if let Some(last_start) = buffer.iter().rposition(|b| !is_continuation(*b)) {
+            if last_start >= skip && std::str::from_utf8(&buffer[last_start..]).is_err() {
+                keep = last_start;
+            }
+        }
+    }
+    let keep = keep.max(skip);
+
+    Ok(LogChunk {
+        content: String::from_utf8_lossy(&buffer[skip..keep]).into_owned(),
+        start: start + skip as u64,
+        end: start + keep as u64,
+        total,
+    })
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+/// Parses an HTTP `Range` header of the form `bytes=start-end`, `bytes=start-`
+/// or `bytes=-suffix` into an offset and length for a file of `total` bytes.
+///
+/// Returns `None` if the range is malformed or not satisfiable.
+pub fn parse_range(header: &str, total: u64) -> Option<(u64, u64)> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    // multiple ranges are not supported
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+
+    let (start, end) = match (first.trim(), last.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            (total.saturating_sub(suffix), total.checked_sub(1)?)
+        }
+        (first, "") => (first.parse().ok()?, total.checked_sub(1)?),
+        (first, last) => (
+            first.parse().ok()?,
+            last.parse::<u64>().ok()?.min(total.checked_sub(1)?),
+        ),
+    };
+
+    if start > end || start >= total {
+        return None;
+    }
+
+    Some((start, end - start + 1))
+}
+
+/// Builds the event stream of a job log: the lines from byte offset `from` that were
+/// already written, then every new line until the job finishes, then a final
+/// [`LogEvent::Finished`].
+///
+/// Lines a slow client missed on the live channel are read back from the log file,
+/// if that fails the stream ends with a [`LogEvent::Error`].
+///
+/// `live` is the log of a job that is still queued or running, `finished` the final
+/// state of a job that already completed.
+pub fn event_stream(
+    path: &str,
+    from: u64,
+    live: Option<&JobLog>,
+    finished: Option<LogFinished>,
+) -> io::Result<BoxStream<'static, LogEvent>> {
+    let (written, receiver, finished) = match live {
+        Some(log) => {
+            let subscription = log.subscribe();
+            let finished = subscription.finished.or(finished);
+            (subscription.written, Some(subscription.receiver), finished)
+        }
+        None => (fs::metadata(path)?.len(), None, finished),
+    };
+
+    let replay: Vec<LogEvent> = read_lines(path, from, written)?
+        .into_iter()
+        .map(LogEvent::Line)
+        .collect();
+
+    // already complete, nothing left to follow
+    if let Some(finished) = finished {
+        let events = replay.into_iter().chain([LogEvent::Finished(finished)]);
+        return Ok(stream::iter(events).boxed());
+    }
+
+    let follower = LiveFollower {
+        path: path.to_string(),
+        receiver,
+        next: written,
+        lagged: false,
+        pending: VecDeque::new(),
+    };
+    let live = stream::unfold(follower, |mut follower| async move {
+        let event = follower.next_event().await?;
+        Some((event, follower))
+    });
+
+    Ok(stream::iter(replay).chain(live).boxed())
+}
+
+/// Follows the live events of a log, filling gaps from the log file.
+struct LiveFollower {
+    path: String,
+    /// `None` once the final event was received.
+    receiver: Option<broadcast::Receiver<LogEvent>>,
+    /// Offset every line before has been sent, may point into the last line sent.
+    next: u64,
+    /// Whether events were dropped since the last one received.
+    lagged: bool,
+    /// Events to send before receiving again.
+    pending: VecDeque<LogEvent>,
+}
+
+impl LiveFollower {
+    async fn next_event(&mut self) -> Option<LogEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                if let LogEvent::Line(line) = &event {
+                    // reading from inside the line skips it, as resuming after an event id does
+                    self.next = line.offset + 1;
+                }
+                return Some(event);
+            }
+
+            let event = match self.receiver.as_mut()?.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::debug!("log stream lagging behind, reading {} lines back", skipped);
+                    self.lagged = true;
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+
+            if self.lagged {
+                self.lagged = false;
+                // the missed lines end where the received one starts, or with the file
+                let to = match &event {
+                    LogEvent::Line(line) => Ok(line.offset),
+                    _ => fs::metadata(&self.path).map(|metadata| metadata.len()),
+                };
+                match to.and_then(|to| read_lines(&self.path, self.next, to)) {
+                    Ok(missed) => self.pending.extend(missed.into_iter().map(LogEvent::Line)),
+                    Err(e) => {
+                        self.receiver = None;
+                        return Some(LogEvent::Error(LogError {
+                            message: format!("Missed log lines and failed to read them: {}", e),
+                            offset: self.next,
+                        }));
+                    }
+                }
+            }
+
+            // end the stream after the final event
+            if !matches!(event, LogEvent::Line(_)) {
+                self.receiver = None;
+            }
+            self.pending.push_back(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_log() -> (tempfile::TempDir, String, JobLog) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("build.log").to_string_lossy().into_owned();
+        let log = JobLog::create(&path).unwrap();
+        (dir, path, log)
+    }
+
+    #[tokio::test]
+    async fn lagging_stream_reads_missed_lines_back() {
+        let (_dir, path, log) = temp_log();
+        log.info("replayed");
+        let events = event_stream(&path, 0, Some(&log), None).unwrap();
+
+        // overflow the live buffer before the stream is polled
+        let count = LIVE_BUFFER * 3;
+        for n in 0..count {
+            log.line(LogStream::Stdout, &format!("line {}", n));
+        }
+        log.finish(JobStatus::Succeeded, None);
+
+        let events: Vec<LogEvent> = events.collect().await;
+        let texts: Vec<String> = events
+            .iter()
+            .filter_map(|event| match event {
+                LogEvent::Line(line) => Some(line.text.clone()),
+                _ => None,
+            })
+            .collect();
+        let expected: Vec<String> = ["replayed".to_string()]
+            .into_iter()
+            .chain((0..count).map(|n| format!("line {}", n)))
+            .collect();
+        assert_eq!(texts, expected);
+        assert!(matches!(events.last(), Some(LogEvent::Finished(_))));
+    }
+
+    #[tokio::test]
+    async fn lagging_stream_ends_with_an_error_if_the_log_is_gone() {
+        let (dir, path, log) = temp_log();
+        let events = event_stream(&path, 0, Some(&log), None).unwrap();
+
+        for n in 0..LIVE_BUFFER * 2 {
+            log.info(&format!("line {}", n));
+        }
+        log.finish(JobStatus::Succeeded, None);
+        drop(dir);
+
+        let events: Vec<LogEvent> = events.collect().await;
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], LogEvent::Error(error) if error.offset == 0));
+    }
+
+    #[test]
+    fn chunk_is_capped_without_a_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("build.log");
+        fs::write(&path, vec![b'x'; MAX_CHUNK_SIZE as usize + 10]).unwrap();
+        let path = path.to_string_lossy();
+
+        let chunk = read_chunk(&path, 0, None).unwrap();
+        assert_eq!(chunk.end, MAX_CHUNK_SIZE);
+        assert_eq!(chunk.total, MAX_CHUNK_SIZE + 10);
+
+        let chunk = read_chunk(&path, 0, Some(u64::MAX)).unwrap();
+        assert_eq!(chunk.end, MAX_CHUNK_SIZE);
+
+        let chunk = read_chunk(&path, chunk.end, None).unwrap();
+        assert_eq!(chunk.content.len(), 10);
+    }
+
+    #[test]
+    fn chunks_never_split_characters() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("build.log");
+        // 'ä' and '€' take two and three bytes
+        fs::write(&path, "aä€b").unwrap();
+        let path = path.to_string_lossy();
+
+        // ends inside 'ä', which is left for the next chunk
+        let chunk = read_chunk(&path, 0, Some(2)).unwrap();
+        assert_eq!(
+            (chunk.content.as_str(), chunk.start, chunk.end),
+            ("a", 0, 1)
+        );
+
+        // starts inside 'ä' and ends inside '€'
+        let chunk = read_chunk(&path, 2, Some(3)).unwrap();
+        assert_eq!((chunk.content.as_str(), chunk.start, chunk.end), ("", 3, 3));
+
+        let mut offset = 0;
+        let mut content = String::new();
+        while offset < 7 {
+            let chunk = read_chunk(&path, offset, Some(4)).unwrap();
+            assert!(chunk.end > offset);
+            content.push_str(&chunk.content);
+            offset = chunk.end;
+        }
+        assert_eq!(content, "aä€b");
+    }
+
+    #[test]
+    fn byte_ranges_are_parsed() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 100)));
+        assert_eq!(parse_range("bytes=900-", 1000), Some((900, 100)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some((900, 100)));
+        assert_eq!(parse_range("bytes=990-2000", 1000), Some((990, 10)));
+        assert_eq!(parse_range("bytes=-2000", 1000), Some((0, 1000)));
+
+        assert_eq!(parse_range("bytes=1000-", 1000), None);
+        assert_eq!(parse_range("bytes=5-1", 1000), None);
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+        assert_eq!(parse_range("bytes=0-", 0), None);
+    }
+}
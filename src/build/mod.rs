@@ -1,3 +1,11 @@
+pub mod artifacts;
+pub mod cargo;
+pub mod digests;
 pub mod docker;
+pub mod jobs;
+pub mod log;
 pub mod make;
-pub mod script;
+pub mod process;
+pub mod releases;
+pub mod runner;
+pub mod triggers;
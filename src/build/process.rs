@@ -0,0 +1,138 @@
+use std::process::{ExitStatus, Stdio};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::Command,
+};
+
+use crate::build::log::{JobLog, LogStream};
+use crate::util::error::{Error, Result};
+
+/// Runs `cmd` to completion, streaming its stdout and stderr line by line into `log`.
+///
+/// The process is killed if the returned future is dropped. On unix it runs in its
+/// own process group, so everything it started is killed along with it.
+pub async fn run_logged(cmd: &mut Command, log: &JobLog) -> Result<ExitStatus> {
+    log.info(&format!("$ {}", describe(cmd)));
+
+    #[cfg(unix)]
+    // SAFETY: setpgid is async-signal-safe, so it may run between fork and exec
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setpgid(0, 0) == 0 {
+                Ok(())
+            } else {
+                Err(std::io::Error::last_os_error())
+            }
+        });
+    }
+
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Error::ToolMissing(format!(
+                "{} is not installed",
+                cmd.as_std().get_program().to_string_lossy()
+            )),
+            _ => Error::BuildFailed(format!("Failed to execute {}: {}", describe(cmd), e)),
+        })?;
+
+    let mut group = ProcessGroupGuard::new(child.id());
+
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+
+    let (_, _, status) = tokio::join!(
+        forward_lines(stdout, LogStream::Stdout, log),
+        forward_lines(stderr, LogStream::Stderr, log),
+        child.wait(),
+    );
+    group.disarm();
+
+    let status = status
+        .map_err(|e| Error::BuildFailed(format!("Failed to wait for {}: {}", describe(cmd), e)))?;
+    log.record_exit(status.code());
+    log.info(&format!("exited with {}", status));
+
+    Ok(status)
+}
+
+/// Kills the process group of a build step if it is dropped before the step finished.
+struct ProcessGroupGuard {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pgid: Option<u32>,
+}
+
+impl ProcessGroupGuard {
+    fn new(pid: Option<u32>) -> Self {
+        ProcessGroupGuard { pgid: pid }
+    }
+
+    fn disarm(&mut self) {
+        self.pgid = None;
+    }
+}
+
+impl Drop for ProcessGroupGuard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(pgid) = self.pgid.and_then(|pgid| i32::try_from(pgid).ok()) {
+            // SAFETY: kill has no memory safety requirements, a stale group id only yields ESRCH
+            unsafe {
+                libc::kill(-pgid, libc::SIGKILL);
+            }
+        }
+    }
+}
+
+/// Like [`run_logged`], but fails unless the process exits successfully.
+pub async fn run_checked(cmd: &mut Command, log: &JobLog) -> Result<()> {
+    let status = run_logged(cmd, log).await?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::BuildFailed(format!(
+            "{} exited with {}",
+            describe(cmd),
+            status
+        )))
+    }
+}
+
+async fn forward_lines(reader: Option<impl AsyncRead + Unpin>, stream: LogStream, log: &JobLog) {
+    let reader = match reader {
+        Some(reader) => reader,
+        None => return,
+    };
+
+    // split on raw bytes, build tools don't always print valid UTF-8
+    let mut lines = BufReader::new(reader).split(b'\n');
+    loop {
+        match lines.next_segment().await {
+            Ok(Some(line)) => {
+                let line = String::from_utf8_lossy(&line);
+                log.line(stream, line.trim_end_matches('\r'));
+            }
+            Ok(None) => break,
+            Err(e) => {
+                log.info(&format!("failed to read {} output: {}", stream.as_str(), e));
+                break;
+            }
+        }
+    }
+}
+
+/// Renders the program and arguments of a command for logs and errors.
+fn describe(cmd: &Command) -> String {
+    let cmd = cmd.as_std();
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
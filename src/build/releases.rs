@@ -0,0 +1,640 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+
+use crate::build::digests::{self, BuildOutput, DigestAlgorithm, Digests};
+use crate::build::jobs::{Job, JobStatus};
+use crate::git::tags;
+use crate::util::error::{Error, Result};
+use crate::util::file_system::{write_atomic, FileSystem};
+use crate::util::signing::{is_signature_file, SigningKey, SigningKeys, SIGNATURE_EXTENSION};
+
+/// Lifecycle state of a release.
+#[derive(Debug, Enum, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[oai(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ReleaseStatus {
+    /// The tag is being built.
+    Building,
+    /// The build succeeded and its artifacts can be downloaded.
+    Published,
+    /// The build failed or was cancelled, building the tag again retries it.
+    Failed,
+}
+
+/// A file attached to a release.
+#[derive(Debug, Object, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct ReleaseArtifact {
+    /// File name, nested build outputs are named after their path with `/` replaced by `-`.
+    pub name: String,
+    /// Size in bytes.
+    pub size: u64,
+    pub content_type: String,
+    /// Digests of the file, unset for releases published before they were recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digests: Option<Digests>,
+    /// Name of the artifact holding the file's minisign signature, if it was signed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// A tag of a repository built by the service.
+#[derive(Debug, Object, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct Release {
+    pub repository: String,
+    pub tag: String,
+    /// SHA of the commit the tag points at.
+    pub commit: String,
+    /// Release notes, the message of an annotated tag.
+    pub notes: Option<String>,
+    pub status: ReleaseStatus,
+    /// Job that built the release.
+    pub job_id: String,
+    /// Why the release failed.
+    pub message: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub published_at: Option<DateTime<Utc>>,
+    pub artifacts: Vec<ReleaseArtifact>,
+}
+
+/// Releases of all repositories, persisted as JSON, with their artifacts kept
+/// in a directory per release.
+///
+/// A release is opened when a tag is queued for a build and published with the
+/// artifacts of the build once it succeeded. Published releases are immutable.
+/// The handle is cheap to clone.
+#[derive(Clone)]
+pub struct ReleaseStore {
+    file_system: FileSystem,
+    /// Releases by repository, then by tag.
+    releases: Arc<Mutex<BTreeMap<String, BTreeMap<String, Release>>>>,
+}
+
+impl ReleaseStore {
+    /// Loads the releases, starting without releases if none were stored yet.
+    ///
+    /// Jobs don't survive restarts, so releases that were being built are marked failed.
+    pub fn load(file_system: &FileSystem) -> Result<Self> {
+        let path = file_system.releases_path();
+        let mut releases: BTreeMap<String, BTreeMap<String, Release>> =
+            match fs::read_to_string(&path) {
+                Ok(content) => serde_json::from_str(&content)
+                    .map_err(|e| Error::Io(format!("Failed to parse releases {}: {}", path, e)))?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+                Err(e) => {
+                    return Err(Error::Io(format!(
+                        "Failed to read releases {}: {}",
+                        path, e
+                    )))
+                }
+            };
+
+        for release in releases
+            .values_mut()
+            .flat_map(|releases| releases.values_mut())
+        {
+            if release.status == ReleaseStatus::Building {
+                release.status = ReleaseStatus::Failed;
+                release.message = Some("Build was interrupted by a restart".to_string());
+            }
+        }
+
+        Ok(ReleaseStore {
+            file_system: file_system.clone(),
+            releases: Arc::new(Mutex::new(releases)),
+        })
+    }
+
+    fn lock_releases(&self) -> MutexGuard<'_, BTreeMap<String, BTreeMap<String, Release>>> {
+        self.releases.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Releases of a repository, the newest first.
+    pub fn list(&self, repository: &str) -> Vec<Release> {
+        let mut releases: Vec<Release> = self
+            .lock_releases()
+            .get(repository)
+            .map(|releases| releases.values().cloned().collect())
+            .unwrap_or_default();
+        releases.sort_by_key(|release| std::cmp::Reverse(release.created_at));
+        releases
+    }
+
+    pub fn get(&self, repository: &str, tag: &str) -> Result<Release> {
+        self.lock_releases()
+            .get(repository)
+            .and_then(|releases| releases.get(tag))
+            .cloned()
+            .ok_or_else(|| Error::NotFound(format!("Release not found ({} {})", repository, tag)))
+    }
+
+    /// The published release with the highest version, or the one published last
+    /// if no tag is a semantic version.
+    pub fn latest(&self, repository: &str) -> Result<Release> {
+        let releases = self.lock_releases();
+        let published: Vec<&Release> = releases
+            .get(repository)
+            .map(|releases| {
+                releases
+                    .values()
+                    .filter(|release| release.status == ReleaseStatus::Published)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let latest = published
+            .iter()
+            .filter_map(|release| tags::parse_version(&release.tag).map(|v| (v, release)))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, release)| *release)
+            .or_else(|| published.iter().copied().max_by_key(|r| r.published_at));
+
+        latest
+            .cloned()
+            .ok_or_else(|| Error::NotFound(format!("No published release ({})", repository)))
+    }
+
+    /// An artifact of a release and the path of its file.
+    pub fn artifact(
+        &self,
+        repository: &str,
+        tag: &str,
+        name: &str,
+    ) -> Result<(ReleaseArtifact, String)> {
+        let release = self.get(repository, tag)?;
+        let artifact = release
+            .artifacts
+            .into_iter()
+            .find(|artifact| artifact.name == name)
+            .ok_or_else(|| {
+                Error::NotFound(format!(
+                    "Artifact not found ({} {} {})",
+                    repository, tag, name
+                ))
+            })?;
+
+        let path = format!(
+            "{}/{}",
+            self.file_system.release_path(repository, tag)?,
+            artifact.name
+        );
+        Ok((artifact, path))
+    }
+
+    /// Opens the release of the tag `job` builds, replacing a failed one.
+    ///
+    /// Fails if the release is already published or another job is building it.
+    pub fn begin(&self, job: &Job, notes: Option<String>) -> Result<()> {
+        let tag = match &job.release_tag {
+            Some(tag) => tag,
+            None => return Ok(()),
+        };
+
+        let mut releases = self.lock_releases();
+        let previous = releases
+            .get(&job.repository)
+            .and_then(|releases| releases.get(tag));
+        match previous {
+            Some(release) if release.status == ReleaseStatus::Published => {
+                return Err(Error::Conflict(format!(
+                    "Release {} of {} is already published",
+                    tag, job.repository
+                )))
+            }
+            Some(release) if release.status == ReleaseStatus::Building => {
+                return Err(Error::Conflict(format!(
+                    "Release {} of {} is already being built by job {}",
+                    tag, job.repository, release.job_id
+                )))
+            }
+            _ => (),
+        }
+
+        let release = Release {
+            repository: job.repository.clone(),
+            tag: tag.clone(),
+            commit: job.commit.clone(),
+            notes,
+            status: ReleaseStatus::Building,
+            job_id: job.id.clone(),
+            message: None,
+            created_at: Utc::now(),
+            published_at: None,
+            artifacts: Vec::new(),
+        };
+        self.update(&mut releases, release)
+    }
+
+    /// Publishes the release `job` built with the artifacts in `artifacts_dir`, or marks it
+    /// failed if the job did not succeed.
+    ///
+    /// The artifacts keep the digests and signatures of the job's outputs, checksum files
+    /// listing them under their release names are added and signed with the current key.
+    pub async fn finish(&self, job: &Job, artifacts_dir: &str, algorithms: &[DigestAlgorithm]) {
+        let tag = match &job.release_tag {
+            Some(tag) => tag.clone(),
+            None => return,
+        };
+
+        let outcome = match job.status {
+            JobStatus::Succeeded => {
+                let source = artifacts_dir.to_string();
+                let target = self.file_system.release_path(&job.repository, &tag);
+                let outputs = job.outputs.clone();
+                let algorithms = algorithms.to_vec();
+                let signing_keys = SigningKeys::new(&self.file_system);
+                // artifacts may be large, don't block the runtime while copying them
+                tokio::task::spawn_blocking(move || {
+                    let target = target?;
+                    let signing_key = signing_keys.current()?;
+                    store_artifacts(
+                        &source,
+                        &target,
+                        &outputs,
+                        &algorithms,
+                        signing_key.as_ref(),
+                    )
+                })
+                .await
+                .unwrap_or_else(|e| Err(Error::Io(format!("Failed to copy artifacts: {}", e))))
+                .map_err(|err| err.to_string())
+            }
+            _ => Err(job
+                .message
+                .clone()
+                .unwrap_or_else(|| "Build did not succeed".to_string())),
+        };
+
+        self.settle(job, &tag, outcome);
+    }
+
+    /// Marks the release `job` was queued for failed, e.g. when the job could not be queued.
+    pub fn abandon(&self, job: &Job, reason: &str) {
+        if let Some(tag) = &job.release_tag {
+            self.settle(job, tag, Err(reason.to_string()));
+        }
+    }
+
+    fn settle(
+        &self,
+        job: &Job,
+        tag: &str,
+        outcome: std::result::Result<Vec<ReleaseArtifact>, String>,
+    ) {
+        let mut releases = self.lock_releases();
+        let mut release = match releases
+            .get(&job.repository)
+            .and_then(|releases| releases.get(tag))
+        {
+            // the release was removed meanwhile, or is built by another job
+            Some(release) if release.job_id == job.id => release.clone(),
+            _ => return,
+        };
+
+        match outcome {
+            Ok(artifacts) => {
+                release.status = ReleaseStatus::Published;
+                release.published_at = Some(Utc::now());
+                release.artifacts = artifacts;
+                tracing::info!(
+                    "published release {} of {} ({} artifacts)",
+                    tag,
+                    job.repository,
+                    release.artifacts.len()
+                );
+            }
+            Err(message) => {
+                release.status = ReleaseStatus::Failed;
+                release.message = Some(message);
+            }
+        }
+
+        if let Err(e) = self.update(&mut releases, release) {
+            tracing::error!(
+                "failed to record release {} of {}: {}",
+                tag,
+                job.repository,
+                e
+            );
+        }
+    }
+
+    /// Removes the releases of a repository together with their artifacts.
+    pub fn remove_repository(&self, repository: &str) -> Result<()> {
+        let path = self.file_system.repository_releases_path(repository)?;
+        let mut releases = self.lock_releases();
+        if let Some(removed) = releases.remove(repository) {
+            if let Err(err) = self.save(&releases) {
+                releases.insert(repository.to_string(), removed);
+                return Err(err);
+            }
+        }
+
+        match fs::remove_dir_all(&path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::Io(format!(
+                "Failed to delete releases {}: {}",
+                path, e
+            ))),
+        }
+    }
+
+    fn update(
+        &self,
+        releases: &mut BTreeMap<String, BTreeMap<String, Release>>,
+        release: Release,
+    ) -> Result<()> {
+        let previous = releases
+            .entry(release.repository.clone())
+            .or_default()
+            .insert(release.tag.clone(), release.clone());
+
+        if let Err(err) = self.save(releases) {
+            let of_repository = releases.entry(release.repository.clone()).or_default();
+            match previous {
+                Some(previous) => of_repository.insert(release.tag, previous),
+                None => of_repository.remove(&release.tag),
+            };
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Persists the releases of all repositories.
+    fn save(&self, releases: &BTreeMap<String, BTreeMap<String, Release>>) -> Result<()> {
+        let path = self.file_system.releases_path();
+        let content = serde_json::to_string_pretty(releases)
+            .map_err(|e| Error::Io(format!("Failed to serialize releases: {}", e)))?;
+
+        write_atomic(&path, content.as_bytes(), false)
+            .map_err(|e| Error::Io(format!("Failed to write releases {}: {}", path, e)))
+    }
+}
+
+/// Copies every file below `source` into `target`, replacing what was there before,
+/// and describes the copies.
+///
+/// Files in subdirectories are named by their path with `-` between the parts,
+/// fails if two files end up with the same name.
+///
+/// Digests are taken from `outputs` and computed with `algorithms` for files missing there.
+/// The checksum files of the job are replaced by ones listing the release names,
+/// signed with `signing_key`.
+fn store_artifacts(
+    source: &str,
+    target: &str,
+    outputs: &[BuildOutput],
+    algorithms: &[DigestAlgorithm],
+    signing_key: Option<&SigningKey>,
+) -> Result<Vec<ReleaseArtifact>> {
+    let io_error = |e: io::Error| Error::Io(format!("Failed to store artifacts: {}", e));
+
+    let mut files = Vec::new();
+    if Path::new(source).exists() {
+        collect_files(Path::new(source), "", &mut files).map_err(io_error)?;
+    }
+    // the job's checksum files list the outputs by path, new ones are written below
+    files.retain(|(name, _)| {
+        let signed = name.strip_suffix(SIGNATURE_EXTENSION).unwrap_or(name);
+        !digests::is_checksum_file(signed)
+    });
+    files.sort();
+
+    // `a/b` and `a-b` both become `a-b`, one would silently replace the other
+    if let Some(pair) = files.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        let relative = |path: &Path| {
+            path.strip_prefix(source)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .into_owned()
+        };
+        return Err(Error::BuildFailed(format!(
+            "Artifacts {} and {} have the same release name {}",
+            relative(&pair[0].1),
+            relative(&pair[1].1),
+            pair[0].0
+        )));
+    }
+
+    match fs::remove_dir_all(target) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(io_error(e)),
+    }
+    fs::create_dir_all(target).map_err(io_error)?;
+
+    let names: Vec<String> = files.iter().map(|(name, _)| name.clone()).collect();
+    let mut artifacts = Vec::new();
+    for (name, path) in files {
+        let output = outputs
+            .iter()
+            .find(|output| Path::new(source).join(&output.name) == path);
+        let digests = match output {
+            Some(output) => output.digests.clone(),
+            None => digests::digest_file(&path, algorithms).map_err(io_error)?,
+        };
+        let signature = format!("{}{}", name, SIGNATURE_EXTENSION);
+
+        let size = fs::copy(&path, Path::new(target).join(&name)).map_err(io_error)?;
+        artifacts.push(ReleaseArtifact {
+            content_type: content_type(&name),
+            signature: names.contains(&signature).then_some(signature),
+            name,
+            size,
+            digests: Some(digests),
+        });
+    }
+
+    // signatures are checked with the public key, checksums are no use for them
+    let entries: Vec<(&str, &Digests)> = artifacts
+        .iter()
+        .filter(|artifact| !is_signature_file(&artifact.name))
+        .filter_map(|artifact| Some((artifact.name.as_str(), artifact.digests.as_ref()?)))
+        .collect();
+    let checksum_files = digests::write_checksums(Path::new(target), &entries).map_err(io_error)?;
+
+    for name in checksum_files {
+        let signature = match signing_key {
+            Some(signing_key) => Some(
+                signing_key
+                    .write_signature(&Path::new(target).join(name))
+                    .map_err(io_error)?,
+            ),
+            None => None,
+        };
+        let mut files = vec![(name.to_string(), signature.clone())];
+        files.extend(signature.map(|signature| (signature, None)));
+
+        for (name, signature) in files {
+            let path = Path::new(target).join(&name);
+            artifacts.push(ReleaseArtifact {
+                size: fs::metadata(&path).map_err(io_error)?.len(),
+                content_type: content_type(&name),
+                digests: Some(digests::digest_file(&path, algorithms).map_err(io_error)?),
+                signature,
+                name,
+            });
+        }
+    }
+    Ok(artifacts)
+}
+
+/// Content type of an artifact, checksum and signature files are text.
+fn content_type(name: &str) -> String {
+    if digests::is_checksum_file(name) || is_signature_file(name) {
+        return "text/plain".to_string();
+    }
+    mime_guess::from_path(name)
+        .first_or_octet_stream()
+        .to_string()
+}
+
+/// Collects the files below `dir` with the names they get as artifacts.
+fn collect_files(
+    dir: &Path,
+    prefix: &str,
+    files: &mut Vec<(String, std::path::PathBuf)>,
+) -> io::Result<()> {
+    for item in fs::read_dir(dir)? {
+        let item = item?;
+        let name = format!("{}{}", prefix, item.file_name().to_string_lossy());
+        let file_type = item.file_type()?;
+        if file_type.is_dir() {
+            collect_files(&item.path(), &format!("{}-", name), files)?;
+        } else if file_type.is_file() {
+            files.push((name, item.path()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str) {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, name).unwrap();
+    }
+
+    fn release(tag: &str, status: ReleaseStatus, published_secs_ago: i64) -> Release {
+        let published_at = Utc::now() - chrono::TimeDelta::try_seconds(published_secs_ago).unwrap();
+        Release {
+            repository: "tool".to_string(),
+            tag: tag.to_string(),
+            commit: "0123456789abcdef".to_string(),
+            notes: None,
+            status,
+            job_id: tag.to_string(),
+            message: None,
+            created_at: published_at,
+            published_at: (status == ReleaseStatus::Published).then_some(published_at),
+            artifacts: Vec::new(),
+        }
+    }
+
+    /// A store holding `releases` of the repository `tool`.
+    fn store(dir: &Path, releases: Vec<Release>) -> ReleaseStore {
+        let file_system = FileSystem::new(&dir.to_string_lossy());
+        let releases: BTreeMap<String, BTreeMap<String, Release>> = BTreeMap::from([(
+            "tool".to_string(),
+            releases
+                .into_iter()
+                .map(|release| (release.tag.clone(), release))
+                .collect(),
+        )]);
+        fs::write(
+            file_system.releases_path(),
+            serde_json::to_string(&releases).unwrap(),
+        )
+        .unwrap();
+        ReleaseStore::load(&file_system).unwrap()
+    }
+
+    #[test]
+    fn latest_release_is_the_highest_published_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(
+            dir.path(),
+            vec![
+                release("v1.2.0", ReleaseStatus::Published, 10),
+                release("v1.10.0", ReleaseStatus::Published, 30),
+                release("v1.9.3", ReleaseStatus::Published, 20),
+                release("v2.0.0", ReleaseStatus::Failed, 0),
+                release("nightly", ReleaseStatus::Published, 0),
+            ],
+        );
+
+        assert_eq!(store.latest("tool").unwrap().tag, "v1.10.0");
+        assert!(matches!(store.latest("other"), Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn latest_release_without_versions_is_the_last_published() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(
+            dir.path(),
+            vec![
+                release("nightly-b", ReleaseStatus::Published, 20),
+                release("nightly-a", ReleaseStatus::Published, 10),
+                release("nightly-c", ReleaseStatus::Building, 0),
+            ],
+        );
+
+        assert_eq!(store.latest("tool").unwrap().tag, "nightly-a");
+    }
+
+    #[test]
+    fn artifacts_in_subdirectories_are_flattened() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write(source.path(), "dist/tool.tar.gz");
+        write(source.path(), "tool");
+        let target = target.path().join("v1.0.0");
+
+        let artifacts = store_artifacts(
+            &source.path().to_string_lossy(),
+            &target.to_string_lossy(),
+            &[],
+            &[],
+            None,
+        )
+        .unwrap();
+
+        let names: Vec<&str> = artifacts.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, ["dist-tool.tar.gz", "tool", "SHA256SUMS"]);
+        assert_eq!(
+            fs::read_to_string(target.join("dist-tool.tar.gz")).unwrap(),
+            "dist/tool.tar.gz"
+        );
+    }
+
+    #[test]
+    fn artifacts_flattened_to_the_same_name_fail_the_release() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        write(source.path(), "a/b");
+        write(source.path(), "a-b");
+
+        let err = store_artifacts(
+            &source.path().to_string_lossy(),
+            &target.path().join("v1.0.0").to_string_lossy(),
+            &[],
+            &[],
+            None,
+        )
+        .unwrap_err();
+
+        assert!(matches!(err, Error::BuildFailed(_)), "{:?}", err);
+        assert!(err.to_string().contains("a-b"));
+    }
+}
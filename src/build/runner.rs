@@ -0,0 +1,306 @@
+use std::{collections::BTreeMap, path::Path};
+
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::build::artifacts::ArtifactManifest;
+use crate::build::docker::{self, image_reference, DockerManager};
+use crate::build::jobs::BuildRequest;
+use crate::build::log::JobLog;
+use crate::build::{cargo, make, process};
+use crate::util::config::Config;
+use crate::util::error::{Error, Result};
+use crate::util::file_system::FileSystem;
+use crate::util::workflows::{workflows_exist, WorkflowScripts};
+
+/// Build methods accepted by the build endpoint.
+pub const BUILD_METHODS: [&str; 4] = ["make", "script", "cargo", "docker"];
+
+/// Image label listing paths (comma separated) to copy out of a built image.
+pub const ARTIFACTS_LABEL: &str = "release_workflows.artifacts";
+
+/// Settings of a single build, on top of what the repository declares.
+#[derive(Debug, Object, Serialize, Deserialize, Clone, Default, Eq, PartialEq)]
+pub struct BuildOptions {
+    /// Make target to build, the Makefile's default target if not set.
+    pub make_target: Option<String>,
+    /// Cargo features to enable in addition to the ones in the repository's metadata.
+    #[oai(default)]
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// Target triples to build for with cargo, instead of the ones in the repository's metadata.
+    #[oai(default)]
+    #[serde(default)]
+    pub targets: Vec<String>,
+    /// Build the docker image without using cached layers.
+    #[oai(default)]
+    #[serde(default)]
+    pub no_cache: bool,
+}
+
+impl BuildOptions {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(target) = &self.make_target {
+            if target.is_empty() || target.starts_with('-') || !target.chars().all(is_safe_char) {
+                return Err(Error::Invalid(format!("Invalid make target {:?}", target)));
+            }
+        }
+
+        // features and targets end up in a shell script, only allow what cargo accepts anyway
+        for value in self.features.iter().chain(&self.targets) {
+            if value.is_empty() || !value.chars().all(is_safe_char) {
+                return Err(Error::Invalid(format!(
+                    "Invalid feature or target {:?}",
+                    value
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Characters allowed in make targets, cargo features and target triples.
+pub fn is_safe_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/' | '+')
+}
+
+/// Checks that every name of the build environment is a valid variable name
+/// and, for builds run by the container engine, leaves the engine's own configuration alone.
+pub fn validate_env(method: &str, env: &BTreeMap<String, String>) -> Result<()> {
+    for name in env.keys() {
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(Error::Invalid(format!(
+                "Invalid environment variable name {:?}",
+                name
+            )));
+        }
+    }
+    if matches!(method, "cargo" | "docker") {
+        docker::check_env(env)?;
+    }
+    Ok(())
+}
+
+/// Checks that `method` is valid and that the repository at `repo_path` provides
+/// the files it needs, before the build starts running anything.
+pub fn check_method(repo_path: &str, method: &str) -> Result<()> {
+    // Validate the method
+    if !BUILD_METHODS.contains(&method) {
+        return Err(Error::Invalid(format!("Invalid build method: {}", method)));
+    }
+
+    // Check if the repository has the required build scripts
+    let script_data =
+        workflows_exist(repo_path).map_err(|e| e.context("Failed to get build scripts"))?;
+
+    // A broken manifest would only fail the build after it ran
+    if script_data.has_artifacts_manifest() {
+        ArtifactManifest::load(repo_path)?;
+    }
+
+    // Check if the specified method is available
+    match method {
+        "make" if !script_data.has_makefile() => Err(Error::Invalid(
+            "Makefile not found in the repository".to_string(),
+        )),
+        "script" if !script_data.has_script() => Err(Error::Invalid(
+            "Build script not found in the repository".to_string(),
+        )),
+        "cargo" if !script_data.has_cargo_toml() => Err(Error::Invalid(
+            "Cargo toml not found in the repository".to_string(),
+        )),
+        "docker" if !script_data.has_dockerfile() => Err(Error::Invalid(
+            "Dockerfile not found in the repository".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Builds the commit exported to `workspace` with the requested method.
+///
+/// Child processes are killed when the returned future is dropped,
+/// which is how running jobs are cancelled.
+pub async fn run_build(
+    config: &Config,
+    id: &str,
+    workspace: &str,
+    request: &BuildRequest,
+    log: &JobLog,
+) -> Result<String> {
+    let name = request.repository.as_str();
+    let method = request.method.as_str();
+    let commit = &request.commit;
+
+    log.info(&format!(
+        "building {} at {} with {}",
+        name, commit.commit, method
+    ));
+
+    let manifest = ArtifactManifest::load(workspace)?;
+    let artifacts_dir = FileSystem::new(&config.data_dir).job_artifacts_path(id);
+
+    // Execute the build process based on the method
+    match method {
+        "cargo" => {
+            cargo::execute_cargo(
+                config,
+                id,
+                workspace,
+                &artifacts_dir,
+                manifest.as_ref(),
+                request,
+                log,
+            )
+            .await
+            .map_err(|e| e.context("Cargo build failed"))?;
+        }
+        "make" => {
+            make::execute_makefile(
+                workspace,
+                &request.env,
+                request.options.make_target.as_deref(),
+                log,
+            )
+            .await
+            .map_err(|e| e.context("Make build failed"))?;
+            collect_from_workspace(manifest.as_ref(), workspace, &artifacts_dir, log)?;
+        }
+        "script" => {
+            // the script runs from the repository root
+            process::run_checked(
+                Command::new("sh")
+                    .arg(WorkflowScripts::get_script_path("."))
+                    .current_dir(workspace)
+                    .envs(&request.env),
+                log,
+            )
+            .await
+            .map_err(|e| e.context("Script build failed"))?;
+            collect_from_workspace(manifest.as_ref(), workspace, &artifacts_dir, log)?;
+        }
+        "docker" => {
+            let dockerfile = WorkflowScripts::find_dockerfile(workspace).ok_or_else(|| {
+                Error::Invalid("Dockerfile not found in the repository".to_string())
+            })?;
+
+            // tag the image with the commit and every tag pointing at it
+            let image = image_reference(name, &commit.commit[..12]);
+            let extra_tags: Vec<String> = commit
+                .tags
+                .iter()
+                .map(|tag| image_reference(name, tag))
+                .collect();
+
+            let container_name = format!("docker_build_{}", id);
+            let docker_manager = DockerManager::new(&config.docker.binary, &image, &container_name)
+                .with_env(request.env.clone());
+
+            docker_manager
+                .build_image_from_context(
+                    &format!("{}/{}", workspace, dockerfile),
+                    workspace,
+                    &extra_tags,
+                    request.options.no_cache,
+                    log,
+                )
+                .await
+                .map_err(|e| e.context("Docker build failed"))?;
+            log.info(&format!("built image {} {}", image, extra_tags.join(" ")));
+
+            extract_image_artifacts(&docker_manager, &artifacts_dir, manifest.as_ref(), log)
+                .await
+                .map_err(|e| e.context("Docker build failed"))?;
+        }
+        _ => return Err(Error::Invalid("Invalid build method specified".to_string())),
+    }
+
+    Ok(format!(
+        "Build successful for commit {} ({})",
+        &commit.commit[..12],
+        name
+    ))
+}
+
+/// Collects the artifacts declared by the repository's manifest from a host build,
+/// host builds have none without one.
+fn collect_from_workspace(
+    manifest: Option<&ArtifactManifest>,
+    workspace: &str,
+    artifacts_dir: &str,
+    log: &JobLog,
+) -> Result<()> {
+    match manifest {
+        Some(manifest) => manifest
+            .collect(Path::new(workspace), artifacts_dir, log)
+            .map_err(|e| e.context("Failed to collect artifacts")),
+        None => Ok(()),
+    }
+}
+
+/// Copies the artifacts declared by the repository's manifest, relative to the image's
+/// working directory, or else the paths in the image's [`ARTIFACTS_LABEL`] to `artifacts_dir`.
+async fn extract_image_artifacts(
+    docker_manager: &DockerManager,
+    artifacts_dir: &str,
+    manifest: Option<&ArtifactManifest>,
+    log: &JobLog,
+) -> Result<()> {
+    if let Some(manifest) = manifest {
+        let working_dir = docker_manager.image_working_dir().await?;
+
+        // the command is never run, it only keeps images without one creatable
+        let container = docker_manager.create_container(&[], &["true"], log).await?;
+        let result = manifest
+            .collect_from_container(docker_manager, &working_dir, artifacts_dir, log)
+            .await
+            .map_err(|e| e.context("Failed to collect artifacts"));
+
+        if let Err(err) = container.remove().await {
+            log.info(&format!("failed to remove container: {}", err));
+        }
+        return result;
+    }
+
+    let labels = docker_manager.image_labels().await?;
+    let paths: Vec<&str> = match labels.get(ARTIFACTS_LABEL) {
+        Some(paths) => paths
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .collect(),
+        None => return Ok(()),
+    };
+
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(artifacts_dir)
+        .map_err(|e| Error::Io(format!("Failed to create artifacts directory: {}", e)))?;
+
+    // the command is never run, it only keeps images without one creatable
+    let container = docker_manager.create_container(&[], &["true"], log).await?;
+
+    let mut result = Ok(());
+    for path in paths {
+        log.info(&format!("extracting {}", path));
+        if let Err(err) = docker_manager
+            .copy_from_container(path, artifacts_dir)
+            .await
+        {
+            result = Err(err);
+            break;
+        }
+    }
+
+    // the container was only needed for copying, don't leave it behind
+    if let Err(err) = container.remove().await {
+        log.info(&format!("failed to remove container: {}", err));
+    }
+
+    result
+}
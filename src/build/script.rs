@@ -1,4 +0,0 @@
-pub async fn generate_script(repo_name: &str) -> String {
-    // TODO: Generate script content
-    format!("{}.sh content", repo_name)
-}
@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+
+use crate::build::jobs::JobQueue;
+use crate::build::runner::{BuildOptions, BUILD_METHODS};
+use crate::git::manager::SyncReport;
+use crate::git::registry::RepositoryEntry;
+use crate::git::tags;
+use crate::util::error::{Error, Result};
+
+const BRANCH_PREFIX: &str = "refs/remotes/origin/";
+const TAG_PREFIX: &str = "refs/tags/";
+
+/// Kind of ref a trigger watches.
+#[derive(Debug, Enum, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[oai(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum TriggerKind {
+    /// Builds every new commit of a matching branch.
+    Branch,
+    /// Builds every new matching tag, e.g. for releases.
+    Tag,
+}
+
+/// Rule starting a build when a sync discovers a matching ref.
+#[derive(Debug, Object, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct BuildTrigger {
+    pub kind: TriggerKind,
+    /// Glob the branch or tag name has to match, e.g. `main` or `v*`.
+    pub pattern: String,
+    /// Build method, the repository's default build method if not set.
+    pub method: Option<String>,
+}
+
+impl BuildTrigger {
+    /// Checks the pattern and that a build method is known for the repository.
+    pub fn validate(&self, entry: &RepositoryEntry) -> Result<()> {
+        tags::tag_filter(Some(&self.pattern), None)?;
+
+        match self.method.as_ref().or(entry.build_method.as_ref()) {
+            Some(method) if BUILD_METHODS.contains(&method.as_str()) => Ok(()),
+            Some(method) => Err(Error::Invalid(format!("Invalid build method: {}", method))),
+            None => Err(Error::Invalid(format!(
+                "Trigger for {} needs a build method, the repository has no default",
+                self.pattern
+            ))),
+        }
+    }
+}
+
+/// A build one of the triggers asks for.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TriggeredBuild {
+    /// Branch or tag name to build.
+    pub git_ref: String,
+    pub method: String,
+    /// What fired, recorded on the job.
+    pub reason: String,
+}
+
+/// Matches the refs changed by a sync against the triggers of the repository.
+///
+/// New commits on branches and newly created tags count, deleted refs and moved
+/// tags don't. Every ref is built at most once, by the first matching trigger.
+pub fn triggered_builds(entry: &RepositoryEntry, report: &SyncReport) -> Vec<TriggeredBuild> {
+    let mut builds = Vec::new();
+
+    for update in &report.updated {
+        let (kind, name) = if let Some(branch) = update.name.strip_prefix(BRANCH_PREFIX) {
+            (TriggerKind::Branch, branch)
+        } else if let Some(tag) = update.name.strip_prefix(TAG_PREFIX) {
+            (TriggerKind::Tag, tag)
+        } else {
+            continue;
+        };
+
+        let created_or_moved = match kind {
+            TriggerKind::Branch => update.new.is_some(),
+            TriggerKind::Tag => update.old.is_none() && update.new.is_some(),
+        };
+        if !created_or_moved || name == "HEAD" {
+            continue;
+        }
+
+        let trigger = entry.triggers.iter().find(|trigger| {
+            trigger.kind == kind
+                && matches!(
+                    tags::tag_filter(Some(&trigger.pattern), None),
+                    Ok(Some(matcher)) if matcher.is_match(name)
+                )
+        });
+
+        let trigger = match trigger {
+            Some(trigger) => trigger,
+            None => continue,
+        };
+
+        let method = match trigger.method.as_ref().or(entry.build_method.as_ref()) {
+            Some(method) => method.clone(),
+            None => {
+                tracing::warn!(
+                    "no build method for trigger {} of {}",
+                    trigger.pattern,
+                    entry.name
+                );
+                continue;
+            }
+        };
+
+        let reason = match kind {
+            TriggerKind::Branch => format!("new commit on branch {}", name),
+            TriggerKind::Tag => format!("new tag {}", name),
+        };
+
+        builds.push(TriggeredBuild {
+            git_ref: name.to_string(),
+            method,
+            reason,
+        });
+    }
+
+    builds
+}
+
+/// Queues the builds the triggers of the repository ask for after a sync.
+pub fn fire(jobs: &JobQueue, entry: &RepositoryEntry, report: &SyncReport) {
+    for build in triggered_builds(entry, report) {
+        tracing::info!(
+            "{} of {}, starting {} build",
+            build.reason,
+            entry.name,
+            build.method
+        );
+
+        match jobs.submit(
+            &entry.name,
+            &build.method,
+            Some(build.git_ref),
+            Some(build.reason),
+            BTreeMap::new(),
+            BuildOptions::default(),
+        ) {
+            Ok(job) => tracing::info!("triggered build job {} ({})", job.id, entry.name),
+            Err(e) => tracing::error!("Failed to trigger build of {} ({})", entry.name, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::git::manager::RefUpdate;
+
+    const OLD: &str = "1111111111111111111111111111111111111111";
+    const NEW: &str = "2222222222222222222222222222222222222222";
+
+    fn entry(triggers: Vec<BuildTrigger>) -> RepositoryEntry {
+        RepositoryEntry {
+            name: "tool".to_string(),
+            url: "https://example.org/tool.git".to_string(),
+            branch: "main".to_string(),
+            credentials: None,
+            sync_interval_secs: 3600,
+            build_method: Some("make".to_string()),
+            created_at: Utc::now(),
+            triggers,
+            webhook_secret: None,
+            notifications: Vec::new(),
+            last_synced_at: None,
+            last_sync_error: None,
+        }
+    }
+
+    fn trigger(kind: TriggerKind, pattern: &str, method: Option<&str>) -> BuildTrigger {
+        BuildTrigger {
+            kind,
+            pattern: pattern.to_string(),
+            method: method.map(str::to_string),
+        }
+    }
+
+    fn update(name: &str, old: Option<&str>, new: Option<&str>) -> RefUpdate {
+        RefUpdate {
+            name: name.to_string(),
+            old: old.map(str::to_string),
+            new: new.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn new_commits_and_new_tags_trigger_builds() {
+        let entry = entry(vec![
+            trigger(TriggerKind::Branch, "main", None),
+            trigger(TriggerKind::Tag, "v*", Some("cargo")),
+            trigger(TriggerKind::Tag, "*", Some("docker")),
+        ]);
+        let report = SyncReport {
+            branch: "main".to_string(),
+            updated: vec![
+                update("refs/remotes/origin/main", Some(OLD), Some(NEW)),
+                update("refs/remotes/origin/dev", Some(OLD), Some(NEW)),
+                update("refs/remotes/origin/HEAD", None, Some(NEW)),
+                update("refs/tags/v1.0.0", None, Some(NEW)),
+                update("refs/tags/nightly", None, Some(NEW)),
+                update("refs/heads/main", Some(OLD), Some(NEW)),
+            ],
+        };
+
+        let builds = triggered_builds(&entry, &report);
+
+        assert_eq!(
+            builds,
+            [
+                TriggeredBuild {
+                    git_ref: "main".to_string(),
+                    method: "make".to_string(),
+                    reason: "new commit on branch main".to_string(),
+                },
+                TriggeredBuild {
+                    git_ref: "v1.0.0".to_string(),
+                    method: "cargo".to_string(),
+                    reason: "new tag v1.0.0".to_string(),
+                },
+                TriggeredBuild {
+                    git_ref: "nightly".to_string(),
+                    method: "docker".to_string(),
+                    reason: "new tag nightly".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn deleted_refs_and_moved_tags_trigger_nothing() {
+        let entry = entry(vec![
+            trigger(TriggerKind::Branch, "*", None),
+            trigger(TriggerKind::Tag, "*", None),
+        ]);
+        let report = SyncReport {
+            branch: "main".to_string(),
+            updated: vec![
+                update("refs/remotes/origin/old", Some(OLD), None),
+                update("refs/tags/v1.0.0", Some(OLD), Some(NEW)),
+                update("refs/tags/v0.9.0", Some(OLD), None),
+            ],
+        };
+
+        assert!(triggered_builds(&entry, &report).is_empty());
+    }
+}
@@ -1,164 +1,1281 @@
-use std::path::Path;
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
 
-use git2::Repository;
-use tokio::time;
+use chrono::{DateTime, FixedOffset, Utc};
+use git2::{
+    build::{CheckoutBuilder, RepoBuilder},
+    AutotagOption, ErrorCode, FetchOptions, FetchPrune, Oid, RemoteCallbacks, Repository,
+    RepositoryInitOptions, Time,
+};
+use poem_openapi::{Enum, Object};
+use tokio::{task::JoinHandle, time};
 
+use crate::build::jobs::JobQueue;
+use crate::build::triggers::{self, BuildTrigger};
+use crate::git::credentials::{check_remote_url, CredentialStore, Credentials, CredentialsInfo};
+use crate::git::registry::{Registry, RepositoryEntry};
+use crate::notify::NotificationRoute;
+use crate::util::config::Config;
+use crate::util::error::{Error, Result};
 use crate::util::file_system::FileSystem;
 
+/// Refspecs fetched on every sync, tags are force-updated so moved tags are picked up.
+const FETCH_REFSPECS: &[&str] = &[
+    "+refs/heads/*:refs/remotes/origin/*",
+    "+refs/tags/*:refs/tags/*",
+];
+
+#[derive(Clone)]
 pub struct RepositoryManager {
     file_system: FileSystem,
+    registry: Arc<Mutex<Registry>>,
+    default_sync_interval_secs: u64,
+    /// Queue the build triggers of synced repositories submit to.
+    jobs: JobQueue,
+    /// Credentials private repositories are cloned and synced with.
+    credentials: CredentialStore,
+    /// Background sync tasks by repository name, aborted when a repository is deleted.
+    sync_tasks: Arc<Mutex<HashMap<String, JoinHandle<()>>>>,
+    /// Locks by checkout location, held while a sync, re-clone or delete touches the checkout.
+    checkout_locks: CheckoutLocks,
+}
+
+type CheckoutLocks = Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>;
+
+/// Options for adding a repository, anything left out falls back to a default.
+#[derive(Debug, Default, Clone)]
+pub struct CloneOptions {
+    /// Branch to check out, the remote's default branch if `None`.
+    pub branch: Option<String>,
+    /// Name of the stored credentials to clone and sync with, anonymous access if `None`.
+    pub credentials: Option<String>,
+    /// Seconds between background syncs.
+    pub sync_interval_secs: Option<u64>,
+    /// Build method used when none is specified.
+    pub build_method: Option<String>,
+}
+
+/// A reference that was created, moved or deleted by a sync.
+#[derive(Debug, Object, Clone, Eq, PartialEq)]
+pub struct RefUpdate {
+    /// Full name of the reference, e.g. `refs/tags/v1.0.0`.
+    pub name: String,
+    /// Previous target, `None` if the reference was created.
+    pub old: Option<String>,
+    /// New target, `None` if the reference was deleted.
+    pub new: Option<String>,
 }
 
+impl RefUpdate {
+    fn new(name: &str, old: Oid, new: Oid) -> Self {
+        let non_zero = |oid: Oid| (!oid.is_zero()).then(|| oid.to_string());
+
+        RefUpdate {
+            name: name.to_string(),
+            old: non_zero(old),
+            new: non_zero(new),
+        }
+    }
+}
+
+/// A resolved commit and the tags pointing at it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CommitInfo {
+    pub commit: String,
+    pub tags: Vec<String>,
+}
+
+/// Result of syncing a repository with its origin.
+#[derive(Debug, Object, Clone, Eq, PartialEq)]
+pub struct SyncReport {
+    /// The local branch that was brought up to date.
+    pub branch: String,
+    /// All references that changed during the sync.
+    pub updated: Vec<RefUpdate>,
+}
+
+/// How a repository came to be managed by the service.
+#[derive(Debug, Enum, Clone, Copy, Eq, PartialEq)]
+#[oai(rename_all = "lowercase")]
+pub enum RepositoryKind {
+    /// Cloned from a remote and kept in sync with it.
+    Mirror,
+    /// Created empty and pushed to over `/git/<name>`.
+    Hosted,
+}
+
+/// A managed repository together with the state of its checkout.
+#[derive(Debug, Object, Clone)]
+pub struct RepositoryInfo {
+    pub name: String,
+    pub kind: RepositoryKind,
+    /// Remote URL mirrors are synced from.
+    pub url: Option<String>,
+    /// Name of the stored credentials a mirror is synced with.
+    pub credentials: Option<String>,
+    /// Branch that is kept up to date, or HEAD points at for hosted repositories.
+    pub branch: Option<String>,
+    /// SHA of the commit HEAD points at, unset for empty repositories.
+    pub head_commit: Option<String>,
+    /// Summary (first line of the message) of the HEAD commit.
+    pub head_summary: Option<String>,
+    /// Seconds between two background syncs of a mirror.
+    pub sync_interval_secs: Option<u64>,
+    /// Build method used when none is specified.
+    pub build_method: Option<String>,
+    /// Time a mirror was added.
+    pub created_at: Option<DateTime<Utc>>,
+    /// Time the last sync with the remote finished, successful or not.
+    pub last_synced_at: Option<DateTime<Utc>>,
+    /// Why the last sync failed, unset if it succeeded.
+    pub last_sync_error: Option<String>,
+}
+
+/// A tag of a repository.
+#[derive(Debug, Object, Clone, Eq, PartialEq)]
 pub struct TagInfo {
     pub name: String,
+    /// Whether this is an annotated tag (with tagger and message) or a lightweight one.
+    pub annotated: bool,
+    /// SHA of the commit the tag points at.
     pub target_commit_id: String,
-    pub tagger_name: String,
-    pub tagger_email: String,
-    // TODO: tagger_when add timestamp of tag (formatting issues)
-    pub message: String,
+    /// Summary (first line of the message) of the tagged commit.
+    pub commit_summary: Option<String>,
+    pub tagger_name: Option<String>,
+    pub tagger_email: Option<String>,
+    /// RFC 3339 time the tag was created, the commit time for lightweight tags.
+    pub timestamp: DateTime<FixedOffset>,
+    /// Message of an annotated tag.
+    pub message: Option<String>,
 }
 
 impl RepositoryManager {
-    pub fn new(base_location: &str) -> Self {
-        let file_system = FileSystem::new(&base_location);
-        RepositoryManager { file_system }
-    }
-
-    // pasted prob not working
-    // fn fast_forward(&self, path: &Path) -> Result<(), Error> {
-    //     let repo = Repository::open(path)?;
-    //
-    //     repo.find_remote("origin")?
-    //         .fetch(&[self.branch], None, None)?;
-    //
-    //     let fetch_head = repo.find_reference("FETCH_HEAD")?;
-    //     let fetch_commit = repo.reference_to_annotated_commit(&fetch_head)?;
-    //     let analysis = repo.merge_analysis(&[&fetch_commit])?;
-    //     if analysis.0.is_up_to_date() {
-    //         Ok(())
-    //     } else if analysis.0.is_fast_forward() {
-    //         let refname = format!("refs/heads/{}", self.branch);
-    //         let mut reference = repo.find_reference(&refname)?;
-    //         reference.set_target(fetch_commit.id(), "Fast-Forward")?;
-    //         repo.set_head(&refname)?;
-    //         repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
-    //     } else {
-    //         Err(Error::from_str("Fast-forward only!"))
-    //     }
-    // }
-
-    // that is sooo stupid but tbh I don!t know how else to "pull" / sync changes using git2
-    async fn reset_repository_using_origin(location: &str) -> Result<Repository, String> {
-        // open the repository
-        let repo = match Repository::open(location) {
-            Ok(repo) => repo,
-            Err(e) => return Err(format!("Failed to open repository: {}", e)),
+    /// Creates a manager for the repositories in the configured data directory.
+    ///
+    /// Loads the persisted registry, the repositories are synced once [`Self::start_syncs`]
+    /// was called. Builds fired by triggers after a sync are submitted to `jobs`.
+    pub fn new(config: &Config, jobs: JobQueue) -> Result<Self> {
+        let file_system = FileSystem::new(&config.data_dir);
+        let registry = Registry::load(&file_system.registry_path())?;
+        let credentials = CredentialStore::load(&file_system.credentials_path())?;
+
+        Ok(RepositoryManager {
+            file_system,
+            registry: Arc::new(Mutex::new(registry)),
+            default_sync_interval_secs: config.sync.interval_secs,
+            jobs,
+            credentials,
+            sync_tasks: Arc::new(Mutex::new(HashMap::new())),
+            checkout_locks: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Schedules the background sync for every registered repository,
+    /// so repositories keep syncing across restarts.
+    pub fn start_syncs(&self) {
+        let entries: Vec<RepositoryEntry> = self.lock_registry().entries().cloned().collect();
+        for entry in entries {
+            tracing::info!("scheduling sync for registered repository ({})", entry.name);
+            self.schedule_sync(entry);
+        }
+    }
+
+    fn lock_registry(&self) -> std::sync::MutexGuard<'_, Registry> {
+        // a poisoned lock only means another thread panicked while holding it,
+        // the registry itself is always written as a whole
+        self.registry.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Handle to the stored credentials.
+    pub fn credentials(&self) -> CredentialStore {
+        self.credentials.clone()
+    }
+
+    /// Returns the registry entry of a repository.
+    pub fn get_entry(&self, name: &str) -> Option<RepositoryEntry> {
+        self.lock_registry().get(name).cloned()
+    }
+
+    fn checkout_lock(&self, location: &str) -> Arc<tokio::sync::Mutex<()>> {
+        checkout_lock(&self.checkout_locks, location)
+    }
+
+    fn lock_sync_tasks(&self) -> std::sync::MutexGuard<'_, HashMap<String, JoinHandle<()>>> {
+        self.sync_tasks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Spawns a task that periodically syncs the repository with its origin
+    /// and fires its build triggers for whatever changed.
+    fn schedule_sync(&self, entry: RepositoryEntry) {
+        let name = entry.name.clone();
+        // registries of older versions may hold names that are no longer accepted
+        let location = match self.file_system.git_path(&entry.name) {
+            Ok(location) => location,
+            Err(err) => {
+                tracing::error!("not syncing repository: {}", err);
+                return;
+            }
+        };
+        let registry = self.registry.clone();
+        let jobs = self.jobs.clone();
+        let credentials = self.credentials.clone();
+        let lock = self.checkout_lock(&location);
+
+        let task = tokio::spawn(async move {
+            loop {
+                // pick up changes to the entry, e.g. new triggers
+                let entry = match registry
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .get(&entry.name)
+                {
+                    Some(entry) => entry.clone(),
+                    None => break,
+                };
+
+                // Fetch and fast-forward to the state of the remote
+                let result = match resolve_credentials(&credentials, entry.credentials.as_deref()) {
+                    Ok(credentials) => {
+                        RepositoryManager::sync_checkout(
+                            lock.clone(),
+                            &location,
+                            Some(&entry.branch),
+                            credentials,
+                        )
+                        .await
+                    }
+                    Err(err) => Err(err),
+                };
+                record_sync(&registry, &entry.name, result.as_ref().err());
+                match result {
+                    Ok(report) if !report.updated.is_empty() => {
+                        tracing::info!(
+                            "synced repository at {} ({} refs updated)",
+                            location,
+                            report.updated.len()
+                        );
+                        triggers::fire(&jobs, &entry, &report);
+                    }
+                    Ok(_) => (),
+                    Err(e) => {
+                        tracing::error!("Failed to sync repository ({})", e);
+                    }
+                };
+
+                time::sleep(time::Duration::from_secs(entry.sync_interval_secs.max(1))).await;
+            }
+        });
+
+        if let Some(previous) = self.lock_sync_tasks().insert(name, task) {
+            previous.abort();
+        }
+    }
+
+    /// Lists the registered mirrors and the hosted repositories, sorted by name.
+    pub fn list_repositories(&self) -> Result<Vec<RepositoryInfo>> {
+        let entries: Vec<RepositoryEntry> = self.lock_registry().entries().cloned().collect();
+        let mirror_names: Vec<String> = entries.iter().map(|entry| entry.name.clone()).collect();
+
+        let mut repositories: Vec<RepositoryInfo> = entries
+            .into_iter()
+            .map(|entry| self.mirror_info(entry))
+            .collect();
+
+        let repos_path = self.file_system.repos_path();
+        let dir = match fs::read_dir(&repos_path) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(repositories),
+            Err(e) => return Err(Error::Io(format!("Failed to list {}: {}", repos_path, e))),
+        };
+
+        for name in dir
+            .flatten()
+            .filter(|item| item.path().is_dir())
+            .filter_map(|item| item.file_name().into_string().ok())
+        {
+            if mirror_names.contains(&name) {
+                continue;
+            }
+            // anything that isn't a repository is left alone, e.g. an interrupted clone
+            let location = match self.file_system.git_path(&name) {
+                Ok(location) => location,
+                Err(_) => continue,
+            };
+            if let Ok(repo) = Repository::open(&location) {
+                repositories.push(hosted_info(&name, &repo));
+            }
+        }
+
+        repositories.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(repositories)
+    }
+
+    /// Returns a registered mirror or hosted repository with the state of its checkout.
+    pub fn repository_info(&self, name: &str) -> Result<RepositoryInfo> {
+        if let Some(entry) = self.get_entry(name) {
+            return Ok(self.mirror_info(entry));
+        }
+
+        let repo = open_repository(&self.file_system.git_path(name)?)?;
+        Ok(hosted_info(name, &repo))
+    }
+
+    fn mirror_info(&self, entry: RepositoryEntry) -> RepositoryInfo {
+        let head = self
+            .file_system
+            .git_path(&entry.name)
+            .ok()
+            .and_then(|location| Repository::open(location).ok())
+            .and_then(|repo| head_commit(&repo));
+
+        RepositoryInfo {
+            name: entry.name,
+            kind: RepositoryKind::Mirror,
+            url: Some(entry.url),
+            credentials: entry.credentials,
+            branch: Some(entry.branch),
+            head_commit: head.as_ref().map(|(commit, _)| commit.clone()),
+            head_summary: head.and_then(|(_, summary)| summary),
+            sync_interval_secs: Some(entry.sync_interval_secs),
+            build_method: entry.build_method,
+            created_at: Some(entry.created_at),
+            last_synced_at: entry.last_synced_at,
+            last_sync_error: entry.last_sync_error,
+        }
+    }
+
+    /// Deletes a repository: stops its background sync, cancels its queued and running
+    /// builds and removes the checkout and the artifacts of its builds.
+    pub async fn delete_repository(&self, name: &str) -> Result<RepositoryInfo> {
+        let location = self.file_system.git_path(name)?;
+        let info = self.repository_info(name)?;
+
+        // removing the entry first keeps triggers and webhooks from queueing new builds
+        self.lock_registry().remove(name)?;
+
+        let task = self.lock_sync_tasks().remove(name);
+        if let Some(task) = task {
+            task.abort();
+            let _ = task.await;
+        }
+
+        // a fetch on the blocking pool outlives the aborted task, the lock waits for it
+        // and for manual syncs and re-clones
+        let _checkout = self.checkout_lock(&location).lock_owned().await;
+
+        let cancelled = self.jobs.purge_repository(name).await;
+        if !cancelled.is_empty() {
+            tracing::info!(
+                "cancelled {} build jobs of deleted repository ({})",
+                cancelled.len(),
+                name
+            );
+        }
+
+        match fs::remove_dir_all(&location) {
+            Ok(()) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => {
+                return Err(Error::Io(format!(
+                    "Failed to delete repository at {}: {}",
+                    location, e
+                )))
+            }
+        }
+
+        self.checkout_locks
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&location);
+        tracing::info!("deleted repository ({})", name);
+        Ok(info)
+    }
+
+    /// Sets or, with `None`, removes the secret webhooks of a registered repository are verified with.
+    pub fn set_webhook_secret(&self, name: &str, secret: Option<String>) -> Result<()> {
+        let mut registry = self.lock_registry();
+
+        let mut entry = match registry.get(name) {
+            Some(entry) => entry.clone(),
+            None => return Err(Error::repo_not_found(name)),
+        };
+
+        entry.webhook_secret = secret;
+        registry.insert(entry)
+    }
+
+    /// Sets or, with `None`, removes the stored credentials a registered repository is synced with.
+    pub fn set_credentials(
+        &self,
+        name: &str,
+        credentials: Option<String>,
+    ) -> Result<RepositoryEntry> {
+        if let Some(credentials) = &credentials {
+            self.credentials.get(credentials)?;
+        }
+
+        let mut registry = self.lock_registry();
+
+        let mut entry = match registry.get(name) {
+            Some(entry) => entry.clone(),
+            None => return Err(Error::repo_not_found(name)),
+        };
+
+        entry.credentials = credentials;
+        registry.insert(entry.clone())?;
+
+        Ok(entry)
+    }
+
+    /// Removes stored credentials, fails while a repository is synced with them.
+    pub fn delete_credentials(&self, name: &str) -> Result<CredentialsInfo> {
+        // holding the registry keeps repositories from starting to use them meanwhile
+        let registry = self.lock_registry();
+
+        let users: Vec<&str> = registry
+            .entries()
+            .filter(|entry| entry.credentials.as_deref() == Some(name))
+            .map(|entry| entry.name.as_str())
+            .collect();
+        if !users.is_empty() {
+            return Err(Error::Conflict(format!(
+                "Credentials {} are used by {}",
+                name,
+                users.join(", ")
+            )));
+        }
+
+        self.credentials.remove(name)
+    }
+
+    /// Replaces the notification routes of a registered repository.
+    pub fn set_notifications(
+        &self,
+        name: &str,
+        routes: Vec<NotificationRoute>,
+    ) -> Result<RepositoryEntry> {
+        let mut registry = self.lock_registry();
+
+        let mut entry = match registry.get(name) {
+            Some(entry) => entry.clone(),
+            None => return Err(Error::repo_not_found(name)),
+        };
+
+        for route in &routes {
+            route.notifier.validate()?;
+        }
+
+        entry.notifications = routes;
+        registry.insert(entry.clone())?;
+
+        Ok(entry)
+    }
+
+    /// Replaces the build triggers of a registered repository.
+    pub fn set_triggers(&self, name: &str, triggers: Vec<BuildTrigger>) -> Result<RepositoryEntry> {
+        let mut registry = self.lock_registry();
+
+        let mut entry = match registry.get(name) {
+            Some(entry) => entry.clone(),
+            None => return Err(Error::repo_not_found(name)),
+        };
+
+        for trigger in &triggers {
+            trigger.validate(&entry)?;
+        }
+
+        entry.triggers = triggers;
+        registry.insert(entry.clone())?;
+
+        Ok(entry)
+    }
+
+    /// Runs [`Self::fetch_and_fast_forward`] on the blocking pool,
+    /// fetching a large repository must not stall the runtime.
+    ///
+    /// `lock` is held until the fetch finished, even if the calling task is aborted meanwhile.
+    async fn sync_checkout(
+        lock: Arc<tokio::sync::Mutex<()>>,
+        location: &str,
+        branch: Option<&str>,
+        credentials: Option<Credentials>,
+    ) -> Result<SyncReport> {
+        let location = location.to_string();
+        let branch = branch.map(str::to_string);
+        let checkout = lock.lock_owned().await;
+        tokio::task::spawn_blocking(move || {
+            let _checkout = checkout;
+            RepositoryManager::fetch_and_fast_forward(
+                &location,
+                branch.as_deref(),
+                credentials.as_ref(),
+            )
+        })
+        .await
+        .unwrap_or_else(|e| Err(Error::Git(format!("Failed to sync repository: {}", e))))
+    }
+
+    /// Fetches `origin` and moves the local branch onto the fetched commit.
+    ///
+    /// Remote branches and tags are fetched with pruning enabled, so refs deleted
+    /// on the remote disappear locally as well. The local branch is fast-forwarded
+    /// if possible and hard-reset to the remote branch otherwise, since the checkout
+    /// is a mirror and is not expected to carry local commits.
+    fn fetch_and_fast_forward(
+        location: &str,
+        branch: Option<&str>,
+        credentials: Option<&Credentials>,
+    ) -> Result<SyncReport> {
+        let repo = open_repository(location)?;
+
+        let branch = match branch {
+            Some(branch) => branch.to_string(),
+            None => RepositoryManager::current_branch(&repo)?,
+        };
+
+        let mut updated = Vec::new();
+
+        {
+            let mut callbacks = RemoteCallbacks::new();
+            callbacks.update_tips(|refname, old, new| {
+                updated.push(RefUpdate::new(refname, old, new));
+                true
+            });
+            if let Some(credentials) = credentials {
+                credentials.authenticate(&mut callbacks);
+            }
+
+            let mut fetch_options = FetchOptions::new();
+            fetch_options
+                .remote_callbacks(callbacks)
+                .prune(FetchPrune::On)
+                .download_tags(AutotagOption::All);
+
+            let mut remote = match repo.find_remote("origin") {
+                Ok(remote) => remote,
+                Err(e) => return Err(Error::Git(format!("Failed to find remote: {}", e))),
+            };
+
+            if let Err(e) = remote.fetch(FETCH_REFSPECS, Some(&mut fetch_options), None) {
+                return Err(Error::Git(format!("Failed to fetch from origin: {}", e)));
+            }
+        }
+
+        let remote_ref = format!("refs/remotes/origin/{}", branch);
+        let target = match repo.refname_to_id(&remote_ref) {
+            Ok(oid) => oid,
+            Err(e) => return Err(Error::Git(format!("Failed to find {}: {}", remote_ref, e))),
+        };
+
+        let local_ref = format!("refs/heads/{}", branch);
+        let current = repo.refname_to_id(&local_ref).ok();
+
+        if current != Some(target) {
+            let fast_forward = match current {
+                Some(current) => repo
+                    .graph_descendant_of(target, current)
+                    .map_err(|e| Error::Git(format!("Failed to compare commits: {}", e)))?,
+                None => false,
+            };
+
+            let log_message = if fast_forward {
+                "sync: fast-forward"
+            } else {
+                "sync: reset to origin"
+            };
+
+            if let Err(e) = repo.reference(&local_ref, target, true, log_message) {
+                return Err(Error::Git(format!("Failed to update {}: {}", local_ref, e)));
+            }
+
+            updated.push(RefUpdate::new(
+                &local_ref,
+                current.unwrap_or_else(Oid::zero),
+                target,
+            ));
+        }
+
+        if let Err(e) = repo.set_head(&local_ref) {
+            return Err(Error::Git(format!(
+                "Failed to set HEAD to {}: {}",
+                local_ref, e
+            )));
+        }
+
+        if let Err(e) = repo.checkout_head(Some(CheckoutBuilder::new().force())) {
+            return Err(Error::Git(format!(
+                "Failed to check out {}: {}",
+                local_ref, e
+            )));
+        }
+
+        Ok(SyncReport { branch, updated })
+    }
+
+    /// Resolves a tag, branch or (abbreviated) commit SHA to a commit, HEAD if `git_ref` is `None`.
+    ///
+    /// Tags take precedence over branches, and branches are looked up on origin first
+    /// since that is what syncs keep up to date.
+    pub fn resolve_commit(location: &str, git_ref: Option<&str>) -> Result<CommitInfo> {
+        let repo = open_repository(location)?;
+
+        let candidates = match git_ref {
+            Some(git_ref) => vec![
+                format!("refs/tags/{}", git_ref),
+                format!("refs/remotes/origin/{}", git_ref),
+                format!("refs/heads/{}", git_ref),
+                git_ref.to_string(),
+            ],
+            None => vec!["HEAD".to_string()],
         };
 
+        let commit = candidates
+            .iter()
+            .find_map(|candidate| {
+                repo.revparse_single(candidate)
+                    .and_then(|object| object.peel_to_commit())
+                    .ok()
+            })
+            .map(|commit| commit.id())
+            .ok_or_else(|| {
+                Error::InvalidRef(format!("Failed to resolve {}", git_ref.unwrap_or("HEAD")))
+            })?;
+
+        let tag_names = match repo.tag_names(None) {
+            Ok(tag_names) => tag_names,
+            Err(e) => return Err(Error::Git(format!("Failed to retrieve tags: {}", e))),
+        };
+
+        let tags = tag_names
+            .iter()
+            .flatten()
+            .filter(|name| {
+                repo.revparse_single(&format!("refs/tags/{}", name))
+                    .and_then(|object| object.peel_to_commit())
+                    .map(|tagged| tagged.id() == commit)
+                    .unwrap_or(false)
+            })
+            .map(str::to_string)
+            .collect();
+
+        Ok(CommitInfo {
+            commit: commit.to_string(),
+            tags,
+        })
+    }
+
+    /// Message of the annotated tag `tag`, `None` for lightweight tags.
+    pub fn tag_message(location: &str, tag: &str) -> Option<String> {
+        let repo = Repository::open(location).ok()?;
+        let object = repo.revparse_single(&format!("refs/tags/{}", tag)).ok()?;
+        let message = object.as_tag()?.message()?;
+        Some(message.trim_end().to_string())
+    }
+
+    /// Writes the files of `commit` to `target_dir`, without touching the
+    /// repository's own working copy, index or HEAD.
+    pub fn export_commit(location: &str, commit: &str, target_dir: &str) -> Result<()> {
+        let repo = open_repository(location)?;
+
+        let tree = match Oid::from_str(commit)
+            .and_then(|oid| repo.find_commit(oid))
+            .and_then(|commit| commit.tree())
+        {
+            Ok(tree) => tree,
+            Err(e) => {
+                return Err(Error::Git(format!(
+                    "Failed to find commit {}: {}",
+                    commit, e
+                )))
+            }
+        };
+
+        // libgit2 only creates missing subdirectories below an absolute target
+        let target = match std::fs::create_dir_all(target_dir)
+            .and_then(|_| std::fs::canonicalize(target_dir))
+        {
+            Ok(target) => target,
+            Err(e) => return Err(Error::Io(format!("Failed to create {}: {}", target_dir, e))),
+        };
+
+        let mut checkout = CheckoutBuilder::new();
+        checkout
+            .target_dir(&target)
+            .update_index(false)
+            .recreate_missing(true)
+            .force();
+
+        match repo.checkout_tree(tree.as_object(), Some(&mut checkout)) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(Error::Git(format!(
+                "Failed to export commit {}: {}",
+                commit, e
+            ))),
+        }
+    }
+
+    /// Returns the short name of the branch HEAD points at.
+    fn current_branch(repo: &Repository) -> Result<String> {
+        let head = match repo.head() {
+            Ok(head) => head,
+            Err(e) => return Err(Error::Git(format!("Failed to resolve HEAD: {}", e))),
+        };
+
+        if !head.is_branch() {
+            return Err(Error::Git(
+                "HEAD is detached, cannot determine branch to sync".to_string(),
+            ));
+        }
+
+        match head.shorthand() {
+            Some(branch) => Ok(branch.to_string()),
+            None => Err(Error::Git("Branch name is not valid UTF-8".to_string())),
+        }
+    }
+
+    /// Deletes the checkout and clones it again from `origin`.
+    ///
+    /// Only used when explicitly requested, e.g. when the local repository is
+    /// corrupted and cannot be fetched into anymore.
+    fn reset_repository_using_origin(
+        location: &str,
+        credentials: Option<&Credentials>,
+    ) -> Result<Repository> {
+        // open the repository
+        let repo = open_repository(location)?;
+
         // get the remote url
         let remote_url = match repo.find_remote("origin") {
             Ok(remote) => match remote.url() {
                 Some(url) => url.to_string(),
-                None => return Err("Remote URL not found".to_string()),
+                None => return Err(Error::Git("Remote URL not found".to_string())),
             },
-            Err(e) => return Err(format!("Failed to find remote: {}", e)),
+            Err(e) => return Err(Error::Git(format!("Failed to find remote: {}", e))),
         };
 
         // delete local repository
         match std::fs::remove_dir_all(location) {
             Ok(()) => (),
-            Err(e) => return Err(format!("Failed to delete repository: {}", e)),
+            Err(e) => return Err(Error::Io(format!("Failed to delete repository: {}", e))),
         };
 
         // clone again
-        let repo = match Repository::clone(&remote_url, &location) {
+        let repo = match clone_builder(credentials).clone(&remote_url, Path::new(location)) {
             Ok(repo) => repo,
-            Err(e) => return Err(format!("Failed to clone repository: {}", e)),
+            Err(e) => return Err(Error::Git(format!("Failed to clone repository: {}", e))),
         };
 
         Ok(repo)
     }
 
-    pub async fn create_repository(&self, name: &str) -> Result<Repository, String> {
-        let location = self.file_system.git_path(&name);
+    /// Creates an empty bare repository, which can be pushed to over `/git/<name>`.
+    ///
+    /// HEAD points at `branch`, so clones check it out once it was pushed.
+    pub async fn create_repository(&self, name: &str, branch: &str) -> Result<Repository> {
+        let location = self.file_system.git_path(name)?;
 
         if Path::new(&location).exists() {
-            return Err(format!("Repository already exists at: {}", location));
+            return Err(Error::AlreadyExists(format!(
+                "Repository already exists at: {}",
+                location
+            )));
         }
 
-        let repo: Repository = match Repository::init_bare(&location) {
+        let mut options = RepositoryInitOptions::new();
+        options.bare(true).initial_head(branch);
+
+        let repo: Repository = match Repository::init_opts(&location, &options) {
             Ok(repo) => repo,
-            Err(e) => return Err(format!("Failed to init repository: {}", e)),
+            Err(e) => return Err(Error::Git(format!("Failed to init repository: {}", e))),
         };
 
         Ok(repo)
     }
 
-    pub async fn clone_repository(&self, url: &str, name: &str) -> Result<Repository, String> {
-        let name = name.to_string();
-        let location = self.file_system.git_path(&name);
+    pub async fn clone_repository(
+        &self,
+        url: &str,
+        name: &str,
+        options: CloneOptions,
+    ) -> Result<Repository> {
+        let location = self.file_system.git_path(name)?;
 
-        if Path::new(&location).exists() {
-            return Err(format!("Repository already exists at: {}", location));
+        if Path::new(&location).exists() || self.lock_registry().get(name).is_some() {
+            return Err(Error::AlreadyExists(format!(
+                "Repository already exists at: {}",
+                location
+            )));
         }
 
-        let repo = match Repository::clone(url, &location) {
-            Ok(repo) => repo,
-            Err(e) => return Err(format!("Failed to clone repository: {}", e)),
+        check_remote_url(url)?;
+        let credentials = resolve_credentials(&self.credentials, options.credentials.as_deref())?;
+
+        // cloning may take long, don't block the runtime meanwhile
+        let repo = {
+            let url = url.to_string();
+            let location = location.clone();
+            let branch = options.branch.clone();
+            tokio::task::spawn_blocking(move || {
+                let mut builder = clone_builder(credentials.as_ref());
+                if let Some(branch) = &branch {
+                    builder.branch(branch);
+                }
+                builder
+                    .clone(&url, Path::new(&location))
+                    .map_err(|e| Error::Git(format!("Failed to clone repository: {}", e)))
+            })
+            .await
+            .unwrap_or_else(|e| Err(Error::Git(format!("Failed to clone repository: {}", e))))?
         };
 
-        // schedule a periodic task to pull updates every hour
-        tokio::spawn(async move {
-            loop {
-                // Reset the repository to the state of the remote
-                match RepositoryManager::reset_repository_using_origin(&location).await {
-                    Ok(_) => (),
-                    Err(e) => {
-                        tracing::error!("Failed to reset repository ({})", e);
-                    }
-                };
+        let branch = match options.branch {
+            Some(branch) => branch,
+            None => RepositoryManager::current_branch(&repo)?,
+        };
 
-                // Sleep for 1 hour
-                time::sleep(time::Duration::from_secs(3600)).await;
-            }
-        });
+        let entry = RepositoryEntry {
+            name: name.to_string(),
+            url: url.to_string(),
+            credentials: options.credentials,
+            branch,
+            sync_interval_secs: options
+                .sync_interval_secs
+                .unwrap_or(self.default_sync_interval_secs),
+            build_method: options.build_method,
+            created_at: Utc::now(),
+            triggers: Vec::new(),
+            webhook_secret: None,
+            notifications: Vec::new(),
+            last_synced_at: None,
+            last_sync_error: None,
+        };
+
+        self.lock_registry().insert(entry.clone())?;
+
+        // schedule a periodic task to pull updates
+        self.schedule_sync(entry);
 
         Ok(repo)
     }
 
-    pub async fn get_tags(&self, name: &str) -> Result<Vec<String>, String> {
-        let location = self.file_system.git_path(&name);
+    /// Lists the tags of a repository that point at commits.
+    pub async fn get_tags(&self, name: &str) -> Result<Vec<TagInfo>> {
+        let location = self.file_system.git_path(name)?;
 
-        let repo = match Repository::open(&location) {
-            Ok(repo) => repo,
-            Err(e) => return Err(format!("Failed to open repository: {}", e)),
-        };
+        let repo = open_repository(&location)?;
 
         let tag_names = match repo.tag_names(None) {
             Ok(tag_names) => tag_names,
-            Err(e) => return Err(format!("Failed to retrieve tags: {}", e)),
+            Err(e) => return Err(Error::Git(format!("Failed to retrieve tags: {}", e))),
         };
 
-        let tag_infos: Vec<String> = tag_names
-            .iter()
-            .filter_map(|tag_name| tag_name.map(|name| name.to_string()))
-            .collect();
+        let mut tag_infos = Vec::new();
+        for tag_name in tag_names.iter().flatten() {
+            match RepositoryManager::tag_info(&repo, tag_name) {
+                Ok(tag_info) => tag_infos.push(tag_info),
+                Err(e) => tracing::debug!("skipping tag {}: {}", tag_name, e),
+            }
+        }
 
         Ok(tag_infos)
     }
 
-    pub async fn sync_repo(&self, path: &str) -> Result<(), String> {
-        match RepositoryManager::reset_repository_using_origin(&path).await {
+    fn tag_info(repo: &Repository, name: &str) -> std::result::Result<TagInfo, git2::Error> {
+        let object = repo.revparse_single(&format!("refs/tags/{}", name))?;
+        let commit = object.peel_to_commit()?;
+
+        let tag_info = match object.as_tag() {
+            Some(tag) => {
+                let tagger = tag.tagger();
+                TagInfo {
+                    name: name.to_string(),
+                    annotated: true,
+                    target_commit_id: commit.id().to_string(),
+                    commit_summary: commit.summary().map(str::to_string),
+                    tagger_name: tagger.as_ref().and_then(|t| t.name().map(str::to_string)),
+                    tagger_email: tagger.as_ref().and_then(|t| t.email().map(str::to_string)),
+                    // tags without tagger fall back to the commit time like lightweight ones
+                    timestamp: to_datetime(tagger.map(|t| t.when()).unwrap_or(commit.time())),
+                    message: tag.message().map(|m| m.trim_end().to_string()),
+                }
+            }
+            None => TagInfo {
+                name: name.to_string(),
+                annotated: false,
+                target_commit_id: commit.id().to_string(),
+                commit_summary: commit.summary().map(str::to_string),
+                tagger_name: None,
+                tagger_email: None,
+                timestamp: to_datetime(commit.time()),
+                message: None,
+            },
+        };
+
+        Ok(tag_info)
+    }
+
+    /// Syncs a repository with its origin, keeping the checkout in place.
+    ///
+    /// Uses the branch configured in the registry, or the checked out branch
+    /// for repositories that are not registered. Build triggers fire just like
+    /// for background syncs.
+    pub async fn sync_repo(&self, name: &str) -> Result<SyncReport> {
+        let path = self.file_system.git_path(name)?;
+        let entry = self.get_entry(name);
+        let branch = entry.as_ref().map(|entry| entry.branch.as_str());
+        let credentials = entry
+            .as_ref()
+            .and_then(|entry| entry.credentials.as_deref());
+
+        let result = match resolve_credentials(&self.credentials, credentials) {
+            Ok(credentials) => {
+                let lock = self.checkout_lock(&path);
+                RepositoryManager::sync_checkout(lock, &path, branch, credentials).await
+            }
+            Err(err) => Err(err),
+        };
+        if entry.is_some() {
+            record_sync(&self.registry, name, result.as_ref().err());
+        }
+
+        match result {
+            Ok(report) => {
+                tracing::info!(
+                    "synced repo at {} ({} refs updated)",
+                    path,
+                    report.updated.len()
+                );
+                if let Some(entry) = &entry {
+                    triggers::fire(&self.jobs, entry, &report);
+                }
+                Ok(report)
+            }
+            Err(e) => {
+                let err = e.context("Failed to sync repository");
+                tracing::error!("{}", err);
+                Err(err)
+            }
+        }
+    }
+
+    /// Deletes the local checkout and clones it again from `origin`.
+    ///
+    /// Fallback for checkouts that can no longer be fetched into.
+    pub async fn reclone_repo(&self, name: &str) -> Result<()> {
+        let path = self.file_system.git_path(name)?;
+        let entry = self.get_entry(name);
+        let credentials = resolve_credentials(
+            &self.credentials,
+            entry
+                .as_ref()
+                .and_then(|entry| entry.credentials.as_deref()),
+        )?;
+
+        let location = path.clone();
+        let checkout = self.checkout_lock(&path).lock_owned().await;
+        let result = tokio::task::spawn_blocking(move || {
+            let _checkout = checkout;
+            RepositoryManager::reset_repository_using_origin(&location, credentials.as_ref())
+        })
+        .await
+        .unwrap_or_else(|e| Err(Error::Git(format!("Failed to clone repository: {}", e))));
+
+        match result {
             Ok(_) => {
                 let msg = format!("reset/synced repo at {}", path);
                 tracing::info!("{}", msg);
                 Ok(())
             }
             Err(e) => {
-                let err_msg = format!("failed to reset repository ({})", e);
-                tracing::error!("{}", err_msg);
-                Err(err_msg)
+                let err = e.context("Failed to reset repository");
+                tracing::error!("{}", err);
+                Err(err)
             }
         }
     }
 }
+
+/// The lock of the checkout at `location`, created on first use.
+fn checkout_lock(locks: &CheckoutLocks, location: &str) -> Arc<tokio::sync::Mutex<()>> {
+    locks
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(location.to_string())
+        .or_default()
+        .clone()
+}
+
+/// Opens the repository at `location`, a missing one is reported as not found.
+fn open_repository(location: &str) -> Result<Repository> {
+    Repository::open(location).map_err(|e| match e.code() {
+        ErrorCode::NotFound => {
+            let name = Path::new(location)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| location.to_string());
+            Error::repo_not_found(&name)
+        }
+        _ => Error::Git(format!("Failed to open repository: {}", e)),
+    })
+}
+
+/// The credentials stored as `name`, `None` for repositories without credentials.
+fn resolve_credentials(store: &CredentialStore, name: Option<&str>) -> Result<Option<Credentials>> {
+    name.map(|name| store.get(name)).transpose()
+}
+
+/// A builder cloning with `credentials`, if any.
+fn clone_builder(credentials: Option<&Credentials>) -> RepoBuilder<'static> {
+    let mut builder = RepoBuilder::new();
+    if let Some(credentials) = credentials {
+        let mut callbacks = RemoteCallbacks::new();
+        credentials.authenticate(&mut callbacks);
+
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(callbacks);
+        builder.fetch_options(fetch_options);
+    }
+    builder
+}
+
+/// Records the time and outcome of a sync in the registry entry of a repository,
+/// if it is still registered.
+fn record_sync(registry: &Mutex<Registry>, name: &str, error: Option<&Error>) {
+    let mut registry = registry.lock().unwrap_or_else(|e| e.into_inner());
+    let mut entry = match registry.get(name) {
+        Some(entry) => entry.clone(),
+        None => return,
+    };
+
+    entry.last_synced_at = Some(Utc::now());
+    entry.last_sync_error = error.map(|e| e.to_string());
+    if let Err(e) = registry.insert(entry) {
+        tracing::warn!("failed to record sync of {}: {}", name, e);
+    }
+}
+
+/// SHA and summary of the commit HEAD points at, `None` for empty repositories.
+fn head_commit(repo: &Repository) -> Option<(String, Option<String>)> {
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+    Some((
+        commit.id().to_string(),
+        commit.summary().map(str::to_string),
+    ))
+}
+
+fn hosted_info(name: &str, repo: &Repository) -> RepositoryInfo {
+    let head = head_commit(repo);
+    // HEAD of a repository nothing was pushed to yet is unborn, but still names its branch
+    let branch = repo
+        .find_reference("HEAD")
+        .ok()
+        .and_then(|head| head.symbolic_target().map(str::to_string))
+        .map(|target| target.trim_start_matches("refs/heads/").to_string());
+
+    RepositoryInfo {
+        name: name.to_string(),
+        kind: RepositoryKind::Hosted,
+        url: None,
+        credentials: None,
+        branch,
+        head_commit: head.as_ref().map(|(commit, _)| commit.clone()),
+        head_summary: head.and_then(|(_, summary)| summary),
+        sync_interval_secs: None,
+        build_method: None,
+        created_at: None,
+        last_synced_at: None,
+        last_sync_error: None,
+    }
+}
+
+/// Converts a git time, keeping its UTC offset.
+fn to_datetime(time: Time) -> DateTime<FixedOffset> {
+    let offset = FixedOffset::east_opt(time.offset_minutes() * 60)
+        .unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
+    DateTime::from_timestamp(time.seconds(), 0)
+        .unwrap_or_default()
+        .with_timezone(&offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::Signature;
+
+    use super::*;
+    use crate::build::releases::ReleaseStore;
+    use crate::util::depends::Dependencies;
+
+    /// Commits a single `README` with `content` to `refname` of `repo`.
+    fn commit(repo: &Repository, refname: &str, parent: Option<Oid>, content: &str) -> Oid {
+        let blob = repo.blob(content.as_bytes()).unwrap();
+        let mut tree = repo.treebuilder(None).unwrap();
+        tree.insert("README", blob, 0o100644).unwrap();
+        let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+        let parents: Vec<_> = parent
+            .into_iter()
+            .map(|oid| repo.find_commit(oid).unwrap())
+            .collect();
+        let parents: Vec<_> = parents.iter().collect();
+        let signature = Signature::now("test", "test@example.org").unwrap();
+
+        let oid = repo
+            .commit(None, &signature, &signature, content, &tree, &parents)
+            .unwrap();
+        repo.reference(refname, oid, true, "test").unwrap();
+        oid
+    }
+
+    /// A bare origin with `main` and `old` branches and a clone of it.
+    fn origin_and_checkout(dir: &Path) -> (Repository, String, Oid) {
+        let origin_path = dir.join("origin.git");
+        let origin = Repository::init_bare(&origin_path).unwrap();
+        let first = commit(&origin, "refs/heads/main", None, "first");
+        origin
+            .reference("refs/heads/old", first, true, "test")
+            .unwrap();
+        origin.set_head("refs/heads/main").unwrap();
+
+        let checkout = dir.join("checkout");
+        Repository::clone(&origin_path.to_string_lossy(), &checkout).unwrap();
+        (origin, checkout.to_string_lossy().into_owned(), first)
+    }
+
+    fn local_branch(location: &str) -> Oid {
+        let repo = Repository::open(location).unwrap();
+        repo.refname_to_id("refs/heads/main").unwrap()
+    }
+
+    /// A manager keeping its data in `dir`.
+    fn manager(dir: &Path) -> RepositoryManager {
+        let config = Config {
+            data_dir: dir.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let releases = ReleaseStore::load(&FileSystem::new(&config.data_dir)).unwrap();
+        let jobs = JobQueue::new(&config, Dependencies::unprobed(&config), releases);
+        RepositoryManager::new(&config, jobs).unwrap()
+    }
+
+    #[tokio::test]
+    async fn delete_waits_for_the_checkout_to_be_released() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = manager(dir.path());
+        let location = manager.file_system.git_path("tool").unwrap();
+        Repository::init(&location).unwrap();
+
+        // stands in for a fetch still running on the blocking pool
+        let checkout = manager.checkout_lock(&location).lock_owned().await;
+        let deleting = tokio::spawn({
+            let manager = manager.clone();
+            async move { manager.delete_repository("tool").await }
+        });
+
+        time::sleep(time::Duration::from_millis(50)).await;
+        assert!(!deleting.is_finished());
+        assert!(Path::new(&location).exists());
+
+        drop(checkout);
+        deleting.await.unwrap().unwrap();
+        assert!(!Path::new(&location).exists());
+    }
+
+    #[tokio::test]
+    async fn invalid_names_are_rejected_instead_of_rewritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = manager(dir.path());
+
+        for name in ["tool.git", ".", "..", "a b", "-tool", ""] {
+            let created = manager.create_repository(name, "main").await;
+            assert!(matches!(created, Err(Error::InvalidName(_))), "{:?}", name);
+            let cloned = manager
+                .clone_repository(
+                    "https://example.org/tool.git",
+                    name,
+                    CloneOptions::default(),
+                )
+                .await;
+            assert!(matches!(cloned, Err(Error::InvalidName(_))), "{:?}", name);
+        }
+        assert!(fs::read_dir(manager.file_system.repos_path()).is_err());
+
+        manager.create_repository("a-b", "main").await.unwrap();
+        assert!(matches!(
+            manager.repository_info("a b"),
+            Err(Error::InvalidName(_))
+        ));
+        assert!(matches!(
+            manager.delete_repository("..").await,
+            Err(Error::InvalidName(_))
+        ));
+        assert!(Path::new(&manager.file_system.git_path("a-b").unwrap()).exists());
+    }
+
+    #[test]
+    fn sync_fast_forwards_the_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let (origin, checkout, first) = origin_and_checkout(dir.path());
+        let second = commit(&origin, "refs/heads/main", Some(first), "second");
+
+        let report =
+            RepositoryManager::fetch_and_fast_forward(&checkout, Some("main"), None).unwrap();
+
+        assert_eq!(report.branch, "main");
+        assert_eq!(local_branch(&checkout), second);
+        let update = report
+            .updated
+            .iter()
+            .find(|update| update.name == "refs/heads/main")
+            .unwrap();
+        assert_eq!(update.old, Some(first.to_string()));
+        assert_eq!(update.new, Some(second.to_string()));
+        let readme = fs::read_to_string(Path::new(&checkout).join("README")).unwrap();
+        assert_eq!(readme, "second");
+    }
+
+    #[test]
+    fn sync_resets_a_diverged_branch_to_origin() {
+        let dir = tempfile::tempdir().unwrap();
+        let (origin, checkout, first) = origin_and_checkout(dir.path());
+        let local = {
+            let repo = Repository::open(&checkout).unwrap();
+            commit(&repo, "refs/heads/main", Some(first), "local")
+        };
+        let remote = commit(&origin, "refs/heads/main", Some(first), "remote");
+
+        RepositoryManager::fetch_and_fast_forward(&checkout, Some("main"), None).unwrap();
+
+        assert_ne!(local, remote);
+        assert_eq!(local_branch(&checkout), remote);
+        let repo = Repository::open(&checkout).unwrap();
+        let reflog = repo.reflog("refs/heads/main").unwrap();
+        assert_eq!(
+            reflog.get(0).unwrap().message(),
+            Some("sync: reset to origin")
+        );
+    }
+
+    #[test]
+    fn sync_prunes_deleted_branches() {
+        let dir = tempfile::tempdir().unwrap();
+        let (origin, checkout, _) = origin_and_checkout(dir.path());
+        origin
+            .find_reference("refs/heads/old")
+            .unwrap()
+            .delete()
+            .unwrap();
+
+        let report =
+            RepositoryManager::fetch_and_fast_forward(&checkout, Some("main"), None).unwrap();
+
+        let repo = Repository::open(&checkout).unwrap();
+        assert!(repo.find_reference("refs/remotes/origin/old").is_err());
+        assert!(report
+            .updated
+            .iter()
+            .any(|update| update.name == "refs/remotes/origin/old" && update.new.is_none()));
+    }
+}
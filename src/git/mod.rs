@@ -1,2 +1,6 @@
+pub mod credentials;
 pub mod manager;
+pub mod registry;
 pub mod server;
+pub mod tags;
+pub mod webhooks;
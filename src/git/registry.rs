@@ -0,0 +1,186 @@
+use std::{collections::BTreeMap, fs, io};
+
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+
+use crate::build::triggers::BuildTrigger;
+use crate::notify::NotificationRoute;
+use crate::util::error::{Error, Result};
+use crate::util::file_system::write_atomic;
+
+/// A repository managed by the service, as persisted in the registry file.
+#[derive(Debug, Object, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct RepositoryEntry {
+    /// Name the repository was added under.
+    pub name: String,
+    /// Remote URL the repository is cloned and synced from.
+    pub url: String,
+    /// Branch that is checked out and kept up to date.
+    pub branch: String,
+    /// Name of the stored credentials the repository is cloned and synced with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credentials: Option<String>,
+    /// Seconds between two background syncs.
+    pub sync_interval_secs: u64,
+    /// Build method used when none is specified, one of "make", "script", "cargo" or "docker".
+    pub build_method: Option<String>,
+    /// Time the repository was added.
+    pub created_at: DateTime<Utc>,
+    /// Rules starting builds when a sync finds new commits or tags.
+    #[serde(default)]
+    pub triggers: Vec<BuildTrigger>,
+    /// Secret push webhooks of the forge are verified with, never exposed by the API.
+    #[oai(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook_secret: Option<String>,
+    /// Notifiers the build events of the repository are sent to.
+    #[serde(default)]
+    pub notifications: Vec<NotificationRoute>,
+    /// Time the last sync with the remote finished, successful or not.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_synced_at: Option<DateTime<Utc>>,
+    /// Why the last sync failed, unset if it succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_sync_error: Option<String>,
+}
+
+/// Persistent list of managed repositories, stored as JSON.
+///
+/// Every change is written to disk immediately so the registry survives restarts.
+pub struct Registry {
+    path: String,
+    entries: BTreeMap<String, RepositoryEntry>,
+}
+
+impl Registry {
+    /// Loads the registry from `path`, starting empty if the file does not exist yet.
+    pub fn load(path: &str) -> Result<Self> {
+        let entries = match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| Error::Io(format!("Failed to parse registry {}: {}", path, e)))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                return Err(Error::Io(format!(
+                    "Failed to read registry {}: {}",
+                    path, e
+                )))
+            }
+        };
+
+        Ok(Registry {
+            path: path.to_string(),
+            entries,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RepositoryEntry> {
+        self.entries.get(name)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &RepositoryEntry> {
+        self.entries.values()
+    }
+
+    /// Adds or replaces an entry and persists the registry.
+    pub fn insert(&mut self, entry: RepositoryEntry) -> Result<()> {
+        let name = entry.name.clone();
+        let previous = self.entries.insert(name.clone(), entry);
+
+        if let Err(err) = self.save() {
+            match previous {
+                Some(previous) => self.entries.insert(name, previous),
+                None => self.entries.remove(&name),
+            };
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Removes an entry and persists the registry, returns the removed entry if there was one.
+    pub fn remove(&mut self, name: &str) -> Result<Option<RepositoryEntry>> {
+        let entry = match self.entries.remove(name) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        if let Err(err) = self.save() {
+            self.entries.insert(name.to_string(), entry);
+            return Err(err);
+        }
+        Ok(Some(entry))
+    }
+
+    /// Persists the registry, readable by the service user only as it holds webhook secrets.
+    fn save(&self) -> Result<()> {
+        let content = serde_json::to_string_pretty(&self.entries)
+            .map_err(|e| Error::Io(format!("Failed to serialize registry: {}", e)))?;
+
+        write_atomic(&self.path, content.as_bytes(), true)
+            .map_err(|e| Error::Io(format!("Failed to write registry {}: {}", self.path, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn registry_is_only_readable_by_the_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir
+            .path()
+            .join("registry.json")
+            .to_string_lossy()
+            .into_owned();
+        // a leftover temporary file must not keep its mode
+        fs::write(format!("{}.tmp", path), "").unwrap();
+
+        let registry = Registry::load(&path).unwrap();
+        registry.save().unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn failed_inserts_are_rolled_back() {
+        let dir = tempfile::tempdir().unwrap();
+        // the registry can't be written where a directory is in the way
+        let path = dir.path().to_string_lossy().into_owned();
+        let mut registry = Registry::load(&format!("{}/missing.json", path)).unwrap();
+        let entry = RepositoryEntry {
+            name: "tool".to_string(),
+            url: "https://example.org/tool.git".to_string(),
+            branch: "main".to_string(),
+            credentials: None,
+            sync_interval_secs: 60,
+            build_method: None,
+            created_at: Utc::now(),
+            triggers: Vec::new(),
+            webhook_secret: None,
+            notifications: Vec::new(),
+            last_synced_at: None,
+            last_sync_error: None,
+        };
+        registry.insert(entry.clone()).unwrap();
+
+        registry.path = path;
+        let changed = RepositoryEntry {
+            branch: "develop".to_string(),
+            ..entry.clone()
+        };
+        assert!(registry.insert(changed).is_err());
+        assert_eq!(registry.get("tool"), Some(&entry));
+
+        let added = RepositoryEntry {
+            name: "other".to_string(),
+            ..entry
+        };
+        assert!(registry.insert(added).is_err());
+        assert!(registry.get("other").is_none());
+    }
+}
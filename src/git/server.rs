@@ -1,15 +1,534 @@
+use std::{process::Stdio, sync::Arc};
+
+use async_compression::tokio::bufread::GzipDecoder;
+use git2::Repository;
 use poem::{
-    handler,
-    web::{Path, Redirect},
+    get, handler,
+    http::{header, StatusCode},
+    web::{Data, Path, Query},
+    Body, Endpoint, EndpointExt, Error, Request, Response, Result, Route,
+};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, BufReader},
+    process::Command,
 };
 
+use crate::api::auth::{Scope, TokenStore};
+use crate::util::file_system::FileSystem;
+
+/// The git services a client can request over smart HTTP.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Service {
+    /// Serves clones and fetches.
+    UploadPack,
+    /// Accepts pushes.
+    ReceivePack,
+}
+
+impl Service {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "git-upload-pack" => Some(Service::UploadPack),
+            "git-receive-pack" => Some(Service::ReceivePack),
+            _ => None,
+        }
+    }
+
+    /// Scope a token needs for the service, pushing changes the code later builds run.
+    fn scope(&self) -> Scope {
+        match self {
+            Service::UploadPack => Scope::Read,
+            Service::ReceivePack => Scope::Build,
+        }
+    }
+
+    /// Name of the service, also the git subcommand that implements it.
+    fn name(&self) -> &'static str {
+        match self {
+            Service::UploadPack => "upload-pack",
+            Service::ReceivePack => "receive-pack",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct InfoRefsQuery {
+    service: Option<String>,
+}
+
+/// Routes of the git smart HTTP protocol for the managed repositories.
+///
+/// Mounted at `/git`, a repository is cloned with `git clone <host>/git/<name>`
+/// (a trailing `.git` is accepted). Pushes are only accepted by bare repositories,
+/// managed clones are kept in sync with their origin instead.
+///
+/// Every request needs an API token, sent as the password of Basic auth (so git's credential
+/// helpers can provide it) or as a Bearer token. Fetching needs the `read` scope, pushing
+/// the `build` scope, and the token must be allowed to access the repository.
+pub fn routes(file_system: FileSystem, tokens: TokenStore) -> impl Endpoint {
+    Route::new()
+        .at("/:name/info/refs", get(info_refs))
+        .at("/:name/git-upload-pack", poem::post(upload_pack))
+        .at("/:name/git-receive-pack", poem::post(receive_pack))
+        .data(Arc::new(file_system))
+        .data(tokens)
+}
+
 #[handler]
-pub async fn redirect(Path(name): Path<String>) -> Redirect {
-    let msg = format!("redirecting to: {}", name);
-    tracing::debug!(msg);
-
-    // TODO: Add a actualyl working git server
-    // supporting push pull co
-    // (idfk how)
-    Redirect::moved_permanent("/test123/again")
+async fn info_refs(
+    Path(name): Path<String>,
+    Query(query): Query<InfoRefsQuery>,
+    file_system: Data<&Arc<FileSystem>>,
+    tokens: Data<&TokenStore>,
+    req: &Request,
+) -> Result<Response> {
+    let service = query
+        .service
+        .as_deref()
+        .and_then(Service::parse)
+        .ok_or_else(|| {
+            Error::from_string(
+                "Only the smart HTTP protocol is supported",
+                StatusCode::FORBIDDEN,
+            )
+        })?;
+
+    authorize(&tokens, req, &name, service).map_err(challenge)?;
+    let location = repository_location(&file_system, &name, service)
+        .map_err(|(err_msg, status)| Error::from_string(err_msg, status))?;
+    let protocol = git_protocol(req);
+
+    let output = Command::new("git")
+        .args([service.name(), "--stateless-rpc", "--advertise-refs", "."])
+        .current_dir(&location)
+        .envs(protocol.iter().map(|protocol| ("GIT_PROTOCOL", protocol)))
+        .output()
+        .await
+        .map_err(|e| server_error(format!("Failed to run git {}: {}", service.name(), e)))?;
+
+    if !output.status.success() {
+        return Err(server_error(format!(
+            "git {} failed: {}",
+            service.name(),
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    let mut body = Vec::new();
+    // protocol v2 clients expect the capabilities right away
+    if !protocol
+        .as_deref()
+        .unwrap_or_default()
+        .contains("version=2")
+    {
+        body.extend(pkt_line(&format!("# service=git-{}\n", service.name())));
+        body.extend(b"0000");
+    }
+    body.extend(output.stdout);
+
+    Ok(Response::builder()
+        .content_type(format!(
+            "application/x-git-{}-advertisement",
+            service.name()
+        ))
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(body))
+}
+
+#[handler]
+async fn upload_pack(
+    Path(name): Path<String>,
+    file_system: Data<&Arc<FileSystem>>,
+    tokens: Data<&TokenStore>,
+    req: &Request,
+    body: Body,
+) -> Result<Response> {
+    authorize(&tokens, req, &name, Service::UploadPack).map_err(challenge)?;
+    run_service(&file_system, &name, Service::UploadPack, req, body).await
+}
+
+#[handler]
+async fn receive_pack(
+    Path(name): Path<String>,
+    file_system: Data<&Arc<FileSystem>>,
+    tokens: Data<&TokenStore>,
+    req: &Request,
+    body: Body,
+) -> Result<Response> {
+    authorize(&tokens, req, &name, Service::ReceivePack).map_err(challenge)?;
+    run_service(&file_system, &name, Service::ReceivePack, req, body).await
+}
+
+/// Pipes the request body through `git <service> --stateless-rpc` and streams its output back.
+async fn run_service(
+    file_system: &FileSystem,
+    name: &str,
+    service: Service,
+    req: &Request,
+    body: Body,
+) -> Result<Response> {
+    let location = repository_location(file_system, name, service)
+        .map_err(|(err_msg, status)| Error::from_string(err_msg, status))?;
+
+    let expected_type = format!("application/x-git-{}-request", service.name());
+    if req.content_type() != Some(expected_type.as_str()) {
+        return Err(Error::from_string(
+            format!("Expected content type {}", expected_type),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        ));
+    }
+
+    // git compresses large requests
+    let gzipped = req
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.eq_ignore_ascii_case("gzip"))
+        .unwrap_or(false);
+    let mut input: Box<dyn AsyncRead + Send + Unpin> = if gzipped {
+        Box::new(GzipDecoder::new(BufReader::new(body.into_async_read())))
+    } else {
+        Box::new(body.into_async_read())
+    };
+
+    let mut child = Command::new("git")
+        .args([service.name(), "--stateless-rpc", "."])
+        .current_dir(&location)
+        .envs(git_protocol(req).map(|protocol| ("GIT_PROTOCOL", protocol)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| server_error(format!("Failed to run git {}: {}", service.name(), e)))?;
+
+    let (mut stdin, stdout, mut stderr) =
+        match (child.stdin.take(), child.stdout.take(), child.stderr.take()) {
+            (Some(stdin), Some(stdout), Some(stderr)) => (stdin, stdout, stderr),
+            _ => return Err(server_error("Failed to open git pipes".to_string())),
+        };
+
+    // feed the request while the response streams out, git may answer before reading everything
+    let name = name.to_string();
+    tokio::spawn(async move {
+        if let Err(e) = tokio::io::copy(&mut input, &mut stdin).await {
+            tracing::warn!("failed to pass request to git {}: {}", service.name(), e);
+        }
+        drop(stdin);
+
+        let mut errors = String::new();
+        let _ = stderr.read_to_string(&mut errors).await;
+
+        match child.wait().await {
+            Ok(status) if status.success() => {
+                tracing::debug!("git {} for {} finished", service.name(), name)
+            }
+            Ok(status) => tracing::warn!(
+                "git {} for {} exited with {}: {}",
+                service.name(),
+                name,
+                status,
+                errors.trim()
+            ),
+            Err(e) => tracing::error!("failed to wait for git {}: {}", service.name(), e),
+        }
+    });
+
+    Ok(Response::builder()
+        .content_type(format!("application/x-git-{}-result", service.name()))
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(Body::from_async_read(stdout)))
+}
+
+/// Fails unless the request carries a token with the scope `service` needs for the repository.
+fn authorize(
+    tokens: &TokenStore,
+    req: &Request,
+    name: &str,
+    service: Service,
+) -> crate::util::error::Result<()> {
+    let name = name.strip_suffix(".git").unwrap_or(name);
+    tokens
+        .authenticate_request(req)?
+        .require(service.scope(), Some(name))
+}
+
+/// Answers missing or invalid tokens with a Basic auth challenge,
+/// so git asks its credential helpers or the user for them.
+fn challenge(err: crate::util::error::Error) -> Error {
+    let mut response = poem::error::ResponseError::as_response(&err);
+    if response.status() == StatusCode::UNAUTHORIZED {
+        response.headers_mut().insert(
+            header::WWW_AUTHENTICATE,
+            header::HeaderValue::from_static("Basic realm=\"release_workflows\""),
+        );
+    }
+    Error::from_response(response)
+}
+
+/// Path of the repository `name`, if it exists and may be used for `service`.
+fn repository_location(
+    file_system: &FileSystem,
+    name: &str,
+    service: Service,
+) -> std::result::Result<String, (String, StatusCode)> {
+    let name = name.strip_suffix(".git").unwrap_or(name);
+    let location = file_system
+        .git_path(name)
+        .map_err(|err| (err.message().to_string(), err.status()))?;
+
+    let repo = match Repository::open(&location) {
+        Ok(repo) => repo,
+        Err(_) => {
+            return Err((
+                format!("Repository not found ({})", name),
+                StatusCode::NOT_FOUND,
+            ))
+        }
+    };
+
+    if service == Service::ReceivePack && !repo.is_bare() {
+        return Err((
+            format!("Repository {} is a mirror and does not accept pushes", name),
+            StatusCode::FORBIDDEN,
+        ));
+    }
+
+    Ok(location)
+}
+
+/// Protocol version requested by the client, passed on to git.
+fn git_protocol(req: &Request) -> Option<String> {
+    req.headers()
+        .get("Git-Protocol")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Encodes a line in git's pkt-line format: its length as four hex digits, then the data.
+fn pkt_line(data: &str) -> Vec<u8> {
+    format!("{:04x}{}", data.len() + 4, data).into_bytes()
+}
+
+fn server_error(err_msg: String) -> Error {
+    tracing::error!(err_msg);
+    Error::from_string(err_msg, StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use poem::{
+        listener::{Acceptor, Listener, TcpListener},
+        test::TestClient,
+    };
+
+    use super::*;
+    use crate::api::auth::NewToken;
+
+    struct Fixture {
+        _dir: tempfile::TempDir,
+        client: TestClient<Box<dyn Endpoint<Output = Response>>>,
+        tokens: TokenStore,
+    }
+
+    /// Serves a bare repository `hosted` and a clone `mirror` without any tokens yet.
+    fn fixture() -> Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        let file_system = FileSystem::new(&dir.path().to_string_lossy());
+        Repository::init_bare(file_system.git_path("hosted").unwrap()).unwrap();
+        Repository::init(file_system.git_path("mirror").unwrap()).unwrap();
+        let tokens = TokenStore::load(&file_system.tokens_path()).unwrap();
+
+        let endpoint = routes(file_system, tokens.clone())
+            .map_to_response()
+            .boxed();
+        Fixture {
+            _dir: dir,
+            client: TestClient::new(endpoint),
+            tokens,
+        }
+    }
+
+    fn token(tokens: &TokenStore, scope: Scope, repositories: &[&str]) -> String {
+        tokens
+            .create(NewToken {
+                name: format!("{} token", scope.as_str()),
+                scopes: vec![scope],
+                repositories: repositories.iter().map(|name| name.to_string()).collect(),
+            })
+            .unwrap()
+            .secret
+    }
+
+    fn basic(secret: &str) -> String {
+        format!("Basic {}", STANDARD.encode(format!("git:{}", secret)))
+    }
+
+    async fn push(fixture: &Fixture, authorization: Option<&str>) -> poem::test::TestResponse {
+        let request = fixture
+            .client
+            .post("/hosted/git-receive-pack")
+            .content_type("application/x-git-receive-pack-request")
+            .body("0000");
+        match authorization {
+            Some(value) => request.header(header::AUTHORIZATION, value),
+            None => request,
+        }
+        .send()
+        .await
+    }
+
+    /// Runs `git` with `args` in `dir`, ignoring the user's git configuration.
+    async fn git(dir: &std::path::Path, args: &[&str]) {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .output()
+            .await
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    #[tokio::test]
+    async fn pushed_commits_can_be_cloned() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_system = FileSystem::new(&dir.path().join("data").to_string_lossy());
+        Repository::init_bare(file_system.git_path("hosted").unwrap()).unwrap();
+        let tokens = TokenStore::load(&file_system.tokens_path()).unwrap();
+        let build = token(&tokens, Scope::Build, &["hosted"]);
+
+        let acceptor = TcpListener::bind("127.0.0.1:0")
+            .into_acceptor()
+            .await
+            .unwrap();
+        let addr = acceptor.local_addr()[0].as_socket_addr().copied().unwrap();
+        tokio::spawn(poem::Server::new_with_acceptor(acceptor).run(routes(file_system, tokens)));
+        let url = format!("http://git:{}@{}/hosted.git", build, addr);
+
+        let work = dir.path().join("work");
+        git(dir.path(), &["init", "-q", "-b", "main", "work"]).await;
+        std::fs::write(work.join("README"), "hosted").unwrap();
+        git(&work, &["add", "README"]).await;
+        git(
+            &work,
+            &[
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@example.org",
+                "commit",
+                "-qm",
+                "first",
+            ],
+        )
+        .await;
+        git(&work, &["push", "-q", &url, "main"]).await;
+
+        git(dir.path(), &["clone", "-q", "-b", "main", &url, "clone"]).await;
+        let readme = std::fs::read_to_string(dir.path().join("clone/README")).unwrap();
+        assert_eq!(readme, "hosted");
+    }
+
+    #[tokio::test]
+    async fn anonymous_push_is_rejected_with_a_challenge() {
+        let fixture = fixture();
+
+        let response = push(&fixture, None).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        response.assert_header(
+            header::WWW_AUTHENTICATE,
+            "Basic realm=\"release_workflows\"",
+        );
+
+        fixture
+            .client
+            .get("/hosted/info/refs?service=git-receive-pack")
+            .send()
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn push_with_an_invalid_token_is_rejected() {
+        let fixture = fixture();
+        token(&fixture.tokens, Scope::Admin, &[]);
+
+        push(&fixture, Some(&basic("rwt_guessed")))
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        push(&fixture, Some("Bearer rwt_guessed"))
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn push_needs_the_build_scope() {
+        let fixture = fixture();
+        let read = token(&fixture.tokens, Scope::Read, &[]);
+        let build = token(&fixture.tokens, Scope::Build, &[]);
+
+        push(&fixture, Some(&basic(&read)))
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+
+        fixture
+            .client
+            .get("/hosted.git/info/refs?service=git-receive-pack")
+            .header(header::AUTHORIZATION, basic(&build))
+            .send()
+            .await
+            .assert_status_is_ok();
+    }
+
+    #[tokio::test]
+    async fn push_to_a_mirror_is_rejected() {
+        let fixture = fixture();
+        let admin = token(&fixture.tokens, Scope::Admin, &[]);
+
+        fixture
+            .client
+            .get("/mirror/info/refs?service=git-receive-pack")
+            .header(header::AUTHORIZATION, format!("Bearer {}", admin))
+            .send()
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn fetch_needs_a_token_for_the_repository() {
+        let fixture = fixture();
+        let read = token(&fixture.tokens, Scope::Read, &["hosted"]);
+        let other = token(&fixture.tokens, Scope::Admin, &["other"]);
+
+        let advertise = |authorization: Option<String>| {
+            let request = fixture
+                .client
+                .get("/hosted/info/refs?service=git-upload-pack");
+            match authorization {
+                Some(value) => request.header(header::AUTHORIZATION, value),
+                None => request,
+            }
+            .send()
+        };
+
+        advertise(None)
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        advertise(Some(basic(&other)))
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+
+        let response = advertise(Some(basic(&read))).await;
+        response.assert_status_is_ok();
+        response.assert_content_type("application/x-git-upload-pack-advertisement");
+    }
 }
@@ -0,0 +1,178 @@
+use std::cmp::Ordering;
+
+use poem_openapi::Enum;
+use regex::Regex;
+use semver::Version;
+
+use crate::git::manager::TagInfo;
+use crate::util::error::{Error, Result};
+
+/// Order of a tag listing.
+#[derive(Debug, Enum, Clone, Copy, Eq, PartialEq, Default)]
+#[oai(rename_all = "lowercase")]
+pub enum TagSort {
+    /// Alphabetical by name.
+    #[default]
+    Name,
+    /// Highest version first, tags that are no semantic version follow, newest first.
+    Semver,
+    /// Newest first.
+    Date,
+}
+
+/// Parses a tag name like `v1.2.3` or `1.2.3-rc.1` as a semantic version.
+pub fn parse_version(name: &str) -> Option<Version> {
+    let version = name
+        .strip_prefix('v')
+        .or_else(|| name.strip_prefix('V'))
+        .unwrap_or(name);
+    Version::parse(version).ok()
+}
+
+pub fn sort_tags(tags: &mut [TagInfo], sort: TagSort) {
+    match sort {
+        TagSort::Name => tags.sort_by(|a, b| a.name.cmp(&b.name)),
+        TagSort::Semver => tags.sort_by(|a, b| {
+            match (parse_version(&a.name), parse_version(&b.name)) {
+                (Some(a), Some(b)) => b.cmp(&a),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => b.timestamp.cmp(&a.timestamp),
+            }
+            .then_with(|| a.name.cmp(&b.name))
+        }),
+        TagSort::Date => tags.sort_by(|a, b| {
+            b.timestamp
+                .cmp(&a.timestamp)
+                .then_with(|| a.name.cmp(&b.name))
+        }),
+    }
+}
+
+/// Builds a matcher for tag names from a glob (`*` and `?` wildcards, e.g. `v*`)
+/// or a regular expression.
+pub fn tag_filter(glob: Option<&str>, regex: Option<&str>) -> Result<Option<Regex>> {
+    let pattern = match (glob, regex) {
+        (Some(_), Some(_)) => {
+            return Err(Error::Invalid(
+                "Use either filter or regex, not both".to_string(),
+            ))
+        }
+        (Some(glob), None) => glob_to_regex(glob),
+        (None, Some(regex)) => regex.to_string(),
+        (None, None) => return Ok(None),
+    };
+
+    Regex::new(&pattern)
+        .map(Some)
+        .map_err(|e| Error::Invalid(format!("Invalid tag filter: {}", e)))
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut pattern = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+
+    fn tag(name: &str, timestamp: i64) -> TagInfo {
+        TagInfo {
+            name: name.to_string(),
+            annotated: false,
+            target_commit_id: "0123456789abcdef".to_string(),
+            commit_summary: None,
+            tagger_name: None,
+            tagger_email: None,
+            timestamp: DateTime::from_timestamp(timestamp, 0)
+                .unwrap()
+                .fixed_offset(),
+            message: None,
+        }
+    }
+
+    fn sorted(mut tags: Vec<TagInfo>, sort: TagSort) -> Vec<String> {
+        sort_tags(&mut tags, sort);
+        tags.into_iter().map(|tag| tag.name).collect()
+    }
+
+    #[test]
+    fn semver_sort_compares_versions_not_names() {
+        let tags = vec![
+            tag("v1.9.0", 40),
+            tag("nightly-old", 10),
+            tag("v1.10.0", 30),
+            tag("v1.10.0-rc.1", 20),
+            tag("nightly-new", 50),
+            tag("1.2.3", 60),
+        ];
+
+        assert_eq!(
+            sorted(tags.clone(), TagSort::Semver),
+            [
+                "v1.10.0",
+                "v1.10.0-rc.1",
+                "v1.9.0",
+                "1.2.3",
+                "nightly-new",
+                "nightly-old"
+            ]
+        );
+        assert_eq!(
+            sorted(tags, TagSort::Date),
+            [
+                "1.2.3",
+                "nightly-new",
+                "v1.9.0",
+                "v1.10.0",
+                "v1.10.0-rc.1",
+                "nightly-old"
+            ]
+        );
+    }
+
+    #[test]
+    fn versions_are_parsed_with_an_optional_prefix() {
+        assert_eq!(parse_version("v1.2.3"), Some(Version::new(1, 2, 3)));
+        assert_eq!(parse_version("V1.2.3"), Some(Version::new(1, 2, 3)));
+        assert!(parse_version("1.2.3-rc.1") < parse_version("1.2.3"));
+        assert_eq!(parse_version("v1.2"), None);
+        assert_eq!(parse_version("release-1.2.3"), None);
+    }
+
+    #[test]
+    fn glob_filters_match_whole_names() {
+        let filter = tag_filter(Some("v1.*"), None).unwrap().unwrap();
+        assert!(filter.is_match("v1.2.3"));
+        assert!(!filter.is_match("v10.0.0"));
+        assert!(!filter.is_match("xv1.2"));
+
+        let filter = tag_filter(Some("v?.0"), None).unwrap().unwrap();
+        assert!(filter.is_match("v2.0"));
+        assert!(!filter.is_match("v20.0"));
+
+        let filter = tag_filter(None, Some(r"^v\d+$")).unwrap().unwrap();
+        assert!(filter.is_match("v12"));
+
+        assert!(tag_filter(None, None).unwrap().is_none());
+        assert!(matches!(
+            tag_filter(Some("v*"), Some("v.*")),
+            Err(Error::Invalid(_))
+        ));
+        assert!(matches!(
+            tag_filter(None, Some("(")),
+            Err(Error::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,491 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use hmac::{Hmac, Mac};
+use poem::{
+    error::ReadBodyError,
+    handler,
+    http::{HeaderMap, StatusCode},
+    web::{Data, Path, RemoteAddr},
+    Body, Endpoint, EndpointExt, Response, Route,
+};
+use sha2::{Digest, Sha256};
+
+use crate::git::manager::RepositoryManager;
+
+/// Number of payload digests remembered to detect replayed payloads.
+///
+/// They are only kept in memory, a payload replayed after a restart is processed again.
+const REMEMBERED_DELIVERIES: usize = 4096;
+
+/// Largest payload accepted, push events of forges stay well below it.
+const MAX_PAYLOAD_SIZE: usize = 1024 * 1024;
+
+/// Forges webhooks are accepted from.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Provider {
+    GitHub,
+    /// Gitea and Forgejo, which share the format.
+    Gitea,
+    GitLab,
+}
+
+impl Provider {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "github" => Some(Provider::GitHub),
+            "gitea" | "forgejo" => Some(Provider::Gitea),
+            "gitlab" => Some(Provider::GitLab),
+            _ => None,
+        }
+    }
+
+    fn event<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        match self {
+            Provider::GitHub => header(headers, "X-GitHub-Event"),
+            Provider::Gitea => {
+                header(headers, "X-Forgejo-Event").or_else(|| header(headers, "X-Gitea-Event"))
+            }
+            Provider::GitLab => header(headers, "X-Gitlab-Event"),
+        }
+    }
+
+    /// Delivery id set by the forge, only used for logging as it is not covered by the signature.
+    fn delivery<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        match self {
+            Provider::GitHub => header(headers, "X-GitHub-Delivery"),
+            Provider::Gitea => header(headers, "X-Forgejo-Delivery")
+                .or_else(|| header(headers, "X-Gitea-Delivery")),
+            // retries of the same event keep the idempotency key
+            Provider::GitLab => header(headers, "Idempotency-Key")
+                .or_else(|| header(headers, "X-Gitlab-Event-UUID")),
+        }
+    }
+
+    /// Whether the event reports pushed commits or tags.
+    fn is_push(&self, event: &str) -> bool {
+        match self {
+            Provider::GitHub | Provider::Gitea => event == "push",
+            Provider::GitLab => event == "Push Hook" || event == "Tag Push Hook",
+        }
+    }
+
+    /// The signature of the payload, the plain token for GitLab.
+    fn signature<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        match self {
+            Provider::GitHub => {
+                header(headers, "X-Hub-Signature-256").and_then(|s| s.strip_prefix("sha256="))
+            }
+            Provider::Gitea => header(headers, "X-Forgejo-Signature")
+                .or_else(|| header(headers, "X-Gitea-Signature")),
+            Provider::GitLab => header(headers, "X-Gitlab-Token"),
+        }
+    }
+
+    /// Checks the payload against the repository's secret: an HMAC-SHA256 signature
+    /// for GitHub and Gitea, the plain token for GitLab.
+    fn verify(&self, headers: &HeaderMap, body: &[u8], secret: &str) -> bool {
+        let signature = self.signature(headers);
+        if *self == Provider::GitLab {
+            return match signature {
+                // comparing digests keeps the comparison time independent of the secret
+                Some(token) => Sha256::digest(token) == Sha256::digest(secret),
+                None => false,
+            };
+        }
+
+        let signature = match signature.and_then(|s| hex::decode(s).ok()) {
+            Some(signature) => signature,
+            None => return false,
+        };
+
+        let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+            Ok(mac) => mac,
+            Err(_) => return false,
+        };
+        mac.update(body);
+        mac.verify_slice(&signature).is_ok()
+    }
+
+    /// Identifies a verified payload, a forge's retry of a delivery sends the same payload.
+    ///
+    /// Unlike the delivery id, body and signature can't be altered without the secret,
+    /// so a captured payload can't be replayed under a new id.
+    fn payload_digest(&self, headers: &HeaderMap, body: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(format!("{:?}\n", self));
+        hasher.update(self.signature(headers).unwrap_or_default());
+        hasher.update(b"\n");
+        hasher.update(body);
+        hasher.finalize().into()
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Digests of recently seen payloads, a repeated digest is a replayed payload.
+///
+/// Payloads whose sync failed are forgotten again, so the forge's redelivery is processed.
+#[derive(Default)]
+struct Deliveries {
+    seen: HashSet<[u8; 32]>,
+    order: VecDeque<[u8; 32]>,
+}
+
+impl Deliveries {
+    /// Remembers the digest, returns false if it was already seen.
+    fn insert(&mut self, digest: [u8; 32]) -> bool {
+        if !self.seen.insert(digest) {
+            return false;
+        }
+
+        self.order.push_back(digest);
+        if self.order.len() > REMEMBERED_DELIVERIES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// Forgets a digest, so the payload is accepted once more.
+    fn forget(&mut self, digest: &[u8; 32]) {
+        if self.seen.remove(digest) {
+            self.order.retain(|seen| seen != digest);
+        }
+    }
+}
+
+struct HookState {
+    repo_manager: RepositoryManager,
+    deliveries: Mutex<Deliveries>,
+}
+
+impl HookState {
+    fn lock_deliveries(&self) -> std::sync::MutexGuard<'_, Deliveries> {
+        self.deliveries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Route receiving push webhooks of external forges, mounted at `/hooks`.
+///
+/// `POST /hooks/<provider>/<repo>` with provider `github`, `gitea`, `forgejo` or `gitlab`
+/// verifies the payload with the repository's webhook secret and syncs the repository,
+/// which starts builds for its matching triggers. Payloads above 1 MiB are rejected
+/// before verifying them, replayed payloads after. Replays are recognized until the next
+/// restart, and a payload whose sync failed may be redelivered.
+pub fn routes(repo_manager: RepositoryManager) -> impl Endpoint {
+    Route::new()
+        .at("/:provider/:repo", poem::post(receive))
+        .data(Arc::new(HookState {
+            repo_manager,
+            deliveries: Mutex::new(Deliveries::default()),
+        }))
+}
+
+#[handler]
+async fn receive(
+    Path((provider, repo)): Path<(String, String)>,
+    state: Data<&Arc<HookState>>,
+    headers: &HeaderMap,
+    remote_addr: &RemoteAddr,
+    body: Body,
+) -> Response {
+    let provider = match Provider::parse(&provider) {
+        Some(provider) => provider,
+        None => return reply(StatusCode::NOT_FOUND, "Unknown provider"),
+    };
+
+    let secret = match state.repo_manager.get_entry(&repo) {
+        Some(entry) => match entry.webhook_secret {
+            Some(secret) => secret,
+            None => return reply(StatusCode::FORBIDDEN, "Webhooks are not enabled"),
+        },
+        None => return reply(StatusCode::NOT_FOUND, "Repository not found"),
+    };
+
+    let body = match body.into_bytes_limit(MAX_PAYLOAD_SIZE).await {
+        Ok(body) => body,
+        Err(ReadBodyError::PayloadTooLarge) => {
+            return reply(StatusCode::PAYLOAD_TOO_LARGE, "Payload too large")
+        }
+        Err(_) => return reply(StatusCode::BAD_REQUEST, "Failed to read payload"),
+    };
+
+    if !provider.verify(headers, &body, &secret) {
+        tracing::warn!(
+            "rejected {:?} webhook for {} from {}: invalid signature",
+            provider,
+            repo,
+            remote_addr
+        );
+        return reply(StatusCode::UNAUTHORIZED, "Invalid signature");
+    }
+
+    // only verified payloads are remembered, so forged ones can't block real ones
+    let delivery = provider.delivery(headers).unwrap_or("unknown");
+    let digest = provider.payload_digest(headers, &body);
+    let fresh = state.lock_deliveries().insert(digest);
+    if !fresh {
+        tracing::warn!(
+            "rejected {:?} webhook for {} from {}: replayed delivery {}",
+            provider,
+            repo,
+            remote_addr,
+            delivery
+        );
+        return reply(StatusCode::CONFLICT, "Delivery was already processed");
+    }
+
+    let event = provider.event(headers).unwrap_or_default();
+    if !provider.is_push(event) {
+        tracing::debug!("ignoring {:?} event {:?} for {}", provider, event, repo);
+        return reply(StatusCode::OK, "Event ignored");
+    }
+
+    tracing::info!("{:?} push webhook for {}, syncing", provider, repo);
+
+    // forges time out quickly, so answer right away and sync in the background
+    let state = state.0.clone();
+    tokio::spawn(async move {
+        if let Err(e) = state.repo_manager.sync_repo(&repo).await {
+            tracing::error!("Failed to sync {} after webhook ({})", repo, e);
+            // the forge retries failed deliveries, which must not count as replays
+            state.lock_deliveries().forget(&digest);
+        }
+    });
+
+    reply(StatusCode::ACCEPTED, "Sync started")
+}
+
+fn reply(status: StatusCode, message: &str) -> Response {
+    Response::builder().status(status).body(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use poem::test::{TestClient, TestResponse};
+
+    use super::*;
+    use crate::build::{jobs::JobQueue, releases::ReleaseStore};
+    use crate::git::registry::{Registry, RepositoryEntry};
+    use crate::util::{config::Config, depends::Dependencies, file_system::FileSystem};
+
+    const SECRET: &str = "hook secret";
+
+    fn sign(body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn github_signature_is_verified() {
+        let body = br#"{"ref":"refs/heads/main"}"#;
+        let signature = sign(body);
+        let signed = headers(&[("X-Hub-Signature-256", &signature)]);
+
+        assert!(Provider::GitHub.verify(&signed, body, SECRET));
+        assert!(!Provider::GitHub.verify(&signed, b"{}", SECRET));
+        assert!(!Provider::GitHub.verify(&signed, body, "other secret"));
+        assert!(!Provider::GitHub.verify(&HeaderMap::new(), body, SECRET));
+        // GitHub's header carries a prefix, Gitea's does not
+        assert!(!Provider::Gitea.verify(&signed, body, SECRET));
+    }
+
+    #[test]
+    fn gitea_signature_is_verified() {
+        let body = b"payload";
+        let signature = sign(body).trim_start_matches("sha256=").to_string();
+
+        let forgejo = headers(&[("X-Forgejo-Signature", &signature)]);
+        assert!(Provider::Gitea.verify(&forgejo, body, SECRET));
+        let gitea = headers(&[("X-Gitea-Signature", &signature)]);
+        assert!(Provider::Gitea.verify(&gitea, body, SECRET));
+        let garbage = headers(&[("X-Gitea-Signature", "not hex")]);
+        assert!(!Provider::Gitea.verify(&garbage, body, SECRET));
+    }
+
+    #[test]
+    fn gitlab_token_is_compared() {
+        let valid = headers(&[("X-Gitlab-Token", SECRET)]);
+        let invalid = headers(&[("X-Gitlab-Token", "guessed")]);
+
+        assert!(Provider::GitLab.verify(&valid, b"payload", SECRET));
+        assert!(!Provider::GitLab.verify(&invalid, b"payload", SECRET));
+        assert!(!Provider::GitLab.verify(&HeaderMap::new(), b"payload", SECRET));
+    }
+
+    #[test]
+    fn payload_digest_ignores_the_delivery_id() {
+        let body = b"payload";
+        let signature = sign(body);
+        let first = headers(&[
+            ("X-Hub-Signature-256", &signature),
+            ("X-GitHub-Delivery", "1"),
+        ]);
+        let second = headers(&[
+            ("X-Hub-Signature-256", &signature),
+            ("X-GitHub-Delivery", "2"),
+        ]);
+
+        assert_eq!(
+            Provider::GitHub.payload_digest(&first, body),
+            Provider::GitHub.payload_digest(&second, body)
+        );
+        assert_ne!(
+            Provider::GitHub.payload_digest(&first, body),
+            Provider::GitHub.payload_digest(&first, b"other payload")
+        );
+    }
+
+    #[test]
+    fn deliveries_forget_the_oldest_digest() {
+        let mut deliveries = Deliveries::default();
+        let digest = |n: usize| Sha256::digest(n.to_le_bytes()).into();
+
+        assert!(deliveries.insert(digest(0)));
+        assert!(!deliveries.insert(digest(0)));
+        for n in 1..=REMEMBERED_DELIVERIES {
+            assert!(deliveries.insert(digest(n)));
+        }
+        assert!(deliveries.insert(digest(0)));
+        assert!(!deliveries.insert(digest(REMEMBERED_DELIVERIES)));
+
+        deliveries.forget(&digest(0));
+        assert!(deliveries.insert(digest(0)));
+        assert_eq!(deliveries.order.len(), REMEMBERED_DELIVERIES);
+    }
+
+    /// Serves the hooks for a registered repository `repo` with a webhook secret.
+    async fn client(dir: &tempfile::TempDir) -> TestClient<impl Endpoint> {
+        let config = Config {
+            data_dir: dir.path().to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let file_system = FileSystem::new(&config.data_dir);
+        Registry::load(&file_system.registry_path())
+            .unwrap()
+            .insert(RepositoryEntry {
+                name: "repo".to_string(),
+                url: dir.path().join("origin").to_string_lossy().into_owned(),
+                branch: "main".to_string(),
+                credentials: None,
+                sync_interval_secs: 3600,
+                build_method: None,
+                created_at: Utc::now(),
+                triggers: Vec::new(),
+                webhook_secret: Some(SECRET.to_string()),
+                notifications: Vec::new(),
+                last_synced_at: None,
+                last_sync_error: None,
+            })
+            .unwrap();
+
+        let releases = ReleaseStore::load(&file_system).unwrap();
+        let jobs = JobQueue::new(&config, Dependencies::probe(&config).await, releases);
+        let repo_manager = RepositoryManager::new(&config, jobs).unwrap();
+        TestClient::new(routes(repo_manager))
+    }
+
+    async fn deliver(
+        client: &TestClient<impl Endpoint>,
+        delivery: &str,
+        body: &'static [u8],
+    ) -> TestResponse {
+        client
+            .post("/github/repo")
+            .header("X-GitHub-Event", "ping")
+            .header("X-GitHub-Delivery", delivery)
+            .header("X-Hub-Signature-256", sign(body))
+            .body(body)
+            .send()
+            .await
+    }
+
+    #[tokio::test]
+    async fn replayed_payload_is_rejected_under_a_new_delivery_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = client(&dir).await;
+
+        deliver(&client, "1", b"first").await.assert_status_is_ok();
+        deliver(&client, "2", b"first")
+            .await
+            .assert_status(StatusCode::CONFLICT);
+        deliver(&client, "1", b"second").await.assert_status_is_ok();
+    }
+
+    #[tokio::test]
+    async fn payload_whose_sync_failed_can_be_redelivered() {
+        let dir = tempfile::tempdir().unwrap();
+        // the repository was never cloned, so its sync fails
+        let client = client(&dir).await;
+        let push = |delivery: &'static str| {
+            client
+                .post("/github/repo")
+                .header("X-GitHub-Event", "push")
+                .header("X-GitHub-Delivery", delivery)
+                .header("X-Hub-Signature-256", sign(b"push"))
+                .body("push")
+                .send()
+        };
+
+        push("1").await.assert_status(StatusCode::ACCEPTED);
+        let mut redelivered = None;
+        for _ in 0..100 {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            let status = push("1").await.0.status();
+            if status != StatusCode::CONFLICT {
+                redelivered = Some(status);
+                break;
+            }
+        }
+        assert_eq!(redelivered, Some(StatusCode::ACCEPTED));
+    }
+
+    #[tokio::test]
+    async fn forged_payload_is_not_remembered() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = client(&dir).await;
+
+        client
+            .post("/github/repo")
+            .header("X-GitHub-Event", "ping")
+            .header("X-Hub-Signature-256", sign(b"other"))
+            .body("payload")
+            .send()
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        deliver(&client, "1", b"payload")
+            .await
+            .assert_status_is_ok();
+    }
+
+    #[tokio::test]
+    async fn oversized_payload_is_rejected_before_verifying_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = client(&dir).await;
+
+        client
+            .post("/github/repo")
+            .header("X-GitHub-Event", "push")
+            .header("X-Hub-Signature-256", "sha256=00")
+            .body(vec![b'x'; MAX_PAYLOAD_SIZE + 1])
+            .send()
+            .await
+            .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
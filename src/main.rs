@@ -1,66 +1,160 @@
-use std::{env, fs::File, io::prelude::*, process};
+use std::{fs, fs::File, io::prelude::*, path::Path, process};
 
+use clap::Parser as _;
 use color_eyre::eyre::Result;
 use handlebars::Handlebars;
-use poem::{endpoint::StaticFilesEndpoint, listener::TcpListener, Route};
+use poem::{endpoint::StaticFilesEndpoint, listener::TcpListener, EndpointExt, Route};
 use poem_openapi::OpenApiService;
 use pulldown_cmark::{html, Options, Parser};
-use tracing::{debug, error, info, subscriber::set_global_default, Level};
+use tracing::{debug, error, info, subscriber::set_global_default, warn};
 use tracing_subscriber::FmtSubscriber;
 
+use crate::api::auth::{self, TokenStore};
 use crate::api::routes::Api;
+use crate::git::manager::RepositoryManager;
+use crate::util::config::{Cli, Command, Config, LogFormat, SigningKeyAction};
+use crate::util::depends::Dependencies;
+use crate::util::file_system::FileSystem;
+use crate::util::signing::SigningKeys;
 
 mod api;
 mod build;
 mod git;
+mod notify;
 mod util;
 
-// TODO: remove its jsut for dev
-const BASE_PATH: &str = "E:/RepoTests/Repos";
 const API_NAME: &str = "Git";
 const SWAGGER_UI_FILE: &str = "swagger_ui.html";
 const REDOC_FILE: &str = "redoc.html";
 const INSTALLATION_FILE: &str = "installation.html";
 const LICENSE_FILE: &str = "license.html";
-const README_FILE: &str = "README.md";
 const INDEX_FILE: &str = "index.html";
 
-fn setup_tracing() {
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(Level::DEBUG)
-        .finish();
-    set_global_default(subscriber).expect("setting default subscriber failed");
+fn setup_tracing(config: &Config) {
+    let builder = FmtSubscriber::builder().with_max_level(config.log_level());
+
+    let result = match config.log.format {
+        LogFormat::Pretty => set_global_default(builder.finish()),
+        LogFormat::Compact => set_global_default(builder.compact().finish()),
+        LogFormat::Json => set_global_default(builder.json().finish()),
+    };
+    result.expect("setting default subscriber failed");
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    setup_tracing();
+    let cli = Cli::parse();
+
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    };
+
+    // key management works on the data directory alone, without starting the service,
+    // stdout only gets the public key, so it can be redirected to a file
+    if let Some(Command::SigningKey { action }) = cli.command {
+        manage_signing_key(&config, action);
+        return Ok(());
+    }
+
+    setup_tracing(&config);
     info!("Startup!");
+    debug!("Loaded configuration: {:?}", config);
 
     color_eyre::install()?;
     debug!("Eyre installed");
 
-    let api_service = OpenApiService::new(Api::new(BASE_PATH), API_NAME, "1.0").server("/api");
+    // the docs only need the types of the API, so nothing is probed, started or created
+    if let Some(Command::StaticDocs { out }) = &cli.command {
+        let api = Api::new(&config, Dependencies::unprobed(&config))?;
+        let api_service = OpenApiService::new(api, API_NAME, "1.0").server("/api");
+        generate_static_docs(&api_service, out)?;
+        return Ok(());
+    }
+
+    fs::create_dir_all(&config.data_dir)?;
+
+    let dependencies = Dependencies::probe(&config).await;
+    let api = Api::new(&config, dependencies)?;
+    api.start(&config);
+    let repo_manager = api.repo_manager();
+    let tokens = api.tokens();
+    let api_service = OpenApiService::new(api, API_NAME, "1.0").server("/api");
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(&config, api_service, repo_manager, tokens).await,
+        Command::StaticDocs { .. } | Command::SigningKey { .. } => {
+            unreachable!("handled before the service is set up")
+        }
+    }
+}
 
-    if let Some(arg) = env::args_os().nth(1) {
-        if arg == "--static-docs" {
-            generate_static_docs(&api_service)?;
-            return Ok(());
+/// Runs a `signing-key` subcommand, printing the resulting public key to stdout.
+fn manage_signing_key(config: &Config, action: SigningKeyAction) {
+    let keys = SigningKeys::new(&FileSystem::new(&config.data_dir));
+
+    let result = match action {
+        SigningKeyAction::Generate => keys.generate().map(Some),
+        SigningKeyAction::Rotate => keys.rotate().map(Some),
+        SigningKeyAction::Show => keys.current().map(|key| key.map(|key| key.public_key())),
+    };
+
+    match result {
+        Ok(Some(public_key)) => {
+            if action != SigningKeyAction::Show {
+                eprintln!("Signing key {} is in use now", public_key.key_id);
+            }
+            print!("{}", public_key.to_minisign());
+        }
+        Ok(None) => {
+            eprintln!("No signing key, create one with `signing-key generate`");
+            process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
         }
     }
+}
+
+async fn serve(
+    config: &Config,
+    api_service: OpenApiService<Api, ()>,
+    repo_manager: RepositoryManager,
+    tokens: TokenStore,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(created) = tokens.bootstrap()? {
+        // stderr instead of the log, so the secret does not end up in log collectors
+        eprintln!(
+            "Initial admin token, it is shown only once: {}",
+            created.secret
+        );
+        warn!("created the initial admin token {}", created.token.id);
+    }
 
     let app: Route = Route::new()
         .nest("/redoc", api_service.redoc())
         .nest("/docs", api_service.swagger_ui())
-        .nest("/api", api_service)
+        .nest(
+            "/api",
+            api_service.data(tokens.clone()).after(auth::missing_token),
+        )
+        .nest(
+            "/git",
+            git::server::routes(FileSystem::new(&config.data_dir), tokens),
+        )
+        .nest("/hooks", git::webhooks::routes(repo_manager))
         .nest(
             "/",
-            StaticFilesEndpoint::new("src/web/")
+            StaticFilesEndpoint::new(&config.static_dir)
                 .show_files_listing()
                 .index_file(INDEX_FILE),
         );
 
-    if let Err(err) = poem::Server::new(TcpListener::bind("0.0.0.0:8080"))
+    if let Err(err) = poem::Server::new(TcpListener::bind(config.listen.as_str()))
         .run(app)
         .await
     {
@@ -71,20 +165,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn generate_static_docs(api_service: &OpenApiService<Api, ()>) -> Result<()> {
+fn generate_static_docs(api_service: &OpenApiService<Api, ()>, out: &Path) -> Result<()> {
+    fs::create_dir_all(out)?;
+
     let swagger_ui_html_content = api_service.swagger_ui_html();
     let redoc_html_content = api_service.redoc_html();
 
-    write_to_file(SWAGGER_UI_FILE, &swagger_ui_html_content)?;
-    write_to_file(REDOC_FILE, &redoc_html_content)?;
+    write_to_file(&out.join(SWAGGER_UI_FILE), &swagger_ui_html_content)?;
+    write_to_file(&out.join(REDOC_FILE), &redoc_html_content)?;
 
     let installation_md_content = include_str!("../Installation.md");
     let installation_html_content = markdown_to_html_with_line_breaks(installation_md_content)?;
-    write_to_file(INSTALLATION_FILE, &installation_html_content)?;
+    write_to_file(&out.join(INSTALLATION_FILE), &installation_html_content)?;
 
     let license_md_content = include_str!("../LICENSE.md");
     let license_html_content = markdown_to_html_with_line_breaks(license_md_content)?;
-    write_to_file(LICENSE_FILE, &license_html_content)?;
+    write_to_file(&out.join(LICENSE_FILE), &license_html_content)?;
 
     let readme_content = include_str!("../README.md");
     let readme_html_content = markdown_to_html_with_line_breaks(readme_content)?;
@@ -97,14 +193,14 @@ fn generate_static_docs(api_service: &OpenApiService<Api, ()>) -> Result<()> {
     data.insert("readme", &readme_html_content);
     let html_content = handlebars.render("index_template", &data)?;
 
-    write_to_file(INDEX_FILE, &html_content)?;
+    write_to_file(&out.join(INDEX_FILE), &html_content)?;
 
-    info!("Static documentation files generated");
+    info!("Static documentation files generated in {}", out.display());
 
     Ok(())
 }
 
-fn write_to_file(file_name: &str, content: &str) -> Result<()> {
+fn write_to_file(file_name: &Path, content: &str) -> Result<()> {
     let mut file = File::create(file_name)?;
     file.write_all(content.as_bytes())?;
     Ok(())
@@ -0,0 +1,129 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::notify::{deliver, http_client, Notification, Notifier};
+use crate::util::error::Result;
+
+/// Discord notification settings.
+#[derive(Debug, Object, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct DiscordConfig {
+    /// Webhook URL of the channel, never returned as it grants access to the channel.
+    #[oai(write_only)]
+    pub webhook_url: String,
+}
+
+impl DiscordConfig {
+    pub fn notifier(&self) -> DiscordWebhookMessage {
+        DiscordWebhookMessage::new(self.webhook_url.clone())
+    }
+}
+
+/// A rich embed of a Discord message.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscordEmbed {
+    pub title: String,
+    pub description: String,
+    /// Colour of the embed's side bar, as RGB integer.
+    pub color: u32,
+    /// Link of the title.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub fields: Vec<EmbedField>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// A name/value pair shown in an embed.
+#[derive(Debug, Clone, Serialize)]
+pub struct EmbedField {
+    pub name: String,
+    pub value: String,
+    pub inline: bool,
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    embeds: [&'a DiscordEmbed; 1],
+}
+
+/// Represents a Discord webhook message.
+#[derive(Debug, Clone)]
+pub struct DiscordWebhookMessage {
+    /// The URL of the Discord webhook.
+    webhook_url: String,
+    client: Client,
+}
+
+impl DiscordWebhookMessage {
+    /// Creates a new instance of `DiscordWebhookMessage`.
+    ///
+    /// # Arguments
+    ///
+    /// * `webhook_url` - The URL of the Discord webhook.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `DiscordWebhookMessage`.
+    pub fn new(webhook_url: String) -> Self {
+        Self {
+            webhook_url,
+            client: http_client(),
+        }
+    }
+
+    /// Sends an embed to the Discord webhook.
+    ///
+    /// Discord answers `204 No Content` unless asked to wait for the message, any 2xx
+    /// status counts as delivered. Rate limited messages are retried after the
+    /// `retry_after` Discord asks for.
+    ///
+    /// # Arguments
+    ///
+    /// * `embed` - The embed to post.
+    ///
+    /// # Returns
+    ///
+    /// `Ok` once the message is delivered, otherwise an error with the status and
+    /// response body.
+    pub async fn send(&self, embed: &DiscordEmbed) -> Result<()> {
+        let payload = WebhookPayload { embeds: [embed] };
+
+        deliver("Discord", || {
+            self.client.post(&self.webhook_url).json(&payload)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl Notifier for DiscordWebhookMessage {
+    async fn notify(&self, notification: &Notification) -> Result<()> {
+        let mut description = notification.summary.clone();
+        if let Some(log_url) = &notification.log_url {
+            description.push_str(&format!("\n[Build log]({})", log_url));
+        }
+
+        let fields = notification
+            .fields()
+            .into_iter()
+            .map(|(name, value)| EmbedField {
+                name: name.to_string(),
+                value,
+                inline: true,
+            })
+            .collect();
+
+        self.send(&DiscordEmbed {
+            title: notification.title.clone(),
+            description,
+            color: notification.event.color(),
+            url: notification.log_url.clone(),
+            fields,
+            timestamp: Some(notification.timestamp()),
+        })
+        .await
+    }
+}
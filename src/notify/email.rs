@@ -0,0 +1,132 @@
+use async_trait::async_trait;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+
+use crate::notify::{Notification, Notifier};
+use crate::util::error::{Error, Result};
+
+/// How the connection to the SMTP server is secured.
+#[derive(Debug, Enum, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Default)]
+#[oai(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain connection upgraded with STARTTLS, port 587 by default.
+    #[default]
+    StartTls,
+    /// TLS from the start, port 465 by default.
+    Tls,
+    /// Unencrypted, port 25 by default. Only meant for local relays.
+    None,
+}
+
+/// Email notification settings.
+#[derive(Debug, Object, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct EmailConfig {
+    /// Host name of the SMTP server.
+    pub smtp_host: String,
+    /// Port of the SMTP server, the default of `security` if not set.
+    pub smtp_port: Option<u16>,
+    #[oai(default)]
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    /// User to authenticate as, no authentication if not set.
+    pub username: Option<String>,
+    /// Password of `username`, never returned.
+    #[oai(write_only)]
+    pub password: Option<String>,
+    /// Sender address, e.g. `Builds <builds@example.com>`.
+    pub from: String,
+    /// Recipient addresses.
+    pub to: Vec<String>,
+}
+
+impl EmailConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.smtp_host.trim().is_empty() {
+            return Err(Error::Invalid("SMTP host must not be empty".to_string()));
+        }
+        self.notifier().map(|_| ())
+    }
+
+    pub fn notifier(&self) -> Result<EmailNotifier> {
+        let from = parse_mailbox(&self.from)?;
+        let to = self
+            .to
+            .iter()
+            .map(|address| parse_mailbox(address))
+            .collect::<Result<Vec<_>>>()?;
+        if to.is_empty() {
+            return Err(Error::Invalid(
+                "Email notifications need at least one recipient".to_string(),
+            ));
+        }
+
+        let builder = match self.security {
+            SmtpSecurity::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.smtp_host)
+            }
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&self.smtp_host),
+            SmtpSecurity::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                &self.smtp_host,
+            )),
+        }
+        .map_err(|e| Error::Invalid(format!("Invalid SMTP host {}: {}", self.smtp_host, e)))?;
+
+        let mut builder = match self.smtp_port {
+            Some(port) => builder.port(port),
+            None => builder,
+        };
+        if let Some(username) = &self.username {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                self.password.clone().unwrap_or_default(),
+            ));
+        }
+
+        Ok(EmailNotifier {
+            transport: builder.build(),
+            from,
+            to,
+        })
+    }
+}
+
+fn parse_mailbox(address: &str) -> Result<Mailbox> {
+    address
+        .parse()
+        .map_err(|e| Error::Invalid(format!("Invalid email address {:?}: {}", address, e)))
+}
+
+/// Sends notifications as plain text emails.
+pub struct EmailNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    async fn notify(&self, notification: &Notification) -> Result<()> {
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .subject(&notification.title)
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        let message = message
+            .body(notification.text())
+            .map_err(|e| Error::Notification(format!("Failed to build email: {}", e)))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| Error::Notification(format!("Failed to send email via SMTP: {}", e)))
+    }
+}
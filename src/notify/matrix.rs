@@ -0,0 +1,117 @@
+use async_trait::async_trait;
+use handlebars::html_escape;
+use poem_openapi::Object;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::notify::{deliver, http_client, validate_url, Notification, Notifier};
+use crate::util::error::{Error, Result};
+
+/// Matrix notification settings.
+#[derive(Debug, Object, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct MatrixConfig {
+    /// Base URL of the homeserver, e.g. `https://matrix.example.org`.
+    pub homeserver: String,
+    /// Id of the room, e.g. `!abcdef:example.org`. The account must have joined it.
+    pub room_id: String,
+    /// Access token of the sending account, never returned.
+    #[oai(write_only)]
+    pub access_token: String,
+}
+
+impl MatrixConfig {
+    pub fn validate(&self) -> Result<()> {
+        validate_url(&self.homeserver)?;
+        if !self.room_id.starts_with('!') || !self.room_id.contains(':') {
+            return Err(Error::Invalid(format!(
+                "Invalid Matrix room id {:?}, expected !<id>:<server>",
+                self.room_id
+            )));
+        }
+        if self.access_token.is_empty() {
+            return Err(Error::Invalid(
+                "Matrix access token must not be empty".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn notifier(&self) -> MatrixNotifier {
+        MatrixNotifier {
+            config: self.clone(),
+            client: http_client(),
+        }
+    }
+}
+
+/// Sends notifications as notices to a Matrix room over the client-server API.
+pub struct MatrixNotifier {
+    config: MatrixConfig,
+    client: Client,
+}
+
+impl MatrixNotifier {
+    /// URL of a new message event, `txn_id` makes retries of the same message idempotent.
+    fn send_url(&self, txn_id: &str) -> Result<Url> {
+        let mut url = Url::parse(&self.config.homeserver)
+            .map_err(|e| Error::Invalid(format!("Invalid Matrix homeserver: {}", e)))?;
+        url.path_segments_mut()
+            .map_err(|_| Error::Invalid("Invalid Matrix homeserver".to_string()))?
+            .pop_if_empty()
+            .extend([
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                &self.config.room_id,
+                "send",
+                "m.room.message",
+                txn_id,
+            ]);
+        Ok(url)
+    }
+}
+
+#[async_trait]
+impl Notifier for MatrixNotifier {
+    async fn notify(&self, notification: &Notification) -> Result<()> {
+        let mut html = format!(
+            "<strong>{}</strong><br>{}<ul>",
+            html_escape(&notification.title),
+            html_escape(&notification.summary)
+        );
+        for (name, value) in notification.fields() {
+            html.push_str(&format!(
+                "<li>{}: <code>{}</code></li>",
+                name,
+                html_escape(&value)
+            ));
+        }
+        html.push_str("</ul>");
+        if let Some(log_url) = &notification.log_url {
+            html.push_str(&format!(
+                "<a href=\"{}\">Build log</a>",
+                html_escape(log_url)
+            ));
+        }
+
+        let payload = json!({
+            // notices don't trigger bots and are shown less prominently
+            "msgtype": "m.notice",
+            "body": notification.text(),
+            "format": "org.matrix.custom.html",
+            "formatted_body": html,
+        });
+        let url = self.send_url(&Uuid::new_v4().to_string())?;
+
+        deliver("Matrix", || {
+            self.client
+                .put(url.clone())
+                .bearer_auth(&self.config.access_token)
+                .json(&payload)
+        })
+        .await
+    }
+}
@@ -0,0 +1,508 @@
+pub mod discord;
+pub mod email;
+pub mod matrix;
+pub mod slack;
+pub mod webhook;
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object, Union};
+use reqwest::{header::RETRY_AFTER, RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::build::jobs::{Job, JobQueue, JobStatus};
+use crate::git::manager::RepositoryManager;
+use crate::util::config::Config;
+use crate::util::error::{Error, Result};
+
+use self::discord::DiscordConfig;
+use self::email::EmailConfig;
+use self::matrix::MatrixConfig;
+use self::slack::SlackConfig;
+use self::webhook::WebhookConfig;
+
+/// Attempts made to deliver a notification while the target rate limits it.
+const MAX_ATTEMPTS: u32 = 3;
+/// Longest rate limit waited for, longer ones fail the delivery.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+/// Time a target gets to answer a request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Characters of an error message shown in a notification, chat services limit messages.
+const MAX_MESSAGE_CHARS: usize = 1024;
+
+/// Points in the life of a build notifications are sent for.
+#[derive(Debug, Enum, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[oai(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum BuildEvent {
+    Started,
+    Succeeded,
+    Failed,
+    /// A build of a tag succeeded, sent after `succeeded`.
+    ReleasePublished,
+}
+
+impl BuildEvent {
+    /// Events a job update stands for, none for queued or cancelled jobs.
+    pub fn of(job: &Job) -> Vec<BuildEvent> {
+        match job.status {
+            JobStatus::Running => vec![BuildEvent::Started],
+            JobStatus::Succeeded if job.release_tag.is_some() => {
+                vec![BuildEvent::Succeeded, BuildEvent::ReleasePublished]
+            }
+            JobStatus::Succeeded => vec![BuildEvent::Succeeded],
+            JobStatus::Failed => vec![BuildEvent::Failed],
+            JobStatus::Queued | JobStatus::Cancelled => Vec::new(),
+        }
+    }
+
+    /// Status colour as RGB integer.
+    pub fn color(&self) -> u32 {
+        match self {
+            BuildEvent::Started => 0x3498db,
+            BuildEvent::Succeeded => 0x2ecc71,
+            BuildEvent::Failed => 0xe74c3c,
+            BuildEvent::ReleasePublished => 0x9b59b6,
+        }
+    }
+}
+
+/// A build event as handed to the notifiers, with everything they show.
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub event: BuildEvent,
+    pub title: String,
+    /// The error of a failed build, the job id otherwise.
+    pub summary: String,
+    /// Link to the job log, if `public_url` is configured.
+    pub log_url: Option<String>,
+    pub duration_secs: Option<i64>,
+    pub job: Job,
+}
+
+impl Notification {
+    pub fn new(event: BuildEvent, job: &Job, public_url: Option<&str>) -> Self {
+        let title = match event {
+            BuildEvent::Started => format!("Build of {} started", job.repository),
+            BuildEvent::Succeeded => format!("Build of {} succeeded", job.repository),
+            BuildEvent::Failed => format!("Build of {} failed", job.repository),
+            BuildEvent::ReleasePublished => format!(
+                "{} {} released",
+                job.repository,
+                job.release_tag.as_deref().unwrap_or_default()
+            ),
+        };
+
+        let summary = match (event, &job.message) {
+            (BuildEvent::Failed, Some(message)) => {
+                message.chars().take(MAX_MESSAGE_CHARS).collect()
+            }
+            _ => format!("Job {}", job.id),
+        };
+
+        let duration_secs = match (job.started_at, job.finished_at) {
+            (Some(started_at), Some(finished_at)) => {
+                Some((finished_at - started_at).num_seconds().max(0))
+            }
+            _ => None,
+        };
+
+        Notification {
+            event,
+            title,
+            summary,
+            log_url: public_url.map(|url| format!("{}/api/jobs/{}/log", url, job.id)),
+            duration_secs,
+            job: job.clone(),
+        }
+    }
+
+    /// Facts about the build shown next to the summary, as name and value.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            ("Repository", self.job.repository.clone()),
+            (
+                "Ref",
+                self.job
+                    .git_ref
+                    .clone()
+                    .unwrap_or_else(|| "HEAD".to_string()),
+            ),
+            ("Commit", self.job.commit.clone()),
+            ("Method", self.job.method.clone()),
+        ];
+
+        if let Some(secs) = self.duration_secs {
+            fields.push(("Duration", format!("{}m {:02}s", secs / 60, secs % 60)));
+        }
+        if let Some(trigger) = &self.job.trigger {
+            fields.push(("Trigger", trigger.clone()));
+        }
+
+        fields
+    }
+
+    /// Plain text rendering for targets without rich formatting.
+    pub fn text(&self) -> String {
+        let mut lines = vec![self.title.clone(), self.summary.clone(), String::new()];
+        for (name, value) in self.fields() {
+            lines.push(format!("{}: {}", name, value));
+        }
+        if let Some(log_url) = &self.log_url {
+            lines.push(format!("Log: {}", log_url));
+        }
+        lines.join("\n")
+    }
+
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.job
+            .finished_at
+            .or(self.job.started_at)
+            .unwrap_or(self.job.created_at)
+    }
+}
+
+/// A target build notifications are delivered to.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn notify(&self, notification: &Notification) -> Result<()>;
+}
+
+/// Settings of a notification target, tagged by its `type`.
+#[derive(Debug, Union, Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[oai(discriminator_name = "type", one_of)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NotifierConfig {
+    /// Discord channel webhook.
+    #[oai(mapping = "discord")]
+    Discord(DiscordConfig),
+    /// Slack incoming webhook.
+    #[oai(mapping = "slack")]
+    Slack(SlackConfig),
+    /// Message in a Matrix room.
+    #[oai(mapping = "matrix")]
+    Matrix(MatrixConfig),
+    /// JSON request to any URL, with an optional Handlebars body template.
+    #[oai(mapping = "webhook")]
+    Webhook(WebhookConfig),
+    /// Email sent over SMTP.
+    #[oai(mapping = "email")]
+    Email(EmailConfig),
+}
+
+impl NotifierConfig {
+    /// Checks the settings, so broken targets are rejected when they are configured.
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            NotifierConfig::Discord(config) => validate_url(&config.webhook_url),
+            NotifierConfig::Slack(config) => validate_url(&config.webhook_url),
+            NotifierConfig::Matrix(config) => config.validate(),
+            NotifierConfig::Webhook(config) => config.validate(),
+            NotifierConfig::Email(config) => config.validate(),
+        }
+    }
+
+    pub fn notifier(&self) -> Result<Box<dyn Notifier>> {
+        Ok(match self {
+            NotifierConfig::Discord(config) => Box::new(config.notifier()),
+            NotifierConfig::Slack(config) => Box::new(config.notifier()),
+            NotifierConfig::Matrix(config) => Box::new(config.notifier()),
+            NotifierConfig::Webhook(config) => Box::new(config.notifier()?),
+            NotifierConfig::Email(config) => Box::new(config.notifier()?),
+        })
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            NotifierConfig::Discord(_) => "discord",
+            NotifierConfig::Slack(_) => "slack",
+            NotifierConfig::Matrix(_) => "matrix",
+            NotifierConfig::Webhook(_) => "webhook",
+            NotifierConfig::Email(_) => "email",
+        }
+    }
+}
+
+/// Sends some events of a repository's builds to a notifier.
+#[derive(Debug, Object, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct NotificationRoute {
+    /// Events sent to the notifier, all events if empty.
+    #[oai(default)]
+    #[serde(default)]
+    pub events: Vec<BuildEvent>,
+    pub notifier: NotifierConfig,
+}
+
+impl NotificationRoute {
+    pub fn wants(&self, event: BuildEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+/// Delivers the updates of every job to the notifiers its repository routes the events to.
+///
+/// Runs until the job queue is dropped. Notifications are sent one after the other, so they
+/// arrive in the order the events happened even while a target rate limits them.
+pub fn spawn(config: &Config, repo_manager: RepositoryManager, jobs: &JobQueue) {
+    let mut updates = jobs.subscribe();
+    let public_url = config
+        .public_url
+        .as_ref()
+        .map(|url| url.trim_end_matches('/').to_string());
+
+    tokio::spawn(async move {
+        loop {
+            let job = match updates.recv().await {
+                Ok(job) => job,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("dropped {} build notifications", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            let routes = match repo_manager.get_entry(&job.repository) {
+                Some(entry) => entry.notifications,
+                None => continue,
+            };
+
+            for event in BuildEvent::of(&job) {
+                let notification = Notification::new(event, &job, public_url.as_deref());
+
+                for route in routes.iter().filter(|route| route.wants(event)) {
+                    let result = match route.notifier.notifier() {
+                        Ok(notifier) => notifier.notify(&notification).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = result {
+                        tracing::error!(
+                            "Failed to send {} notification about job {} ({:?}): {}",
+                            route.notifier.kind(),
+                            job.id,
+                            event,
+                            e
+                        );
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// HTTP client shared by the notifiers sending requests.
+pub(crate) fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .unwrap_or_default()
+}
+
+pub(crate) fn validate_url(url: &str) -> Result<()> {
+    match reqwest::Url::parse(url) {
+        Ok(url) if url.scheme() == "https" || url.scheme() == "http" => Ok(()),
+        _ => Err(Error::Invalid(format!(
+            "Invalid URL {:?}, expected an http or https URL",
+            url
+        ))),
+    }
+}
+
+/// Body of a 429 response, Discord sends seconds, Matrix milliseconds.
+#[derive(Deserialize)]
+struct RateLimit {
+    retry_after: Option<f64>,
+    retry_after_ms: Option<u64>,
+}
+
+/// Sends the request built by `request` until the target accepts it.
+///
+/// Any 2xx status counts as delivered. A `429 Too Many Requests` is retried after the
+/// time the target asks for (the `retry_after` body field or the `Retry-After` header),
+/// up to three attempts; any other status is an error with the response body.
+pub(crate) async fn deliver(target: &str, request: impl Fn() -> RequestBuilder) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let response = request()
+            .send()
+            .await
+            .map_err(|e| Error::Notification(format!("Failed to reach {}: {}", target, e)))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let header_retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<f64>().ok());
+        let body = response.text().await.unwrap_or_default();
+
+        if status != StatusCode::TOO_MANY_REQUESTS {
+            return Err(Error::Notification(format!(
+                "{} rejected the notification with {}: {}",
+                target,
+                status,
+                body.trim()
+            )));
+        }
+
+        // the body is more precise than the header's whole seconds
+        let retry_after = serde_json::from_str::<RateLimit>(&body)
+            .ok()
+            .and_then(|limit| {
+                limit
+                    .retry_after
+                    .or(limit.retry_after_ms.map(|ms| ms as f64 / 1000.0))
+            })
+            .or(header_retry_after)
+            .filter(|secs| secs.is_finite() && *secs >= 0.0)
+            .map(Duration::from_secs_f64)
+            .unwrap_or(Duration::from_secs(1));
+
+        if attempt == MAX_ATTEMPTS || retry_after > MAX_RETRY_AFTER {
+            return Err(Error::Notification(format!(
+                "{} rate limited the notification, retry after {:.1}s",
+                target,
+                retry_after.as_secs_f64()
+            )));
+        }
+
+        tracing::debug!(
+            "{} rate limited the notification, retrying in {:.1}s",
+            target,
+            retry_after.as_secs_f64()
+        );
+        tokio::time::sleep(retry_after).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Answers the requests to the returned URL with `responses` in turn,
+    /// and counts the requests received.
+    async fn serve(responses: Vec<&'static str>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let received = requests.clone();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                read_request(&mut stream).await;
+                received.fetch_add(1, Ordering::SeqCst);
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+
+        (url, requests)
+    }
+
+    /// Reads the head and the body of a request, so answering doesn't reset the connection.
+    async fn read_request(stream: &mut tokio::net::TcpStream) {
+        let mut request = Vec::new();
+        let mut buffer = [0; 4096];
+        loop {
+            let read = stream.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some(head_end) = text.find("\r\n\r\n") {
+                let length = text[..head_end]
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                if request.len() >= head_end + 4 + length {
+                    return;
+                }
+            }
+            if read == 0 {
+                return;
+            }
+        }
+    }
+
+    const RATE_LIMITED: &str = "HTTP/1.1 429 Too Many Requests\r\nconnection: close\r\ncontent-length: 22\r\n\r\n{\"retry_after\": 0.01}\n";
+    const RATE_LIMITED_LONG: &str = "HTTP/1.1 429 Too Many Requests\r\nconnection: close\r\nretry-after: 120\r\ncontent-length: 0\r\n\r\n";
+    const ACCEPTED: &str = "HTTP/1.1 204 No Content\r\nconnection: close\r\n\r\n";
+    const REJECTED: &str =
+        "HTTP/1.1 400 Bad Request\r\nconnection: close\r\ncontent-length: 11\r\n\r\nbad payload";
+
+    async fn deliver_to(url: &str) -> Result<()> {
+        let client = http_client();
+        deliver("Test", || client.post(url).json(&serde_json::json!({}))).await
+    }
+
+    #[tokio::test]
+    async fn rate_limited_notifications_are_retried() {
+        let (url, requests) = serve(vec![RATE_LIMITED, ACCEPTED]).await;
+
+        deliver_to(&url).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_stop_after_three_attempts() {
+        let (url, requests) = serve(vec![RATE_LIMITED; 3]).await;
+
+        let err = deliver_to(&url).await.unwrap_err();
+        assert!(matches!(&err, Error::Notification(message) if message.contains("rate limited")));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn long_rate_limits_and_rejections_fail_at_once() {
+        let (url, requests) = serve(vec![RATE_LIMITED_LONG]).await;
+        let err = deliver_to(&url).await.unwrap_err();
+        assert!(err.message().contains("retry after 120.0s"), "{}", err);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let (url, requests) = serve(vec![REJECTED]).await;
+        let err = deliver_to(&url).await.unwrap_err();
+        assert!(matches!(&err, Error::Notification(message)
+            if message.contains("400") && message.contains("bad payload")));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected_as_invalid_requests() {
+        let discord = NotifierConfig::Discord(DiscordConfig {
+            webhook_url: "ftp://example.org/hook".to_string(),
+        });
+        assert!(matches!(discord.validate(), Err(Error::Invalid(_))));
+
+        let webhook = NotifierConfig::Webhook(WebhookConfig {
+            url: "https://example.org/hook".to_string(),
+            template: Some("{{#if}}".to_string()),
+            headers: Default::default(),
+        });
+        assert!(matches!(webhook.validate(), Err(Error::Invalid(_))));
+
+        let slack = NotifierConfig::Slack(SlackConfig {
+            webhook_url: "https://hooks.slack.com/services/x".to_string(),
+        });
+        assert_eq!(slack.validate(), Ok(()));
+    }
+}
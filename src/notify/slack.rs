@@ -0,0 +1,60 @@
+use async_trait::async_trait;
+use poem_openapi::Object;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::notify::{deliver, http_client, Notification, Notifier};
+use crate::util::error::Result;
+
+/// Slack notification settings.
+#[derive(Debug, Object, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct SlackConfig {
+    /// URL of the incoming webhook, never returned as it grants access to the channel.
+    #[oai(write_only)]
+    pub webhook_url: String,
+}
+
+impl SlackConfig {
+    pub fn notifier(&self) -> SlackNotifier {
+        SlackNotifier {
+            webhook_url: self.webhook_url.clone(),
+            client: http_client(),
+        }
+    }
+}
+
+/// Posts notifications to a Slack incoming webhook as coloured attachments.
+pub struct SlackNotifier {
+    webhook_url: String,
+    client: Client,
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    async fn notify(&self, notification: &Notification) -> Result<()> {
+        let fields: Vec<_> = notification
+            .fields()
+            .into_iter()
+            .map(|(name, value)| json!({ "title": name, "value": value, "short": true }))
+            .collect();
+
+        let payload = json!({
+            // shown in push notifications and clients without attachment support
+            "text": notification.title,
+            "attachments": [{
+                "color": format!("#{:06x}", notification.event.color()),
+                "title": notification.title,
+                "title_link": notification.log_url,
+                "text": notification.summary,
+                "fields": fields,
+                "ts": notification.timestamp().timestamp(),
+            }],
+        });
+
+        deliver("Slack", || {
+            self.client.post(&self.webhook_url).json(&payload)
+        })
+        .await
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use handlebars::Handlebars;
+use poem_openapi::Object;
+use reqwest::{header::CONTENT_TYPE, Client};
+use serde::{Deserialize, Serialize};
+
+use crate::build::jobs::JobStatus;
+use crate::notify::{deliver, http_client, validate_url, BuildEvent, Notification, Notifier};
+use crate::util::error::{Error, Result};
+
+const TEMPLATE_NAME: &str = "body";
+
+/// Generic webhook notification settings.
+///
+/// Without a template the request body is the JSON of all values a template can use:
+/// `event`, `title`, `summary`, `repository`, `git_ref`, `commit`, `method`, `status`,
+/// `job_id`, `trigger`, `release_tag`, `message`, `exit_code`, `duration_secs`, `log_url`,
+/// `created_at`, `started_at` and `finished_at`.
+#[derive(Debug, Object, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct WebhookConfig {
+    /// URL the notification is posted to.
+    pub url: String,
+    /// Handlebars template of the JSON body, e.g. `{"text": "{{title}}"}`.
+    /// Values are escaped for use inside JSON strings.
+    pub template: Option<String>,
+    /// Extra request headers, e.g. for authentication, never returned.
+    #[oai(write_only, default)]
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl WebhookConfig {
+    pub fn validate(&self) -> Result<()> {
+        validate_url(&self.url)?;
+        for (name, value) in &self.headers {
+            if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err()
+                || reqwest::header::HeaderValue::from_str(value).is_err()
+            {
+                return Err(Error::Invalid(format!("Invalid webhook header {:?}", name)));
+            }
+        }
+        self.notifier().map(|_| ())
+    }
+
+    pub fn notifier(&self) -> Result<WebhookNotifier> {
+        let mut templates = Handlebars::new();
+        templates.set_strict_mode(true);
+        templates.register_escape_fn(escape_json);
+        if let Some(template) = &self.template {
+            templates
+                .register_template_string(TEMPLATE_NAME, template)
+                .map_err(|e| Error::Invalid(format!("Invalid webhook template: {}", e)))?;
+        }
+
+        Ok(WebhookNotifier {
+            config: self.clone(),
+            templates,
+            client: http_client(),
+        })
+    }
+}
+
+/// Escapes a value for a JSON string, so templates stay valid JSON whatever the value.
+fn escape_json(value: &str) -> String {
+    let quoted = serde_json::to_string(value).unwrap_or_default();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+/// Values available to the body template.
+#[derive(Serialize)]
+struct TemplateContext<'a> {
+    event: BuildEvent,
+    title: &'a str,
+    summary: &'a str,
+    repository: &'a str,
+    git_ref: Option<&'a str>,
+    commit: &'a str,
+    method: &'a str,
+    status: &'a str,
+    job_id: &'a str,
+    trigger: Option<&'a str>,
+    release_tag: Option<&'a str>,
+    message: Option<&'a str>,
+    exit_code: Option<i32>,
+    duration_secs: Option<i64>,
+    log_url: Option<&'a str>,
+    created_at: DateTime<Utc>,
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+/// Posts notifications as JSON to a URL of choice.
+pub struct WebhookNotifier {
+    config: WebhookConfig,
+    templates: Handlebars<'static>,
+    client: Client,
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    async fn notify(&self, notification: &Notification) -> Result<()> {
+        let job = &notification.job;
+        let context = TemplateContext {
+            event: notification.event,
+            title: &notification.title,
+            summary: &notification.summary,
+            repository: &job.repository,
+            git_ref: job.git_ref.as_deref(),
+            commit: &job.commit,
+            method: &job.method,
+            status: match job.status {
+                JobStatus::Queued => "queued",
+                JobStatus::Running => "running",
+                JobStatus::Succeeded => "succeeded",
+                JobStatus::Failed => "failed",
+                JobStatus::Cancelled => "cancelled",
+            },
+            job_id: &job.id,
+            trigger: job.trigger.as_deref(),
+            release_tag: job.release_tag.as_deref(),
+            message: job.message.as_deref(),
+            exit_code: job.exit_code,
+            duration_secs: notification.duration_secs,
+            log_url: notification.log_url.as_deref(),
+            created_at: job.created_at,
+            started_at: job.started_at,
+            finished_at: job.finished_at,
+        };
+
+        let body = if self.config.template.is_some() {
+            self.templates
+                .render(TEMPLATE_NAME, &context)
+                .map_err(|e| {
+                    Error::Notification(format!("Failed to render webhook template: {}", e))
+                })?
+        } else {
+            serde_json::to_string(&context)
+                .map_err(|e| Error::Io(format!("Failed to serialize notification: {}", e)))?
+        };
+
+        deliver("Webhook", || {
+            let mut request = self
+                .client
+                .post(&self.config.url)
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            for (name, value) in &self.config.headers {
+                request = request.header(name, value);
+            }
+            request
+        })
+        .await
+    }
+}
@@ -0,0 +1,432 @@
+use std::{
+    fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+use tracing::Level;
+
+use crate::build::digests::DigestAlgorithm;
+use crate::util::error::{Error, Result};
+
+/// Config file read when no `--config` is given, skipped if it does not exist.
+const DEFAULT_CONFIG_FILE: &str = "release_workflows.toml";
+
+/// Manages git repositories and builds releases from them.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Path to the TOML configuration file.
+    #[arg(long, short, global = true, env = "RELEASE_WORKFLOWS_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub overrides: Overrides,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the HTTP server (default).
+    Serve,
+    /// Write the API documentation as static HTML files.
+    StaticDocs {
+        /// Directory the HTML files are written to.
+        #[arg(long, default_value = ".")]
+        out: PathBuf,
+    },
+    /// Manage the key build outputs are signed with.
+    SigningKey {
+        #[command(subcommand)]
+        action: SigningKeyAction,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Subcommand, Eq, PartialEq)]
+pub enum SigningKeyAction {
+    /// Generate the signing key, build outputs are not signed without one.
+    Generate,
+    /// Replace the signing key with a new one, the old public key stays listed.
+    Rotate,
+    /// Print the current public key in minisign format.
+    Show,
+}
+
+/// Settings that can be given as CLI flags or environment variables,
+/// taking precedence over the configuration file.
+#[derive(Debug, Args)]
+pub struct Overrides {
+    /// Directory holding repositories and service state.
+    #[arg(long, global = true, env = "RELEASE_WORKFLOWS_DATA_DIR")]
+    pub data_dir: Option<String>,
+
+    /// Address the HTTP server listens on.
+    #[arg(long, global = true, env = "RELEASE_WORKFLOWS_LISTEN")]
+    pub listen: Option<String>,
+
+    /// Directory served as static web content.
+    #[arg(long, global = true, env = "RELEASE_WORKFLOWS_STATIC_DIR")]
+    pub static_dir: Option<String>,
+
+    /// Log level (trace, debug, info, warn, error).
+    #[arg(long, global = true, env = "RELEASE_WORKFLOWS_LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    /// Log output format.
+    #[arg(long, global = true, env = "RELEASE_WORKFLOWS_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+
+    /// Default seconds between background syncs of a repository.
+    #[arg(long, global = true, env = "RELEASE_WORKFLOWS_SYNC_INTERVAL")]
+    pub sync_interval: Option<u64>,
+
+    /// Container engine binary used for docker and cargo builds.
+    #[arg(long, global = true, env = "RELEASE_WORKFLOWS_DOCKER_BINARY")]
+    pub docker_binary: Option<String>,
+
+    /// Number of builds running at the same time.
+    #[arg(long, global = true, env = "RELEASE_WORKFLOWS_BUILD_WORKERS")]
+    pub build_workers: Option<usize>,
+
+    /// URL the service is reachable at, used for links in notifications.
+    #[arg(long, global = true, env = "RELEASE_WORKFLOWS_PUBLIC_URL")]
+    pub public_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, ValueEnum, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Pretty,
+    Compact,
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: "info".to_string(),
+            format: LogFormat::Pretty,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncConfig {
+    /// Seconds between background syncs, used when a repository doesn't set its own.
+    pub interval_secs: u64,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        SyncConfig {
+            interval_secs: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DockerConfig {
+    /// Binary of the container engine, `docker` or a compatible one like `podman`.
+    pub binary: String,
+    /// Image the cargo method builds in, unless the repository pins a toolchain version.
+    pub rust_image: String,
+}
+
+impl Default for DockerConfig {
+    fn default() -> Self {
+        DockerConfig {
+            binary: "docker".to_string(),
+            rust_image: "rust:latest".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BuildConfig {
+    /// Number of builds running at the same time.
+    pub workers: usize,
+    /// Maximum number of queued builds, further builds are rejected.
+    pub queue_size: usize,
+    /// Digests computed for build outputs in addition to SHA-256.
+    pub digests: Vec<DigestAlgorithm>,
+}
+
+impl Default for BuildConfig {
+    fn default() -> Self {
+        BuildConfig {
+            workers: 2,
+            queue_size: 64,
+            digests: Vec::new(),
+        }
+    }
+}
+
+/// Service configuration, merged from defaults, the config file,
+/// environment variables and CLI flags (in increasing precedence).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub data_dir: String,
+    pub listen: String,
+    pub static_dir: String,
+    /// URL the service is reachable at, e.g. `https://builds.example.com`.
+    /// Notifications link to job logs below it, they carry no links if unset.
+    pub public_url: Option<String>,
+    pub log: LogConfig,
+    pub sync: SyncConfig,
+    pub docker: DockerConfig,
+    pub build: BuildConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            data_dir: "data".to_string(),
+            listen: "0.0.0.0:8080".to_string(),
+            static_dir: "src/web/".to_string(),
+            public_url: None,
+            log: LogConfig::default(),
+            sync: SyncConfig::default(),
+            docker: DockerConfig::default(),
+            build: BuildConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration for the given command line.
+    ///
+    /// A config file given explicitly must exist, the default one is optional.
+    pub fn load(cli: &Cli) -> Result<Self> {
+        let default_file = Path::new(DEFAULT_CONFIG_FILE);
+
+        let mut config = match &cli.config {
+            Some(path) => Config::from_file(path)?,
+            None if default_file.exists() => Config::from_file(default_file)?,
+            None => Config::default(),
+        };
+
+        config.apply(&cli.overrides);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => {
+                Error::Config(format!("Config file not found: {}", path.display()))
+            }
+            _ => Error::Io(format!(
+                "Failed to read config file {}: {}",
+                path.display(),
+                e
+            )),
+        })?;
+
+        toml::from_str(&content)
+            .map_err(|e| Error::Config(format!("Invalid config file {}: {}", path.display(), e)))
+    }
+
+    fn apply(&mut self, overrides: &Overrides) {
+        if let Some(data_dir) = &overrides.data_dir {
+            self.data_dir = data_dir.clone();
+        }
+        if let Some(listen) = &overrides.listen {
+            self.listen = listen.clone();
+        }
+        if let Some(static_dir) = &overrides.static_dir {
+            self.static_dir = static_dir.clone();
+        }
+        if let Some(public_url) = &overrides.public_url {
+            self.public_url = Some(public_url.clone());
+        }
+        if let Some(level) = &overrides.log_level {
+            self.log.level = level.clone();
+        }
+        if let Some(format) = overrides.log_format {
+            self.log.format = format;
+        }
+        if let Some(interval) = overrides.sync_interval {
+            self.sync.interval_secs = interval;
+        }
+        if let Some(binary) = &overrides.docker_binary {
+            self.docker.binary = binary.clone();
+        }
+        if let Some(workers) = overrides.build_workers {
+            self.build.workers = workers;
+        }
+    }
+
+    /// Checks all settings, reporting every invalid one at once.
+    fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        if self.data_dir.trim().is_empty() {
+            errors.push("data_dir must not be empty".to_string());
+        }
+        if let Err(e) = SocketAddr::from_str(&self.listen) {
+            errors.push(format!(
+                "listen address {:?} is invalid: {}",
+                self.listen, e
+            ));
+        }
+        if let Some(public_url) = &self.public_url {
+            if !public_url.starts_with("http://") && !public_url.starts_with("https://") {
+                errors.push(format!(
+                    "public_url {:?} must be an http or https URL",
+                    public_url
+                ));
+            }
+        }
+        if Level::from_str(&self.log.level).is_err() {
+            errors.push(format!(
+                "log level {:?} is invalid, expected one of trace, debug, info, warn, error",
+                self.log.level
+            ));
+        }
+        if self.sync.interval_secs == 0 {
+            errors.push("sync interval must be at least one second".to_string());
+        }
+        if self.docker.binary.trim().is_empty() {
+            errors.push("docker binary must not be empty".to_string());
+        }
+        if self.docker.rust_image.trim().is_empty() {
+            errors.push("docker rust_image must not be empty".to_string());
+        }
+
+        if self.build.workers == 0 {
+            errors.push("at least one build worker is required".to_string());
+        }
+        if self.build.queue_size == 0 {
+            errors.push("build queue size must be at least one".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Config(format!(
+                "Invalid configuration: {}",
+                errors.join("; ")
+            )))
+        }
+    }
+
+    pub fn log_level(&self) -> Level {
+        // validated in `Config::load`
+        Level::from_str(&self.log.level).unwrap_or(Level::INFO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_config(dir: &Path, content: &str) -> String {
+        let path = dir.join("config.toml");
+        fs::write(&path, content).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn flags_override_environment_over_file_over_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_config(
+            dir.path(),
+            r#"
+            data_dir = "/srv/release_workflows"
+            listen = "127.0.0.1:9000"
+
+            [build]
+            workers = 3
+            "#,
+        );
+
+        // the only test setting these, so tests running in parallel don't see them
+        std::env::set_var("RELEASE_WORKFLOWS_LISTEN", "127.0.0.1:9001");
+        std::env::set_var("RELEASE_WORKFLOWS_BUILD_WORKERS", "4");
+        let cli = Cli::try_parse_from([
+            "release_workflows",
+            "--config",
+            &path,
+            "--listen",
+            "127.0.0.1:9002",
+        ]);
+        std::env::remove_var("RELEASE_WORKFLOWS_LISTEN");
+        std::env::remove_var("RELEASE_WORKFLOWS_BUILD_WORKERS");
+
+        let config = Config::load(&cli.unwrap()).unwrap();
+        assert_eq!(config.listen, "127.0.0.1:9002");
+        assert_eq!(config.build.workers, 4);
+        assert_eq!(config.data_dir, "/srv/release_workflows");
+        assert_eq!(config.build.queue_size, BuildConfig::default().queue_size);
+        assert_eq!(config.docker.rust_image, "rust:latest");
+    }
+
+    #[test]
+    fn broken_config_files_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.toml");
+        let cli =
+            Cli::try_parse_from(["release_workflows", "--config", &missing.to_string_lossy()]);
+        assert!(matches!(Config::load(&cli.unwrap()), Err(Error::Config(_))));
+
+        let path = write_config(dir.path(), "[build]\nworker = 3\n");
+        let cli = Cli::try_parse_from(["release_workflows", "--config", &path]);
+        assert!(matches!(Config::load(&cli.unwrap()), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn validation_reports_every_invalid_setting() {
+        assert_eq!(Config::default().validate(), Ok(()));
+
+        let config = Config {
+            listen: "localhost".to_string(),
+            public_url: Some("builds.example.com".to_string()),
+            log: LogConfig {
+                level: "loud".to_string(),
+                format: LogFormat::Json,
+            },
+            sync: SyncConfig { interval_secs: 0 },
+            build: BuildConfig {
+                workers: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let message = match config.validate() {
+            Err(Error::Config(message)) => message,
+            other => panic!("expected a config error, got {:?}", other),
+        };
+        for setting in [
+            "listen",
+            "public_url",
+            "log level",
+            "sync interval",
+            "build worker",
+        ] {
+            assert!(
+                message.contains(setting),
+                "{} missing in {}",
+                setting,
+                message
+            );
+        }
+    }
+}
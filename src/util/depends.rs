@@ -1 +1,318 @@
+use std::{
+    process::Stdio,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use tokio::{process::Command, time};
+
+use crate::build::runner::BUILD_METHODS;
+use crate::util::config::Config;
+use crate::util::error::{Error, Result};
+
+/// Time a tool gets to report its version before it counts as unavailable.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Result of probing one host tool.
+#[derive(Debug, Object, Clone)]
+pub struct ToolStatus {
+    /// Binary that was probed, e.g. `git` or the configured container engine.
+    pub name: String,
+    pub available: bool,
+    /// Version the tool reported, e.g. `2.39.2`.
+    pub version: Option<String>,
+    /// Why the tool is unavailable.
+    pub error: Option<String>,
+}
+
+/// Whether a build method can run on this host.
+#[derive(Debug, Object, Clone)]
+pub struct MethodStatus {
+    pub method: String,
+    pub available: bool,
+    /// Tools the method needs that are not available.
+    pub missing: Vec<String>,
+}
+
+/// Tools found on the host and the build methods they allow.
+#[derive(Debug, Object, Clone)]
+pub struct ToolReport {
+    pub probed_at: DateTime<Utc>,
+    pub tools: Vec<ToolStatus>,
+    pub build_methods: Vec<MethodStatus>,
+}
+
+impl ToolReport {
+    fn tool(&self, name: &str) -> Option<&ToolStatus> {
+        self.tools.iter().find(|tool| tool.name == name)
+    }
+
+    fn method(&self, method: &str) -> Option<&MethodStatus> {
+        self.build_methods
+            .iter()
+            .find(|status| status.method == method)
+    }
+
+    /// Whether the service can work as intended: git is installed and every build method can run.
+    pub fn is_healthy(&self) -> bool {
+        self.tool("git").is_some_and(|git| git.available)
+            && self.build_methods.iter().all(|method| method.available)
+    }
+}
+
+/// Host tools the service and its build methods depend on, probed at startup.
+///
+/// The results are cached, builds are checked against them before they are queued.
+/// The handle is cheap to clone.
+#[derive(Clone)]
+pub struct Dependencies {
+    /// Binary of the container engine the docker and cargo methods use.
+    engine: String,
+    report: Arc<RwLock<ToolReport>>,
+}
+
+impl Dependencies {
+    /// Probes every tool and logs the ones that are missing.
+    pub async fn probe(config: &Config) -> Self {
+        let engine = config.docker.binary.clone();
+        let report = probe_tools(&engine).await;
+        log_missing(&report);
+
+        Dependencies {
+            engine,
+            report: Arc::new(RwLock::new(report)),
+        }
+    }
+
+    /// Dependencies that were never probed, every tool counts as available.
+    ///
+    /// For commands like `static-docs` that need the API but run no builds.
+    pub fn unprobed(config: &Config) -> Self {
+        Dependencies {
+            engine: config.docker.binary.clone(),
+            report: Arc::new(RwLock::new(ToolReport {
+                probed_at: Utc::now(),
+                tools: Vec::new(),
+                build_methods: Vec::new(),
+            })),
+        }
+    }
+
+    /// The cached results of the last probe.
+    pub fn report(&self) -> ToolReport {
+        self.report
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Probes the tools again, e.g. after one was installed, and updates the cache.
+    pub async fn refresh(&self) -> ToolReport {
+        let report = probe_tools(&self.engine).await;
+        log_missing(&report);
+
+        *self.report.write().unwrap_or_else(|e| e.into_inner()) = report.clone();
+        report
+    }
+
+    /// Fails with [`Error::ToolMissing`] if a tool `method` needs is not installed.
+    pub fn check_method(&self, method: &str) -> Result<()> {
+        let report = self.report();
+        match report.method(method) {
+            Some(status) if !status.available => Err(Error::ToolMissing(format!(
+                "Build method {} is unavailable, {} not installed",
+                method,
+                status.missing.join(", ")
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Tools needed to run a build `method`.
+fn required_tools<'a>(method: &str, engine: &'a str) -> Vec<&'a str> {
+    match method {
+        "make" => vec!["make"],
+        "script" => vec!["sh"],
+        // cargo builds run inside a container, cargo itself isn't needed on the host
+        "cargo" | "docker" => vec![engine],
+        _ => Vec::new(),
+    }
+}
+
+async fn probe_tools(engine: &str) -> ToolReport {
+    let mut names = vec!["git", "make", "sh", "docker", "podman", "cargo"];
+    if !names.contains(&engine) {
+        names.push(engine);
+    }
+
+    let probes = names.iter().map(|name| probe_tool(name));
+    let tools = futures_util::future::join_all(probes).await;
+
+    let build_methods = BUILD_METHODS
+        .iter()
+        .map(|method| {
+            let missing: Vec<String> = required_tools(method, engine)
+                .into_iter()
+                .filter(|name| {
+                    !tools
+                        .iter()
+                        .any(|tool| tool.name == *name && tool.available)
+                })
+                .map(str::to_string)
+                .collect();
+
+            MethodStatus {
+                method: method.to_string(),
+                available: missing.is_empty(),
+                missing,
+            }
+        })
+        .collect();
+
+    ToolReport {
+        probed_at: Utc::now(),
+        tools,
+        build_methods,
+    }
+}
+
+/// Runs `name --version`, or a no-op for `sh` which has no version flag everywhere.
+async fn probe_tool(name: &str) -> ToolStatus {
+    let mut cmd = Command::new(name);
+    match name {
+        "sh" => cmd.args(["-c", "exit 0"]),
+        _ => cmd.arg("--version"),
+    };
+    cmd.stdin(Stdio::null()).kill_on_drop(true);
+
+    let unavailable = |error: String| ToolStatus {
+        name: name.to_string(),
+        available: false,
+        version: None,
+        error: Some(error),
+    };
+
+    let output = match time::timeout(PROBE_TIMEOUT, cmd.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            return unavailable("not installed".to_string())
+        }
+        Ok(Err(e)) => return unavailable(format!("failed to execute: {}", e)),
+        Err(_) => return unavailable("timed out".to_string()),
+    };
+
+    if !output.status.success() {
+        return unavailable(format!("exited with {}", output.status));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    ToolStatus {
+        name: name.to_string(),
+        available: true,
+        version: parse_version(&stdout),
+        error: None,
+    }
+}
+
+/// Picks the version from the first line of `--version` output,
+/// e.g. `24.0.7` from `Docker version 24.0.7, build afdd53b`.
+fn parse_version(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find(|line| !line.trim().is_empty())?
+        .split_whitespace()
+        .find(|word| word.starts_with(|c: char| c.is_ascii_digit()))
+        .map(|word| word.trim_end_matches(',').to_string())
+}
+
+fn log_missing(report: &ToolReport) {
+    for tool in report.tools.iter().filter(|tool| !tool.available) {
+        tracing::debug!(
+            "{} is unavailable: {}",
+            tool.name,
+            tool.error.as_deref().unwrap_or_default()
+        );
+    }
+    for method in report
+        .build_methods
+        .iter()
+        .filter(|method| !method.available)
+    {
+        tracing::warn!(
+            "build method {} is unavailable, missing {}",
+            method.method,
+            method.missing.join(", ")
+        );
+    }
+    if !report.tool("git").is_some_and(|git| git.available) {
+        tracing::warn!("git is not installed, serving repositories over HTTP will fail");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MISSING_ENGINE: &str = "release-workflows-missing-engine";
+
+    fn probed(report: ToolReport) -> Dependencies {
+        Dependencies {
+            engine: MISSING_ENGINE.to_string(),
+            report: Arc::new(RwLock::new(report)),
+        }
+    }
+
+    #[test]
+    fn versions_are_parsed_from_the_first_line() {
+        assert_eq!(
+            parse_version("Docker version 24.0.7, build afdd53b"),
+            Some("24.0.7".to_string())
+        );
+        assert_eq!(
+            parse_version("\ngit version 2.39.2\n"),
+            Some("2.39.2".to_string())
+        );
+        assert_eq!(parse_version("GNU Make\n4.3"), None);
+    }
+
+    #[tokio::test]
+    async fn missing_tools_are_reported_as_not_installed() {
+        let status = probe_tool(MISSING_ENGINE).await;
+        assert!(!status.available);
+        assert_eq!(status.error.as_deref(), Some("not installed"));
+
+        let status = probe_tool("sh").await;
+        assert!(status.available);
+    }
+
+    #[tokio::test]
+    async fn methods_needing_a_missing_engine_are_unavailable() {
+        let report = probe_tools(MISSING_ENGINE).await;
+        assert!(!report.is_healthy());
+
+        for method in ["docker", "cargo"] {
+            let status = report.method(method).unwrap();
+            assert!(!status.available);
+            assert_eq!(status.missing, vec![MISSING_ENGINE.to_string()]);
+        }
+        assert!(report.method("script").unwrap().available);
+
+        let dependencies = probed(report);
+        assert!(matches!(
+            dependencies.check_method("docker"),
+            Err(Error::ToolMissing(_))
+        ));
+        assert!(dependencies.check_method("script").is_ok());
+    }
+
+    #[test]
+    fn unprobed_dependencies_allow_every_method() {
+        let dependencies = Dependencies::unprobed(&Config::default());
+        for method in BUILD_METHODS {
+            assert!(dependencies.check_method(method).is_ok());
+        }
+    }
+}
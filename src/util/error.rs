@@ -1 +1,295 @@
+use std::fmt;
 
+use poem::{http::StatusCode, Response};
+use poem_openapi::{payload::Json, ApiResponse, Object};
+
+/// Errors of the service, each kind with the HTTP status and code the API reports.
+///
+/// Every variant carries the message shown to the user.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Error {
+    /// No repository with the name is registered or on disk.
+    RepoNotFound(String),
+    /// Another resource, like a job, doesn't exist.
+    NotFound(String),
+    /// A repository with the name already exists.
+    AlreadyExists(String),
+    /// The resource is in a state that doesn't allow the operation, e.g. a finished job.
+    Conflict(String),
+    /// A tag, branch or commit could not be resolved.
+    InvalidRef(String),
+    /// A repository name that can't be used, e.g. `..` or one with whitespace.
+    InvalidName(String),
+    /// The request can't be processed, e.g. an unknown build method or a bad pattern.
+    Invalid(String),
+    /// A git operation failed.
+    Git(String),
+    /// A tool needed for the operation is not installed.
+    ToolMissing(String),
+    /// The build ran but did not succeed.
+    BuildFailed(String),
+    /// The container engine failed.
+    Docker(String),
+    /// Reading or writing files failed.
+    Io(String),
+    /// The configuration is invalid.
+    Config(String),
+    /// The service can't take the request right now, e.g. the build queue is full.
+    Unavailable(String),
+    /// No valid API token was presented.
+    Unauthorized(String),
+    /// The API token doesn't grant the operation.
+    Forbidden(String),
+    /// A notification target could not be reached or rejected a notification.
+    Notification(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn repo_not_found(name: &str) -> Self {
+        Error::RepoNotFound(format!("Repository not found ({})", name))
+    }
+
+    /// Machine readable code of the error kind, reported in API error bodies.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::RepoNotFound(_) => "repo_not_found",
+            Error::NotFound(_) => "not_found",
+            Error::AlreadyExists(_) => "already_exists",
+            Error::Conflict(_) => "conflict",
+            Error::InvalidRef(_) => "invalid_ref",
+            Error::InvalidName(_) => "invalid_name",
+            Error::Invalid(_) => "invalid_request",
+            Error::Git(_) => "git_failure",
+            Error::ToolMissing(_) => "tool_missing",
+            Error::BuildFailed(_) => "build_failed",
+            Error::Docker(_) => "docker_failure",
+            Error::Io(_) => "io_error",
+            Error::Config(_) => "config_error",
+            Error::Unavailable(_) => "unavailable",
+            Error::Unauthorized(_) => "unauthorized",
+            Error::Forbidden(_) => "forbidden",
+            Error::Notification(_) => "notification_failed",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::InvalidName(_) => StatusCode::BAD_REQUEST,
+            Error::RepoNotFound(_) | Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::AlreadyExists(_) | Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::InvalidRef(_) | Error::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::ToolMissing(_) | Error::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Git(_)
+            | Error::BuildFailed(_)
+            | Error::Docker(_)
+            | Error::Io(_)
+            | Error::Config(_)
+            | Error::Notification(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Error::RepoNotFound(message)
+            | Error::NotFound(message)
+            | Error::AlreadyExists(message)
+            | Error::Conflict(message)
+            | Error::InvalidRef(message)
+            | Error::InvalidName(message)
+            | Error::Invalid(message)
+            | Error::Git(message)
+            | Error::ToolMissing(message)
+            | Error::BuildFailed(message)
+            | Error::Docker(message)
+            | Error::Io(message)
+            | Error::Config(message)
+            | Error::Unavailable(message)
+            | Error::Unauthorized(message)
+            | Error::Forbidden(message)
+            | Error::Notification(message) => message,
+        }
+    }
+
+    /// Prefixes the message with what was being done, keeping the kind of the error.
+    pub fn context(self, context: &str) -> Self {
+        let prefix = |message: String| format!("{}: {}", context, message);
+        match self {
+            Error::RepoNotFound(message) => Error::RepoNotFound(prefix(message)),
+            Error::NotFound(message) => Error::NotFound(prefix(message)),
+            Error::AlreadyExists(message) => Error::AlreadyExists(prefix(message)),
+            Error::Conflict(message) => Error::Conflict(prefix(message)),
+            Error::InvalidRef(message) => Error::InvalidRef(prefix(message)),
+            Error::InvalidName(message) => Error::InvalidName(prefix(message)),
+            Error::Invalid(message) => Error::Invalid(prefix(message)),
+            Error::Git(message) => Error::Git(prefix(message)),
+            Error::ToolMissing(message) => Error::ToolMissing(prefix(message)),
+            Error::BuildFailed(message) => Error::BuildFailed(prefix(message)),
+            Error::Docker(message) => Error::Docker(prefix(message)),
+            Error::Io(message) => Error::Io(prefix(message)),
+            Error::Config(message) => Error::Config(prefix(message)),
+            Error::Unavailable(message) => Error::Unavailable(prefix(message)),
+            Error::Unauthorized(message) => Error::Unauthorized(prefix(message)),
+            Error::Forbidden(message) => Error::Forbidden(prefix(message)),
+            Error::Notification(message) => Error::Notification(prefix(message)),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Body of every API error response.
+#[derive(Debug, Object, Clone)]
+pub struct ErrorBody {
+    /// Machine readable kind of the error, e.g. `repo_not_found` or `tool_missing`.
+    pub code: String,
+    /// Human readable description.
+    pub message: String,
+}
+
+impl From<&Error> for ErrorBody {
+    fn from(err: &Error) -> Self {
+        ErrorBody {
+            code: err.code().to_string(),
+            message: err.message().to_string(),
+        }
+    }
+}
+
+/// Error responses of the API, the status follows from the kind of the error.
+#[derive(ApiResponse, Debug)]
+pub enum ErrorResponse {
+    /// Client Error -> Invalid Repository Name
+    #[oai(status = 400)]
+    BadRequest(Json<ErrorBody>),
+
+    /// Client Error -> Missing Or Invalid API Token
+    #[oai(status = 401)]
+    Unauthorized(Json<ErrorBody>),
+
+    /// Client Error -> API Token Lacks The Scope Or Repository
+    #[oai(status = 403)]
+    Forbidden(Json<ErrorBody>),
+
+    /// Client Error -> Not Found
+    #[oai(status = 404)]
+    NotFound(Json<ErrorBody>),
+
+    /// Client Error -> Conflict With The Current State
+    #[oai(status = 409)]
+    Conflict(Json<ErrorBody>),
+
+    /// Client Error -> Unprocessable Request
+    #[oai(status = 422)]
+    Unprocessable(Json<ErrorBody>),
+
+    /// Server Errors -> Internal Server Error
+    #[oai(status = 500)]
+    ServerError(Json<ErrorBody>),
+
+    /// Server Errors -> Service Unavailable
+    #[oai(status = 503)]
+    Unavailable(Json<ErrorBody>),
+}
+
+impl From<Error> for ErrorResponse {
+    fn from(err: Error) -> Self {
+        let status = err.status();
+        if status.is_server_error() {
+            tracing::error!("{}", err);
+        } else {
+            tracing::debug!("{}", err);
+        }
+
+        let body = Json(ErrorBody::from(&err));
+        match status {
+            StatusCode::BAD_REQUEST => ErrorResponse::BadRequest(body),
+            StatusCode::UNAUTHORIZED => ErrorResponse::Unauthorized(body),
+            StatusCode::FORBIDDEN => ErrorResponse::Forbidden(body),
+            StatusCode::NOT_FOUND => ErrorResponse::NotFound(body),
+            StatusCode::CONFLICT => ErrorResponse::Conflict(body),
+            StatusCode::UNPROCESSABLE_ENTITY => ErrorResponse::Unprocessable(body),
+            StatusCode::SERVICE_UNAVAILABLE => ErrorResponse::Unavailable(body),
+            _ => ErrorResponse::ServerError(body),
+        }
+    }
+}
+
+/// Lets plain Poem handlers, like the git and webhook routes, return the error directly.
+impl poem::error::ResponseError for Error {
+    fn status(&self) -> StatusCode {
+        Error::status(self)
+    }
+
+    fn as_response(&self) -> Response {
+        let body = serde_json::json!({ "code": self.code(), "message": self.message() });
+        Response::builder()
+            .status(self.status())
+            .content_type("application/json")
+            .body(body.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::error::ResponseError;
+
+    use super::*;
+
+    #[test]
+    fn errors_map_to_their_http_status_and_code() {
+        let cases = [
+            (Error::repo_not_found("tool"), 404, "repo_not_found"),
+            (Error::AlreadyExists(String::new()), 409, "already_exists"),
+            (Error::InvalidRef(String::new()), 422, "invalid_ref"),
+            (Error::InvalidName(String::new()), 400, "invalid_name"),
+            (Error::Unauthorized(String::new()), 401, "unauthorized"),
+            (Error::Forbidden(String::new()), 403, "forbidden"),
+            (Error::ToolMissing(String::new()), 503, "tool_missing"),
+            (Error::Git(String::new()), 500, "git_failure"),
+            (
+                Error::Notification(String::new()),
+                500,
+                "notification_failed",
+            ),
+        ];
+
+        for (err, status, code) in cases {
+            assert_eq!(err.status().as_u16(), status, "{:?}", err);
+            assert_eq!(err.code(), code);
+            assert_eq!(ResponseError::as_response(&err).status().as_u16(), status);
+        }
+    }
+
+    #[test]
+    fn api_responses_use_the_variant_of_the_status() {
+        let response = ErrorResponse::from(Error::Conflict("Job finished".to_string()));
+        assert!(matches!(
+            response,
+            ErrorResponse::Conflict(Json(ErrorBody { ref code, ref message }))
+                if code == "conflict" && message == "Job finished"
+        ));
+
+        let response = ErrorResponse::from(Error::Docker("engine gone".to_string()));
+        assert!(matches!(response, ErrorResponse::ServerError(_)));
+    }
+
+    #[test]
+    fn context_keeps_the_kind() {
+        let err = Error::InvalidRef("unknown tag v9".to_string()).context("Failed to build");
+        assert_eq!(
+            err,
+            Error::InvalidRef("Failed to build: unknown tag v9".to_string())
+        );
+        assert_eq!(err.to_string(), "Failed to build: unknown tag v9");
+    }
+}
@@ -1,5 +1,14 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
 use regex::Regex;
 
+use crate::util::error::{Error, Result};
+
+#[derive(Clone)]
 pub struct FileSystem {
     pub base_location: String,
 }
@@ -11,11 +20,167 @@ impl FileSystem {
         }
     }
 
-    pub fn git_path(&self, name: &str) -> String {
-        // replace whitespaces with hyphens and remove invalid characters
-        let re = Regex::new(r"[^A-Za-z0-9-_.]").unwrap();
-        let sanitized_name = re.replace_all(name, "-").into_owned();
+    /// Checkout of the repository `name`, fails for names [`check_name`] rejects.
+    pub fn git_path(&self, name: &str) -> Result<String> {
+        check_name(name)?;
+        Ok(format!("{}/{}", self.repos_path(), name))
+    }
+
+    /// Directory the checkouts of all repositories are kept in.
+    pub fn repos_path(&self) -> String {
+        format!("{}/repos", self.base_location)
+    }
+
+    /// Directory holding a directory per build job.
+    pub fn jobs_path(&self) -> String {
+        format!("{}/jobs", self.base_location)
+    }
+
+    /// Directory holding everything a build job leaves behind.
+    pub fn job_path(&self, id: &str) -> String {
+        format!("{}/{}", self.jobs_path(), id)
+    }
+
+    /// Directory the commit a job builds is exported to while it runs.
+    pub fn workspace_path(&self, id: &str) -> String {
+        format!("{}/workspaces/{}", self.base_location, id)
+    }
+
+    /// Directory build outputs of a job are collected in.
+    pub fn job_artifacts_path(&self, id: &str) -> String {
+        format!("{}/artifacts", self.job_path(id))
+    }
+
+    pub fn job_log_path(&self, id: &str) -> String {
+        format!("{}/build.log", self.job_path(id))
+    }
+
+    /// File the state of a job is recorded in, so it can be looked up after a restart.
+    pub fn job_metadata_path(&self, id: &str) -> String {
+        format!("{}/job.json", self.job_path(id))
+    }
+
+    /// File the releases of all repositories are recorded in.
+    pub fn releases_path(&self) -> String {
+        format!("{}/releases.json", self.base_location)
+    }
+
+    /// Directory holding the artifacts of all releases of a repository.
+    pub fn repository_releases_path(&self, repository: &str) -> Result<String> {
+        check_name(repository)?;
+        Ok(format!("{}/releases/{}", self.base_location, repository))
+    }
+
+    /// Directory the artifacts of a release are stored in.
+    pub fn release_path(&self, repository: &str, tag: &str) -> Result<String> {
+        Ok(format!(
+            "{}/{}",
+            self.repository_releases_path(repository)?,
+            sanitize(tag)
+        ))
+    }
+
+    pub fn registry_path(&self) -> String {
+        format!("{}/registry.json", self.base_location)
+    }
+
+    /// File the credentials of private repositories are stored in.
+    pub fn credentials_path(&self) -> String {
+        format!("{}/credentials.json", self.base_location)
+    }
+
+    /// File the keys build outputs are signed with are stored in.
+    pub fn signing_keys_path(&self) -> String {
+        format!("{}/signing_keys.json", self.base_location)
+    }
+
+    /// File the hashes of the API tokens are stored in.
+    pub fn tokens_path(&self) -> String {
+        format!("{}/tokens.json", self.base_location)
+    }
+}
+
+/// Fails with [`Error::InvalidName`] unless `name` can name a repository.
+///
+/// Names are used as directory names as they are, so only letters, digits, `-`, `_` and `.`
+/// are allowed and they must not start with `.` or `-`. A trailing `.git` is rejected as
+/// well, the git server strips it from the names it serves.
+pub fn check_name(name: &str) -> Result<()> {
+    let re = Regex::new(r"^[A-Za-z0-9_][A-Za-z0-9-_.]{0,99}$").unwrap();
+    if !re.is_match(name) {
+        return Err(Error::InvalidName(format!(
+            "Invalid repository name {:?}, use up to 100 letters, digits, '-', '_' and '.', \
+             not starting with '.' or '-'",
+            name
+        )));
+    }
+    if name.ends_with(".git") {
+        return Err(Error::InvalidName(format!(
+            "Invalid repository name {:?}, it must not end in .git",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Makes a tag usable as a single path component,
+/// whitespaces and other invalid characters are replaced with hyphens.
+fn sanitize(name: &str) -> String {
+    let re = Regex::new(r"[^A-Za-z0-9-_.]").unwrap();
+    re.replace_all(name, "-").into_owned()
+}
+
+/// Writes `content` to a new file at `path`, removing whatever was there before.
+///
+/// Unlike `fs::write` this never writes through a symlink, use it in directories builds control.
+pub fn replace_file(path: &Path, content: &[u8]) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+    }
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)?
+        .write_all(content)
+}
+
+/// Replaces the file at `path` with `content` atomically, creating its directory if needed.
+///
+/// The content goes to a temporary file next to it first, which is then renamed over `path`,
+/// so a crash never leaves a half written file behind. With `private` the file gets
+/// mode 0600 on unix, for files holding secrets.
+pub fn write_atomic(path: &str, content: &[u8], private: bool) -> io::Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let tmp_path = format!("{}.tmp", path);
+    if private {
+        write_private(&tmp_path, content)?;
+    } else {
+        fs::write(&tmp_path, content)?;
+    }
+    fs::rename(&tmp_path, path)
+}
+
+/// Writes `content` to a file with mode 0600 on unix.
+fn write_private(path: &str, content: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
 
-        format!("{}/{}", self.base_location, sanitized_name)
+    let mut file = options.open(path)?;
+    // the mode only applies to new files, a leftover file keeps its own
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
     }
+    file.write_all(content)
 }
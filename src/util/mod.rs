@@ -1,5 +1,6 @@
+pub mod config;
 pub mod depends;
-pub mod discord;
 pub mod error;
 pub mod file_system;
+pub mod signing;
 pub mod workflows;
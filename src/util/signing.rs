@@ -0,0 +1,321 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use blake2::{Blake2b512, Digest};
+use chrono::{DateTime, Utc};
+use ed25519_dalek::Signer;
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+
+use crate::util::error::{Error, Result};
+use crate::util::file_system::{replace_file, write_atomic, FileSystem};
+
+/// Algorithm id of minisign public keys.
+const KEY_ALGORITHM: &[u8] = b"Ed";
+/// Algorithm id of minisign signatures over the BLAKE2b-512 hash of a file.
+const SIGNATURE_ALGORITHM: &[u8] = b"ED";
+
+/// Extension of the signature written next to a signed file.
+pub const SIGNATURE_EXTENSION: &str = ".minisig";
+
+/// Whether `name` is the name of a signature file.
+pub fn is_signature_file(name: &str) -> bool {
+    name.ends_with(SIGNATURE_EXTENSION)
+}
+
+/// A public key build outputs are or were signed with.
+#[derive(Debug, Object, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct PublicKey {
+    /// Key id as shown by minisign, 16 hex digits.
+    pub key_id: String,
+    /// The key in minisign format, the second line of a `minisign.pub` file.
+    pub public_key: String,
+    pub created_at: DateTime<Utc>,
+    /// When the key was replaced by rotating, unset for the current key.
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+impl PublicKey {
+    /// Contents of a `minisign.pub` file, as read by `minisign -V -p`.
+    pub fn to_minisign(&self) -> String {
+        format!(
+            "untrusted comment: minisign public key {}\n{}\n",
+            self.key_id, self.public_key
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct StoredKey {
+    key_id: String,
+    /// The ed25519 seed, base64 encoded.
+    secret_key: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct KeyFile {
+    current: Option<StoredKey>,
+    /// Public parts of rotated keys, so older signatures stay verifiable.
+    #[serde(default)]
+    retired: Vec<PublicKey>,
+}
+
+/// An ed25519 key signing files in minisign format.
+pub struct SigningKey {
+    key_id: String,
+    /// The key id in the byte order minisign embeds it.
+    key_number: [u8; 8],
+    key: ed25519_dalek::SigningKey,
+    created_at: DateTime<Utc>,
+}
+
+impl SigningKey {
+    fn from_stored(stored: &StoredKey) -> Result<Self> {
+        let invalid = || Error::Io(format!("Invalid signing key {}", stored.key_id));
+
+        let key_number = u64::from_str_radix(&stored.key_id, 16)
+            .map_err(|_| invalid())?
+            .to_le_bytes();
+        let seed: [u8; 32] = STANDARD
+            .decode(&stored.secret_key)
+            .ok()
+            .and_then(|seed| seed.try_into().ok())
+            .ok_or_else(invalid)?;
+
+        Ok(SigningKey {
+            key_id: stored.key_id.clone(),
+            key_number,
+            key: ed25519_dalek::SigningKey::from_bytes(&seed),
+            created_at: stored.created_at,
+        })
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        let mut key = KEY_ALGORITHM.to_vec();
+        key.extend_from_slice(&self.key_number);
+        key.extend_from_slice(self.key.verifying_key().as_bytes());
+
+        PublicKey {
+            key_id: self.key_id.clone(),
+            public_key: STANDARD.encode(key),
+            created_at: self.created_at,
+            retired_at: None,
+        }
+    }
+
+    /// Signs the file at `path` and writes the signature next to it, as `minisign -S` does.
+    ///
+    /// Returns the file name of the signature.
+    pub fn write_signature(&self, path: &Path) -> io::Result<String> {
+        let mut hasher = Blake2b512::new();
+        let mut file = fs::File::open(path)?;
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        let signature = self.key.sign(&hasher.finalize()).to_bytes();
+
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let trusted_comment = format!(
+            "timestamp:{}\tfile:{}\thashed",
+            Utc::now().timestamp(),
+            file_name
+        );
+
+        // the global signature covers the trusted comment, so it can't be altered either
+        let mut signed_comment = signature.to_vec();
+        signed_comment.extend_from_slice(trusted_comment.as_bytes());
+        let global_signature = self.key.sign(&signed_comment).to_bytes();
+
+        let mut signature_line = SIGNATURE_ALGORITHM.to_vec();
+        signature_line.extend_from_slice(&self.key_number);
+        signature_line.extend_from_slice(&signature);
+
+        let content = format!(
+            "untrusted comment: signature from release_workflows key {}\n{}\ntrusted comment: {}\n{}\n",
+            self.key_id,
+            STANDARD.encode(signature_line),
+            trusted_comment,
+            STANDARD.encode(global_signature)
+        );
+
+        let signature_name = format!("{}{}", file_name, SIGNATURE_EXTENSION);
+        replace_file(&path.with_file_name(&signature_name), content.as_bytes())?;
+        Ok(signature_name)
+    }
+}
+
+/// The keys build outputs are signed with, stored in a file readable by the service user only.
+///
+/// Keys are created and rotated with the `signing-key` subcommands, the file is read again
+/// on every use, so a rotated key is picked up by the next build without a restart.
+#[derive(Clone)]
+pub struct SigningKeys {
+    path: String,
+}
+
+impl SigningKeys {
+    pub fn new(file_system: &FileSystem) -> Self {
+        SigningKeys {
+            path: file_system.signing_keys_path(),
+        }
+    }
+
+    /// The key to sign with, `None` if no key was generated.
+    pub fn current(&self) -> Result<Option<SigningKey>> {
+        self.read()?
+            .current
+            .as_ref()
+            .map(SigningKey::from_stored)
+            .transpose()
+    }
+
+    /// The current public key followed by the retired ones, the newest first.
+    pub fn public_keys(&self) -> Result<Vec<PublicKey>> {
+        let keys = self.read()?;
+        let mut public_keys = Vec::new();
+        if let Some(current) = &keys.current {
+            public_keys.push(SigningKey::from_stored(current)?.public_key());
+        }
+        public_keys.extend(keys.retired.into_iter().rev());
+        Ok(public_keys)
+    }
+
+    /// Generates the first signing key, fails if there is one already.
+    pub fn generate(&self) -> Result<PublicKey> {
+        let mut keys = self.read()?;
+        if let Some(current) = &keys.current {
+            return Err(Error::Conflict(format!(
+                "A signing key exists already ({}), use `signing-key rotate` to replace it",
+                current.key_id
+            )));
+        }
+
+        let key = new_key()?;
+        keys.current = Some(key.clone());
+        self.write(&keys)?;
+        Ok(SigningKey::from_stored(&key)?.public_key())
+    }
+
+    /// Replaces the current key with a new one, keeping the public part of the old one.
+    pub fn rotate(&self) -> Result<PublicKey> {
+        let mut keys = self.read()?;
+        let current = keys.current.take().ok_or_else(|| {
+            Error::NotFound(
+                "No signing key to rotate, create one with `signing-key generate`".to_string(),
+            )
+        })?;
+
+        let mut retired = SigningKey::from_stored(&current)?.public_key();
+        retired.retired_at = Some(Utc::now());
+        keys.retired.push(retired);
+
+        let key = new_key()?;
+        keys.current = Some(key.clone());
+        self.write(&keys)?;
+        Ok(SigningKey::from_stored(&key)?.public_key())
+    }
+
+    fn read(&self) -> Result<KeyFile> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => serde_json::from_str(&content).map_err(|e| {
+                Error::Io(format!("Failed to parse signing keys {}: {}", self.path, e))
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(KeyFile::default()),
+            Err(e) => Err(Error::Io(format!(
+                "Failed to read signing keys {}: {}",
+                self.path, e
+            ))),
+        }
+    }
+
+    /// Persists the keys, readable by the service user only.
+    fn write(&self, keys: &KeyFile) -> Result<()> {
+        let content = serde_json::to_string_pretty(keys)
+            .map_err(|e| Error::Io(format!("Failed to serialize signing keys: {}", e)))?;
+
+        write_atomic(&self.path, content.as_bytes(), true)
+            .map_err(|e| Error::Io(format!("Failed to write signing keys {}: {}", self.path, e)))
+    }
+}
+
+/// Creates a key from the OS random generator.
+fn new_key() -> Result<StoredKey> {
+    let mut seed = [0u8; 32];
+    let mut key_number = [0u8; 8];
+    getrandom::getrandom(&mut seed)
+        .and_then(|_| getrandom::getrandom(&mut key_number))
+        .map_err(|e| Error::Io(format!("Failed to generate signing key: {}", e)))?;
+
+    Ok(StoredKey {
+        key_id: format!("{:016X}", u64::from_le_bytes(key_number)),
+        secret_key: STANDARD.encode(seed),
+        created_at: Utc::now(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    use super::*;
+
+    #[test]
+    fn signatures_verify_in_minisign_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = SigningKeys::new(&FileSystem::new(&dir.path().to_string_lossy()));
+        let public_key = keys.generate().unwrap();
+        let key = keys.current().unwrap().unwrap();
+
+        let path = dir.path().join("tool");
+        fs::write(&path, "release contents").unwrap();
+        assert_eq!(key.write_signature(&path).unwrap(), "tool.minisig");
+
+        let public_key = STANDARD.decode(&public_key.public_key).unwrap();
+        assert_eq!(&public_key[..2], KEY_ALGORITHM);
+        let key_number = &public_key[2..10];
+        let verifying_key = VerifyingKey::from_bytes(public_key[10..].try_into().unwrap()).unwrap();
+
+        let content = fs::read_to_string(dir.path().join("tool.minisig")).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("untrusted comment: "));
+
+        let signature_line = STANDARD.decode(lines[1]).unwrap();
+        assert_eq!(&signature_line[..2], SIGNATURE_ALGORITHM);
+        assert_eq!(&signature_line[2..10], key_number);
+        let signature = Signature::from_slice(&signature_line[10..]).unwrap();
+        let prehash = Blake2b512::digest(b"release contents");
+        verifying_key.verify(&prehash, &signature).unwrap();
+
+        let trusted_comment = lines[2].strip_prefix("trusted comment: ").unwrap();
+        assert!(trusted_comment.contains("file:tool"));
+        let mut signed_comment = signature_line[10..].to_vec();
+        signed_comment.extend_from_slice(trusted_comment.as_bytes());
+        let global_signature = Signature::from_slice(&STANDARD.decode(lines[3]).unwrap()).unwrap();
+        verifying_key
+            .verify(&signed_comment, &global_signature)
+            .unwrap();
+
+        // a signature of other contents doesn't verify
+        assert!(verifying_key
+            .verify(&Blake2b512::digest(b"tampered"), &signature)
+            .is_err());
+    }
+}
@@ -1,12 +1,17 @@
-use std::{fs, io, path::Path};
+use std::{fs, path::Path};
 
 use poem_openapi::Object;
 
+use crate::util::error::{Error, Result};
+
 #[derive(Debug, Object, Clone, Eq, PartialEq)]
 pub struct WorkflowScripts {
     makefile: bool,
     script: bool,
     cargo_toml: bool,
+    dockerfile: bool,
+    /// Whether `workflows/artifacts.toml` declares the artifacts to collect.
+    artifacts_manifest: bool,
 }
 
 impl WorkflowScripts {
@@ -15,6 +20,8 @@ impl WorkflowScripts {
             makefile: false,
             script: false,
             cargo_toml: false,
+            dockerfile: false,
+            artifacts_manifest: false,
         }
     }
 
@@ -30,6 +37,14 @@ impl WorkflowScripts {
         self.cargo_toml = exists;
     }
 
+    fn set_dockerfile(&mut self, exists: bool) {
+        self.dockerfile = exists;
+    }
+
+    fn set_artifacts_manifest(&mut self, exists: bool) {
+        self.artifacts_manifest = exists;
+    }
+
     pub fn has_makefile(&self) -> bool {
         self.makefile
     }
@@ -42,6 +57,14 @@ impl WorkflowScripts {
         self.cargo_toml
     }
 
+    pub fn has_dockerfile(&self) -> bool {
+        self.dockerfile
+    }
+
+    pub fn has_artifacts_manifest(&self) -> bool {
+        self.artifacts_manifest
+    }
+
     pub fn get_makefile_path(path: &str) -> String {
         format!("{}/workflows/make/Makefile", path)
     }
@@ -53,20 +76,34 @@ impl WorkflowScripts {
     pub fn get_cargo_toml_path(path: &str) -> String {
         format!("{}/Cargo.toml", path)
     }
+
+    pub fn get_artifacts_manifest_path(path: &str) -> String {
+        format!("{}/workflows/artifacts.toml", path)
+    }
+
+    /// Returns the Dockerfile of the repository at `path`, relative to `path`.
+    ///
+    /// `workflows/docker/Dockerfile` takes precedence over a `Dockerfile` in the
+    /// repository root, so a repository can keep a separate release image.
+    pub fn find_dockerfile(path: &str) -> Option<&'static str> {
+        ["workflows/docker/Dockerfile", "Dockerfile"]
+            .into_iter()
+            .find(|candidate| Path::new(&format!("{}/{}", path, candidate)).is_file())
+    }
 }
 
-pub fn workflows_exist(path: &str) -> Result<WorkflowScripts, io::Error> {
+pub fn workflows_exist(path: &str) -> Result<WorkflowScripts> {
     let mut scripts = WorkflowScripts::new();
 
     // Check if Makefile exists
-    if let Ok(metadata) = fs::metadata(&WorkflowScripts::get_makefile_path(path)) {
+    if let Ok(metadata) = fs::metadata(WorkflowScripts::get_makefile_path(path)) {
         if metadata.is_file() {
             scripts.set_makefile(true);
         }
     }
 
     // Check if build_script.sh exists
-    if let Ok(metadata) = fs::metadata(&WorkflowScripts::get_script_path(path)) {
+    if let Ok(metadata) = fs::metadata(WorkflowScripts::get_script_path(path)) {
         if metadata.is_file() {
             scripts.set_script(true);
         }
@@ -77,12 +114,23 @@ pub fn workflows_exist(path: &str) -> Result<WorkflowScripts, io::Error> {
         scripts.set_cargo_toml(true);
     }
 
-    if scripts.has_makefile() || scripts.has_script() || scripts.has_cargo_toml() {
+    // Check if a Dockerfile exists
+    scripts.set_dockerfile(WorkflowScripts::find_dockerfile(path).is_some());
+
+    // Check if an artifact manifest exists
+    scripts.set_artifacts_manifest(
+        Path::new(&WorkflowScripts::get_artifacts_manifest_path(path)).is_file(),
+    );
+
+    if scripts.has_makefile()
+        || scripts.has_script()
+        || scripts.has_cargo_toml()
+        || scripts.has_dockerfile()
+    {
         Ok(scripts)
     } else {
-        Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "Required files not found in release workflow directory",
+        Err(Error::Invalid(
+            "Required files not found in release workflow directory".to_string(),
         ))
     }
 }